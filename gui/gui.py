@@ -55,25 +55,19 @@ def select_dir_or_file(dir: bool): # If dir, we know it is the target path. Othe
 
             if not os.path.isdir(folder_selected+"/.time"):
                 if messagebox.askquestion('Config file','This folder is not currently being tracked, do you want to begin tracking it? A config folder will be created for you, and default settings will be applied. (No hashing, compression level 5, multithreading enabled)'):
-                    os.mkdir(folder_selected+"/.time")
                     print("Starting to track "+folder_selected)
                     if platform == "posix":
                         config_path = folder_selected+"/.time/gui-config.json"
+                        binary = 'ftm'
                     else:
                         config_path = folder_selected+"\\.time\\gui-config.json"
-                    config_file = open(config_path, 'w')
-                    print("Writing config to "+str(config_file))
-                    config_file.write('''[
-{
-    "folder_path": "'''+folder_selected+'''",
-    "get_hashes": false,
-    "thread_count": 0,
-    "brotli_compression_level": 5,
-    "snapshot_mode": "fastest",
-    "its_my_fault_if_i_lose_data": true
-}
-]''')
-                    config_file.close()
+                        binary = path_windows
+                    print("Writing config to "+config_path)
+                    p1 = subprocess.Popen([binary, '-c', config_path, 'init', '--folder', folder_selected, '--its-my-fault-if-i-lose-data'], stdout=subprocess.PIPE)
+                    output = p1.communicate()[0]
+                    print(output)
+                    if p1.returncode != 0:
+                        messagebox.showerror("Error", "There was an issue creating a config! Error: "+str(output))
             else:
                 if not os.path.exists(folder_selected+"/.time/gui-config.json"):
                     print(folder_selected+"/.time/gui-config.json")
@@ -86,17 +80,22 @@ def select_dir_or_file(dir: bool): # If dir, we know it is the target path. Othe
         print(config_path)
 
 def get_snap_list():
-    global listbox, target_path
+    global listbox, config_path
     listbox.delete(0, END)
-    print(target_path+'/.time/snapshots.json')
-    if os.path.exists(target_path+'/.time/snapshots.json'):
-        with open(target_path+'/.time/snapshots.json') as f:
-            d = json.load(f)
-            for i in range(len(d)):
-                print(d[i]["date_created"])
-                listbox.insert(END, d[i]["date_created"])
+    if platform == "posix":
+        p1 = subprocess.Popen(['ftm', '-c', config_path, 'list', '--json'], stdout=subprocess.PIPE)
     else:
+        p1 = subprocess.Popen([path_windows, '-c', config_path, 'list', '--json'], stdout=subprocess.PIPE)
+    output = p1.communicate()[0]
+    d = json.loads(output) if p1.returncode == 0 else []
+    if len(d) == 0:
         messagebox.showinfo("No Snapshots", "Did not find any snapshots to list.")
+    for snapshot in d:
+        label = snapshot["date_created"]
+        if snapshot["message"] != "":
+            label += " - " + snapshot["message"]
+        print(label)
+        listbox.insert(END, label)
 
 def create_snapshot():
     global config_path
@@ -0,0 +1,125 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::diffs;
+use crate::error::{FtmError, IoContext};
+use crate::metadata_manager;
+use crate::restore::{self, PathVersion};
+use crate::snapshot_manager::ChangeKind;
+use crate::storage::StorageBackend;
+use crate::DiffEntry;
+use crate::MetaFile;
+use crate::SnapshotEntries;
+
+pub fn snapshot_paths(entry: &SnapshotEntries) -> HashSet<String> {
+    // Every path that exists in the snapshot, skipping the ones that were recorded as removed
+    let mut paths = HashSet::new();
+    for (index, path) in entry.target_path.iter().enumerate() {
+        if entry.patch_ids[index] != "REMOVED" {
+            paths.insert(path.clone());
+        }
+    }
+    paths
+}
+
+pub fn compare_snapshots(
+    snapshot_store: &[SnapshotEntries],
+    from_index: usize,
+    to_index: usize,
+) -> BTreeMap<String, ChangeKind> {
+    /* Two versions of a path are the same if they resolve to the same patch. We never need to touch the patch
+    files for this, since an unmodified file always points back to the patch it was last written with.
+    */
+    let from_paths = snapshot_paths(&snapshot_store[from_index]);
+    let to_paths = snapshot_paths(&snapshot_store[to_index]);
+    let mut changes = BTreeMap::new();
+
+    for path in from_paths.union(&to_paths) {
+        let kind = if !from_paths.contains(path) {
+            ChangeKind::Added
+        } else if !to_paths.contains(path) {
+            ChangeKind::Removed
+        } else {
+            match (
+                restore::resolve_version(snapshot_store, from_index, path),
+                restore::resolve_version(snapshot_store, to_index, path),
+            ) {
+                (PathVersion::Directory, PathVersion::Directory) => ChangeKind::Unchanged,
+                (PathVersion::Patch(from_id), PathVersion::Patch(to_id)) if from_id == to_id => {
+                    ChangeKind::Unchanged
+                }
+                _ => ChangeKind::Modified,
+            }
+        };
+        changes.insert(path.clone(), kind);
+    }
+    changes
+}
+
+pub fn compare_with_folder(
+    snapshot_store: &[SnapshotEntries],
+    patch_index: &HashMap<String, DiffEntry>,
+    from_index: usize,
+    folder_path: &str,
+    time_dir: &str,
+    storage: &dyn StorageBackend,
+) -> Result<BTreeMap<String, ChangeKind>, FtmError> {
+    /* Anything get_diffs reports as unmodified still matches the most recent snapshot, since that is when
+    metadata.json was last written. If the snapshot we compare against has the same version as the most recent
+    one, we can skip rebuilding it. Everything else gets rebuilt in memory and compared byte for byte.
+    */
+    let latest_index = snapshot_store.len() - 1;
+    let metadata_holder: HashSet<MetaFile> =
+        metadata_manager::read_metadata_from_file(&(time_dir.to_string() + "/metadata.json"))
+            .unwrap_or_default();
+    let mut clean_paths = HashSet::new();
+    for path in diffs::get_diffs(false, &metadata_holder, folder_path, time_dir)? {
+        if !path.modified {
+            clean_paths.insert(path.path);
+        }
+    }
+
+    let mut current_paths = HashSet::new();
+    for entry in WalkDir::new(folder_path) {
+        let entry = entry?;
+        if let Some(path_str) = entry.path().to_str() {
+            if diffs::is_tracked(path_str, folder_path, time_dir) {
+                current_paths.insert(path_str.to_string());
+            }
+        }
+    }
+
+    let from_paths = snapshot_paths(&snapshot_store[from_index]);
+    let mut changes = BTreeMap::new();
+
+    for path in from_paths.union(&current_paths) {
+        let kind = if !from_paths.contains(path) {
+            ChangeKind::Added
+        } else if !current_paths.contains(path) {
+            ChangeKind::Removed
+        } else {
+            match restore::resolve_version(snapshot_store, from_index, path) {
+                PathVersion::Directory if Path::new(path).is_dir() => ChangeKind::Unchanged,
+                PathVersion::Patch(id) if Path::new(path).is_file() => {
+                    let same_as_latest =
+                        match restore::resolve_version(snapshot_store, latest_index, path) {
+                            PathVersion::Patch(latest_id) => latest_id == id,
+                            _ => false,
+                        };
+                    if (clean_paths.contains(path) && same_as_latest)
+                        || restore::rebuild_patch(storage, patch_index, &id)?
+                            == std::fs::read(path).at(path)?
+                    {
+                        ChangeKind::Unchanged
+                    } else {
+                        ChangeKind::Modified
+                    }
+                }
+                _ => ChangeKind::Modified,
+            }
+        };
+        changes.insert(path.clone(), kind);
+    }
+    Ok(changes)
+}
@@ -0,0 +1,326 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, TryLockError};
+use std::thread;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::error::{FtmError, IoContext};
+use crate::progress::{Progress, ProgressSink};
+use crate::serve;
+use crate::snapshot_manager;
+use crate::Repository;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SnapshotRequest {
+    message: String,
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct RestoreRequest {
+    index: usize, // Same numbering as restore --restore-index
+    tag: String,  // Used instead of index if set
+    paths: Vec<String>,
+    to: String,
+}
+
+#[derive(Serialize)]
+struct PendingChange {
+    path: String,
+    change: String,
+}
+
+struct Daemon {
+    repository: Repository,
+    busy: Mutex<()>, // Held while a snapshot or restore is running, they would trip over each other
+    token: String,   // Every request has to send it as a bearer token
+    port: Option<u16>, // None on a unix socket
+}
+
+fn json_response<T: Serialize>(status: u16, value: &T) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string(serde_json::to_string(value).expect("Unable to serialize response!"))
+        .with_status_code(status)
+        .with_header(
+            Header::from_bytes("Content-Type", "application/json").expect("Invalid header"),
+        )
+}
+
+fn error_response(status: u16, message: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    json_response(status, &serde_json::json!({ "error": message }))
+}
+
+fn same_token(given: &str, token: &str) -> bool {
+    // Compare every byte, so the time it takes does not tell how much of the token was right
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+impl Daemon {
+    fn check(&self, request: &Request) -> Result<(), (u16, &'static str)> {
+        serve::check_origin(request, self.port)?;
+        let authorized = serve::header_value(request, "Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|given| same_token(given.trim(), &self.token));
+        if !authorized {
+            return Err((401, "Missing or wrong bearer token"));
+        }
+        if *request.method() == Method::Post {
+            let is_json =
+                serve::header_value(request, "Content-Type").is_some_and(|content_type| {
+                    content_type
+                        .split(';')
+                        .next()
+                        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+                });
+            if !is_json {
+                return Err((415, "Content-Type has to be application/json"));
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&self, mut request: Request) -> Result<(), FtmError> {
+        let url = request.url().split('?').next().unwrap_or("").to_string();
+        debug!("{} {}", request.method(), url);
+        let folder_path = self.repository.folder_path();
+        if let Err((status, message)) = self.check(&request) {
+            debug!("Refusing {} {}: {}", request.method(), url, message);
+            request.respond(error_response(status, message)).at(&url)?;
+            return Ok(());
+        }
+
+        let response = match (request.method(), url.trim_end_matches('/')) {
+            (Method::Get, "/snapshots") => json_response(200, &self.repository.list()?),
+            (Method::Get, "/status") => {
+                let changes: Vec<PendingChange> = self
+                    .repository
+                    .status()?
+                    .into_iter()
+                    .map(|(path, change)| PendingChange {
+                        path: snapshot_manager::relative_path(folder_path, &path).to_string(),
+                        change: change.to_string(),
+                    })
+                    .collect();
+                json_response(200, &serde_json::json!({ "changes": changes }))
+            }
+            (Method::Post, "/snapshot") | (Method::Post, "/restore") => {
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).at(&url)?;
+                if body.trim().is_empty() {
+                    body = "{}".to_string();
+                }
+                let _guard = match self.busy.try_lock() {
+                    Ok(guard) => guard,
+                    Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(), // A previous run panicked, nothing to protect anymore
+                    Err(TryLockError::WouldBlock) => {
+                        request
+                            .respond(error_response(
+                                409,
+                                "Another snapshot or restore is already running",
+                            ))
+                            .at(&url)?;
+                        return Ok(());
+                    }
+                };
+                if url.trim_end_matches('/') == "/snapshot" {
+                    let options: SnapshotRequest = match serde_json::from_str(&body) {
+                        Ok(options) => options,
+                        Err(e) => {
+                            request
+                                .respond(error_response(400, &e.to_string()))
+                                .at(&url)?;
+                            return Ok(());
+                        }
+                    };
+                    self.stream(request, |on_progress| self.snapshot(&options, on_progress))
+                        .at(&url)?;
+                } else {
+                    let options: RestoreRequest = match serde_json::from_str(&body) {
+                        Ok(options) => options,
+                        Err(e) => {
+                            request
+                                .respond(error_response(400, &e.to_string()))
+                                .at(&url)?;
+                            return Ok(());
+                        }
+                    };
+                    self.stream(request, |on_progress| self.restore(&options, on_progress))
+                        .at(&url)?;
+                }
+                return Ok(());
+            }
+            (_, "/snapshots") | (_, "/status") | (_, "/snapshot") | (_, "/restore") => {
+                error_response(405, "Method not allowed")
+            }
+            _ => error_response(404, "Not found"),
+        };
+        request.respond(response).at(&url)?;
+        Ok(())
+    }
+
+    fn stream<F>(&self, request: Request, operation: F) -> io::Result<()>
+    where
+        F: FnOnce(&dyn ProgressSink),
+    {
+        /* Progress is sent as one JSON object per line while the operation runs. tiny_http would hold chunks back
+        until they fill up, so the chunked body is written straight to the connection instead.
+        */
+        let writer = RefCell::new(request.into_writer());
+        writer.borrow_mut().write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nTransfer-Encoding: chunked\r\n\r\n",
+        )?;
+        let on_progress = |event: Progress| {
+            let mut writer = writer.borrow_mut();
+            let line = serde_json::to_string(&event).expect("Unable to serialize event!") + "\n";
+            let chunk = format!("{:X}\r\n{}\r\n", line.len(), line);
+            // The client going away should not stop a snapshot halfway through
+            if writer
+                .write_all(chunk.as_bytes())
+                .and_then(|_| writer.flush())
+                .is_err()
+            {
+                debug!("Client stopped listening for progress");
+            }
+        };
+        operation(&on_progress);
+        let mut writer = writer.into_inner();
+        writer.write_all(b"0\r\n\r\n")?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn snapshot(&self, options: &SnapshotRequest, on_progress: &dyn ProgressSink) {
+        // A failed snapshot has already been sent to the client as a failed event
+        let _ = self
+            .repository
+            .snapshot(&options.message, &options.tags, on_progress);
+    }
+
+    fn restore(&self, options: &RestoreRequest, on_progress: &dyn ProgressSink) {
+        let selected_index = if !options.tag.is_empty() {
+            self.repository.tagged_snapshot(&options.tag)
+        } else if options.index > 0 {
+            self.repository.resolve_snapshot(Some(options.index - 1))
+        } else {
+            Err(FtmError::Config(
+                "No snapshot matches the given index or tag".to_string(),
+            ))
+        };
+        let selected_index = match selected_index {
+            Ok(selected_index) => selected_index,
+            Err(e) => {
+                on_progress.event(Progress::Failed {
+                    message: e.to_string(),
+                });
+                return;
+            }
+        };
+        let restore_to = if options.to.is_empty() {
+            None
+        } else if Path::new(&options.to).is_absolute() {
+            Some(options.to.as_str()) // The repository only restores into a folder that is empty
+        } else {
+            on_progress.event(Progress::Failed {
+                message: format!("{} is not an absolute path", options.to),
+            });
+            return;
+        };
+        // Restoring, Finished and Failed events come from the repository
+        let _ = self
+            .repository
+            .restore(selected_index, &options.paths, restore_to, on_progress);
+    }
+}
+
+fn bind(listen: &str) -> Result<Server, FtmError> {
+    // Anyone who can reach the daemon can restore over the folder, so TCP is only allowed on loopback addresses
+    if let Some(socket_path) = listen.strip_prefix("unix:") {
+        #[cfg(unix)]
+        {
+            if Path::new(socket_path).exists() {
+                fs::remove_file(socket_path).at(socket_path)?; // Left behind by a daemon that did not shut down cleanly
+            }
+            return Server::http_unix(Path::new(socket_path)).map_err(|e| FtmError::Listen {
+                address: listen.to_string(),
+                reason: e.to_string(),
+            });
+        }
+        #[cfg(not(unix))]
+        {
+            return Err(FtmError::Listen {
+                address: listen.to_string(),
+                reason: format!("unix sockets are not supported here, use tcp:127.0.0.1:PORT instead of {socket_path}"),
+            });
+        }
+    }
+    let address = listen.strip_prefix("tcp:").unwrap_or(listen);
+    serve::require_loopback(address)?;
+    Server::http(address).map_err(|e| FtmError::Listen {
+        address: address.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+fn write_token(path: &str) -> Result<String, FtmError> {
+    // A new token every start, only readable by the user running the daemon
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| io::Error::other(e.to_string()))
+        .at(path)?;
+    let token = hex::encode(bytes);
+    if Path::new(path).exists() {
+        fs::remove_file(path).at(path)?; // So the permissions below apply to a fresh file
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(token.as_bytes()))
+        .at(path)?;
+    Ok(token)
+}
+
+pub fn run(listen: &str, repository: Repository) -> Result<(), FtmError> {
+    let server = bind(listen)?;
+    let time_dir = repository.time_dir();
+    fs::create_dir_all(&time_dir).at(&time_dir)?;
+    let token_path = time_dir + "/daemon_token";
+    let token = write_token(&token_path)?;
+    println!(
+        "Listening on {listen} for {}. Endpoints: GET /snapshots, GET /status, POST /snapshot, POST /restore",
+        repository.folder_path()
+    );
+    println!("Send the token in {token_path} as Authorization: Bearer TOKEN with every request.");
+    let daemon = Arc::new(Daemon {
+        repository,
+        busy: Mutex::new(()),
+        token,
+        port: server.server_addr().to_ip().map(|address| address.port()),
+    });
+
+    for request in server.incoming_requests() {
+        // Every request gets a thread, so listing snapshots still works while a snapshot is being taken
+        let daemon = Arc::clone(&daemon);
+        thread::spawn(move || {
+            if let Err(e) = daemon.handle(request) {
+                eprintln!("Could not answer a request: {e}");
+            }
+        });
+    }
+    Ok(())
+}
@@ -1,96 +1,84 @@
-use std::error::Error;
 use std::collections::HashSet;
-use std::sync::{Arc, Mutex};
+use std::sync::{mpsc, Arc, Mutex};
 use sha2::{Digest, Sha256};
 use std::path::Path;
 use std::fs::File;
 use bsdiff::diff;
 use walkdir::WalkDir;
-use std::fs::metadata;
+use std::fs::{metadata, Metadata};
 use std::{io, time::UNIX_EPOCH};
 use std::io::ErrorKind;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle, ProgressState};
 use std::thread;
 use chrono::DateTime;
-use std::io::Write;
 use std::io::Read;
-use std::process;
 use log::debug;
 use xxhash_rust::xxh3::xxh3_64;
 
 use crate::compression;
+use crate::error::{corrupt_patch, corrupt_store, FtmError, IoContext};
+use crate::progress::{Progress, ProgressSink};
 use crate::restore;
+use crate::storage::{StorageBackend, PATCH_STORE};
 use crate::DiffEntry;
 use crate::MetaFile;
 use crate::ModifiedList;
 
+#[allow(clippy::too_many_arguments)]
 pub fn create_diff( // Never call this on a directory. Do checks outside of the function
-    mut old_file: String,
+    old_file: String,
     new_file: String,
     target_path: String,
-    time_dir: String,
+    storage: &dyn StorageBackend,
     ref_patch: String,
     old_raw: Vec<u8>,
     compression_level: u32,
     patch_store: &Arc<Mutex<Vec<DiffEntry>>>,
     create_reverse: bool,
-) -> Result<String, Box<dyn Error>> {
-    /* This handles everything related to creating a diff, including storing its metadata/location.
-    If old_raw is set, then we will use it as the target file. Will create a forward diff and backward diff.
-    Backward diff will be {diff_id}-reverse. Every diff is compressed with brotli before being written.
+) -> Result<(String, u64), FtmError> {
+    /* This handles everything related to creating a diff, including adding it to patch_store. The caller writes
+    patch_store once every patch of the snapshot exists. If old_raw is set, then we will use it as the target file.
+    Will create a forward diff and backward diff. Backward diff will be {diff_id}-reverse. Every diff is compressed
+    with brotli before being stored. Returns the ID and the compressed size of the forward diff.
     */
     // println!("create_diff called");
     // println!("New: {new_file}");
     // println!("Old: {old_file}");
     let mut sha256 = Sha256::new();
-    let old: Vec<u8>;
     let current_time: String = chrono::offset::Local::now().to_string();
 
-    if !Path::new(&old_file).exists() || !Path::new(&old_file).is_file() {
-        // In this case, we assume there is a new file, so old_file is directed to an empty file
-        old_file = time_dir.clone() + "/tmp_empty";
-    }
-
-    if !old_raw.is_empty() {
+    let old: Vec<u8> = if !old_raw.is_empty() {
         // Handle case where old is stored in memory
         debug!("create_diff: Old stored in memory!");
-        old = old_raw;
+        old_raw
+    } else if Path::new(&old_file).is_file() {
+        std::fs::read(&old_file).at(&old_file)?
     } else {
-        old = std::fs::read(old_file.clone()).unwrap_or_else(|_| panic!("Could not open {old_file}!"));
-    }
+        // In this case, we assume there is a new file, so we diff against nothing
+        Vec::new()
+    };
     // println!("Old file is {}", old_file);
-    let new = std::fs::read(new_file.clone()).unwrap_or_else(|_| panic!("Could not open {new_file}!"));
+    let new = std::fs::read(&new_file).at(&new_file)?;
 
     sha256.update(current_time.clone() + &target_path); // Generate an ID to identify the patch. This can be derived from the data stored in DiffEntry, which can then be used to identify where the patch file is.
     let patch_id: String = format!("{:X}", sha256.finalize());
+    let reverse_id = patch_id.clone() + "-reverse";
 
-    let mut patch_target = File::create(Path::new(&(time_dir.clone() + "/" + &patch_id))).unwrap_or_else(|_| panic!("Could not create patch_target at {}",
-        time_dir.clone() + "/" + &patch_id));
     if create_reverse {
         debug!("Creating reverse!");
-        let mut patch_target_reverse =
-            File::create(Path::new(&(time_dir.clone() + "/" + &patch_id + "-reverse"))).unwrap_or_else(|_| panic!("Could not create patch_target at {}",
-                time_dir.clone() + "/" + &patch_id));
-
         let mut patch_reverse = Vec::new();
         // println!("{:?}", new);
         // println!("{:?}", old);
-        diff(&new, &old, &mut patch_reverse)?;
+        diff(&new, &old, &mut patch_reverse).map_err(|e| corrupt_patch(&patch_id, e))?;
         // println!("Compressing reverse...");
         
-        let temp_compressed = compression::compress_data(patch_reverse, compression_level)?;
+        let temp_compressed = compression::compress_data(patch_reverse, compression_level).at(&storage.location(&reverse_id))?;
         // let elapsed = now.elapsed();
         // println!("Compressing reverse: {:.2?}", elapsed);
 
-        patch_target_reverse
-            .write_all(&temp_compressed)
-            .expect("Unable to write to patch file!");
+        storage.put(&reverse_id, &temp_compressed)?;
     } else {
         debug!("Creating false reverse!");
-        let mut patch_target_reverse =
-            File::create(Path::new(&(time_dir.clone() + "/" + &patch_id + "-reverse"))).unwrap_or_else(|_| panic!("Could not create patch_target at {}",
-                time_dir.clone() + "/" + &patch_id));
-        write!(patch_target_reverse, ":3").unwrap_or_else(|_| panic!("There was an issue writing to {}!", time_dir.clone() + "/" + &patch_id + "-reverse"));
+        storage.put(&reverse_id, b":3")?;
     }
 
     let mut patch = Vec::new();
@@ -98,25 +86,21 @@ pub fn create_diff( // Never call this on a directory. Do checks outside of the
     
 
     // let now = Instant::now();
-    diff(&old, &new, &mut patch)?;
+    diff(&old, &new, &mut patch).map_err(|e| corrupt_patch(&patch_id, e))?;
     // let elapsed = now.elapsed();
     // println!("Diff calc: {:.2?}", elapsed);
 
     // let now = Instant::now();
     // println!("Compressing patch...");
-    let temp_compressed = compression::compress_data(patch, compression_level)?;
+    let temp_compressed = compression::compress_data(patch, compression_level).at(&storage.location(&patch_id))?;
     // let elapsed = now.elapsed();
     // println!("Compressing orig: {:.2?}", elapsed);
 
-    patch_target
-        .write_all(&temp_compressed)
-        .expect("Unable to write to patch file!");
+    storage.put(&patch_id, &temp_compressed)?;
 
     // let now = Instant::now();
 
     // let mut writer = brotli::Compressor::new(&mut io::stdout(), 4096, 4, 20);
-    let patch_store_file = time_dir.clone() + "/patches.json";
-
     let patch_entry = DiffEntry {
         date_created: current_time,
         target_path,
@@ -126,20 +110,16 @@ pub fn create_diff( // Never call this on a directory. Do checks outside of the
     {
         let mut patch_store = patch_store.lock().unwrap();
         patch_store.push(patch_entry);
-
-        let json =
-            serde_json::to_string_pretty(&*patch_store).expect("Unable to serialize metadata!");
-        let mut file = File::create(Path::new(&patch_store_file)).unwrap_or_else(|_| panic!("Unable to create metadata file at {patch_store_file}"));
-        file.write_all(json.as_bytes()).unwrap_or_else(|_| panic!("Unable to write to metadata file at {patch_store_file}"));
     }
-    Ok(patch_id)
+    Ok((patch_id, temp_compressed.len() as u64))
 }
 
 pub fn get_diffs(
     check_hash: bool,
     metadata_holder: &HashSet<MetaFile>,
     folder_path: &str,
-) -> Result<HashSet<ModifiedList>, Box<dyn Error>> {
+    time_dir: &str,
+) -> Result<HashSet<ModifiedList>, FtmError> {
     
     let mut different_files: HashSet<ModifiedList> = HashSet::new();
     let mut temp_hold: HashSet<ModifiedList> = HashSet::new();
@@ -150,7 +130,7 @@ pub fn get_diffs(
         let path = entry.path();
         // debug!("{:?}", path);
         if let Some(path_str) = path.to_str() {
-            if !path_str.contains(".time") && !path_str.contains(".git") && path_str != folder_path {
+            if is_tracked(path_str, folder_path, time_dir) {
                 current_files.insert(ModifiedList {
                     path: path_str.to_string(),
                     exists: true,
@@ -191,11 +171,7 @@ pub fn get_diffs(
                 // File exists, continue
                 // let metadata = metadata(&meta.path)?;
                 // Get the modification time from the metadata
-                let modified_time = metadata.modified()?; // Replace ? with proper error handling if we want to do it here. Otherwise, we handle it outside the function.
-
-                // Convert SystemTime to UNIX epoch
-                let duration_since_epoch = modified_time.duration_since(UNIX_EPOCH)?;
-                let epoch_seconds = duration_since_epoch.as_secs();
+                let epoch_seconds = modified_seconds(&metadata, &meta.path)?;
                 // Checking date modified and size is prioritized over hash since it is much faster.
                 // if Path::new(&meta.path.clone()).is_file() {
                     // Ensure the parent directory is not counted as updated file
@@ -261,11 +237,8 @@ pub fn get_diffs(
                         modified: true,
                     });
                 }
-                other_error => {
-                    panic!(
-                        "Problem reading file: {} with error: {}",
-                        meta.path, other_error
-                    );
+                _ => {
+                    return Err(FtmError::Io { path: meta.path.clone(), source: error });
                 }
             },
         }
@@ -278,8 +251,9 @@ pub fn update_metadata(
     metadata_holder: &mut HashSet<MetaFile>,
     modified_list: &HashSet<ModifiedList>,
     hash_enabled: bool,
-) -> Result<(), Box<dyn Error>> {
-    // Update metadata with modified_list to update data.
+    on_progress: &dyn ProgressSink,
+) -> Result<bool, FtmError> {
+    // Update metadata with modified_list to update data. Returns false without touching metadata_holder if nothing changed.
     let mut paths_to_update = Vec::new(); // Paths that need updating
     let mut temp_hold: HashSet<ModifiedList> = HashSet::new();
     let mut updated_files = HashSet::new(); // Temp set to hold elements that we will add at the end
@@ -321,7 +295,7 @@ pub fn update_metadata(
     //     paths_to_update.push(path.path.clone());
     // }
 
-    println!("Finished generating list. Recalculating metadata...");
+    on_progress.event(Progress::Message { text: "Finished generating list. Recalculating metadata...".to_string() });
     // debug!("{:?}", modified_list);
     // println!("{:?}", modified_list);
     {
@@ -333,27 +307,21 @@ pub fn update_metadata(
             }
         }
         if !modified_files {
-            println!("No files changed, nothing to do!");
-            process::exit(1);
+            return Ok(false);
         }
     }
 
     for path in paths_to_update {
         let _hash_str: String = Default::default();
         if hash_enabled {
-            let _hash_str: String = hash(&path).unwrap_or_else(|_| panic!("There was a unhandled issue getting the hash of {path}"));
+            let _hash_str: String = hash(&path)?;
         } else {
             let _hash_str: String = "".to_string();
         }
-        let file_metadata = metadata(&path)?;
+        let file_metadata = metadata(&path).at(&path)?;
         let size = file_metadata.len(); // Get file size
 
-        // Get the modification time from the metadata
-        let modified_time = file_metadata.modified()?;
-
-        // Convert SystemTime to UNIX epoch
-        let duration_since_epoch = modified_time.duration_since(UNIX_EPOCH)?;
-        let epoch_seconds = duration_since_epoch.as_secs();
+        let epoch_seconds = modified_seconds(&file_metadata, &path)?;
 
         let updated_meta_file = MetaFile {
             date_modified: epoch_seconds,
@@ -389,53 +357,47 @@ pub fn update_metadata(
 
     metadata_holder.retain(|meta| !paths_to_remove.contains(&meta.path));
 
-    Ok(())
+    Ok(true)
 }
 
 pub fn get_properties(
     folder_path: &str,
+    time_dir: &str,
     mut metadata_holder: HashSet<MetaFile>,
     hash_enabled: bool,
-) -> Result<HashSet<MetaFile>, Box<dyn std::error::Error>> {
+    on_progress: &dyn ProgressSink,
+) -> Result<HashSet<MetaFile>, FtmError> {
     let mut file_count = 0;
-    let mut file_index = 0;
 
-    for _entry in WalkDir::new(folder_path) {
-        file_count += 1;
+    for entry in WalkDir::new(folder_path) {
+        // Same paths as the loop below, so the count matches the FileProcessed events
+        if let Some(path_str) = entry?.path().to_str() {
+            if is_tracked(path_str, folder_path, time_dir) {
+                file_count += 1;
+            }
+        }
     }
 
-    let pb = ProgressBar::new(file_count);
-    pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos:>3}/{len:3} ({eta})")
-        .unwrap()
-        .with_key("eta", |state: &ProgressState, w: &mut dyn std::fmt::Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
-        .progress_chars("#>-"));
+    on_progress.event(Progress::Indexing { files: file_count });
 
     for entry in WalkDir::new(folder_path) {
-        file_index += 1;
-        pb.set_position(file_index); // Update progress bar.
-
         let entry = entry?;
         let path = entry.path();
 
         // Convert Path to &str
         if let Some(path_str) = path.to_str() {
-            if !path_str.contains(".time") && !path_str.contains(".git") && path_str != folder_path {
+            if is_tracked(path_str, folder_path, time_dir) {
                 // Use the path as a &str
                 let _hash_str: String = Default::default();
                 if hash_enabled {
-                    let _hash_str: String = hash(path_str).unwrap_or_else(|_| panic!("There was a unhandled issue getting the hash of {path_str}"));
+                    let _hash_str: String = hash(path_str)?;
                 } else {
                     let _hash_str: String = "".to_string();
                 }
-                let metadata = metadata(path)?;
+                let metadata = metadata(path).at(path_str)?;
                 let size = metadata.len(); // Get file size
 
-                // Get the modification time from the metadata
-                let modified_time = metadata.modified()?;
-
-                // Convert SystemTime to UNIX epoch
-                let duration_since_epoch = modified_time.duration_since(UNIX_EPOCH)?;
-                let epoch_seconds = duration_since_epoch.as_secs();
+                let epoch_seconds = modified_seconds(&metadata, path_str)?;
                 // println!("{}", size);
                 // println!("{}", epoch_seconds);
                 // println!("{}", path_str);
@@ -447,6 +409,7 @@ pub fn get_properties(
                     path: path_str.to_string(),
                 };
                 metadata_holder.insert(meta_file);
+                on_progress.event(Progress::FileProcessed { path: path_str.to_string(), bytes: size });
             }
             // metadata_holder.push(MetaFile {hash: hash});
         } else {
@@ -454,31 +417,43 @@ pub fn get_properties(
             eprintln!("Error: Path is not valid UTF-8: {}", path.display());
         }
     }
-    pb.finish();
     Ok(metadata_holder)
 }
 
-pub fn hash(path: &str) -> Result<String, Box<dyn Error>> {
+pub fn is_tracked(path: &str, folder_path: &str, time_dir: &str) -> bool {
+    // Snapshots skip the folder itself, .git and .time, wherever .time is kept
+    path != folder_path && !Path::new(path).starts_with(time_dir) && !path.contains(".git")
+}
+
+pub fn file_processed(path: &str) -> Progress {
+    // Directories and removed files are reported with 0 bytes
+    let bytes = metadata(path).ok().filter(|meta| meta.is_file()).map(|meta| meta.len()).unwrap_or(0);
+    Progress::FileProcessed { path: path.to_string(), bytes }
+}
+
+pub fn patch_written(path: &str, patch_id: &str, bytes: u64) -> Progress {
+    Progress::PatchWritten { path: path.to_string(), patch_id: patch_id.to_string(), bytes }
+}
+
+pub fn modified_seconds(metadata: &Metadata, path: &str) -> Result<u64, FtmError> {
+    // Date modified as seconds since the UNIX epoch, which is what metadata.json stores
+    let modified_time = metadata.modified().at(path)?;
+    let duration_since_epoch = modified_time
+        .duration_since(UNIX_EPOCH)
+        .map_err(io::Error::other)
+        .at(path)?;
+    Ok(duration_since_epoch.as_secs())
+}
+
+pub fn hash(path: &str) -> Result<String, FtmError> {
     // println!("hash called");
-    let mut file = match File::open(Path::new(path)) {
-        Ok(file) => file,
-        Err(e) => {
-            if e.kind() == io::ErrorKind::NotFound {
-                eprintln!("Error: The file '{}' was not found.", path);
-                panic!("quit");
-            } else {
-                // Handle other kinds of I/O errors
-                eprintln!("Error: Unable to open file '{}': {}", path, e);
-            }
-            return Err(Box::new(e));
-        }
-    };
+    let mut file = File::open(Path::new(path)).at(path)?;
 
     let mut hasher = Sha256::new();
 
     let mut buffer = [0u8; 1024];
-    while let Ok(bytes_read) = file.read(&mut buffer) {
-        // Run the loop as long as file.read returns Ok(bytes_read)
+    loop {
+        let bytes_read = file.read(&mut buffer).at(path)?;
         if bytes_read == 0 {
             break;
         }
@@ -493,12 +468,13 @@ pub fn hash(path: &str) -> Result<String, Box<dyn Error>> {
     Ok(hash_string)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn create_diffs_multithread(
     patch_ids: &Arc<Mutex<Vec<String>>>,
     ref_patch_ids: &Arc<Mutex<Vec<String>>>,
     target_paths: &Arc<Mutex<Vec<String>>>,
     modified: &Arc<Mutex<Vec<bool>>>,
-    folder_path: &String,
+    storage: &Arc<dyn StorageBackend>,
     changed_files_vec: Vec<ModifiedList>, // We need it to be a vec since hashset doesn't support slices
     changed_count: u32,
     thread_count: u32,
@@ -506,8 +482,9 @@ pub fn create_diffs_multithread(
     patch_store: &Arc<Mutex<Vec<DiffEntry>>>, // This will be populated if first run, otherwise it must be pre populated
     mut create_reverse: bool,
     inital_run: bool,
-    snapshot_mode: &String,
-) {
+    snapshot_mode: &str,
+    on_progress: &dyn ProgressSink,
+) -> Result<(), FtmError> {
     /*
     Get the amount that we should give to each thread via split_into. Then calculate slice begin and end
     and pass a cloned slice, the thread can own this. The thread will need to lock and unlock patch_ids and target_paths
@@ -530,11 +507,12 @@ pub fn create_diffs_multithread(
         }
     }
 
-    let m = MultiProgress::new();
+    // Children can't share on_progress, so they send their events here and we pass them on while waiting for them
+    let (progress_tx, progress_rx) = mpsc::channel();
 
     for i in 0..thread_count {
-        // Spawn our childrenfolder_path
-        let folder_path_new = folder_path.clone(); // To prevent moving ownership, we need to clone this value.
+        // Spawn our children
+        let storage = Arc::clone(storage); // To prevent moving ownership, we need to clone this value.
         let slice_begin: usize = (i * split_into).try_into().unwrap();
         let mut slice_end: usize = ((i * split_into) + split_into).try_into().unwrap();
         // println!("slice_begin: {}", slice_begin);
@@ -547,7 +525,7 @@ pub fn create_diffs_multithread(
         let ref_patch_ids = Arc::clone(ref_patch_ids);
         let patch_store = Arc::clone(patch_store);
         let modified = Arc::clone(modified);
-
+        let progress = progress_tx.clone();
 
 
         let slice = changed_files_vec[slice_begin..slice_end].to_vec(); // Create new vector since our reference will die
@@ -557,19 +535,18 @@ pub fn create_diffs_multithread(
                 for path in slice.iter() {
                     if path.modified {
                         if Path::new(&path.path.clone()).is_file() {
-                            let patch_id = create_diff(
+                            let (patch_id, bytes) = create_diff(
                                 "".to_string(), // This will never exist, so we can always create a temp file instead.
                                 path.path.clone(),
                                 path.path.clone(),
-                                folder_path_new.clone() + "/.time",
+                                &*storage,
                                 "First patch".to_string(),
                                 Vec::new(),
                                 compression_level,
                                 &patch_store,
                                 create_reverse,
-                            )
-                            .unwrap_or_else(|_| panic!("Was unable to create a diff between a new empty file and {}",
-                                path.path));
+                            )?;
+                            let _ = progress.send(patch_written(&path.path, &patch_id, bytes));
                             {
                                 let mut patch_ids = patch_ids.lock().unwrap();
                                 let mut target_paths = target_paths.lock().unwrap();
@@ -601,8 +578,7 @@ pub fn create_diffs_multithread(
                         let mut modified = modified.lock().unwrap();
                         
                         if Path::new(&path.path).is_file() {
-                            let file_contents = std::fs::read(&path.path).unwrap_or_else(|_| panic!("Could not open {} to check if it has been modified! Do I have read permission?",
-                            path.path));
+                            let file_contents = std::fs::read(&path.path).at(&path.path)?;
                             let hash = xxh3_64(&file_contents);
                             patch_ids.push(hash.to_string());
                         } else {
@@ -612,25 +588,21 @@ pub fn create_diffs_multithread(
                         ref_patch_ids.push("UNMODIFIED".to_string());
                         modified.push(false); 
                     }
+                    if path.modified {
+                        let _ = progress.send(file_processed(&path.path));
+                    }
                 }
+                Ok(())
             }));
         } else {
             create_reverse = true;
             debug!("create_reverse is true");
             let path_temp_hold = path_temp_hold_ref.clone();
-            let folder_path_clone = folder_path.clone();
-            let m = m.clone();
-            let snapshot_mode = snapshot_mode.clone(); // Is this creating correct snapshots?
+            let snapshot_mode = snapshot_mode.to_string(); // Is this creating correct snapshots?
+            let patch_store_file = storage.location(PATCH_STORE);
             children.push(thread::spawn(move || {
-                let total: u64 = slice.len() as u64;
-                let pb = m.add(ProgressBar::new(total));
-                pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos:>3}/{len:3} ({eta})")
-        .unwrap()
-        .with_key("eta", |state: &ProgressState, w: &mut dyn std::fmt::Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
-        .progress_chars("#>-"));
                 for path in slice.iter() {
                     if path.modified {
-                        pb.inc(1);
                         // println!("{}", path.path.clone());
 
                         if path_temp_hold.contains(&ModifiedList {
@@ -658,20 +630,15 @@ pub fn create_diffs_multithread(
                                     if matching_items.len() > 1 {
                                         // println!("Found matching items:");
                                         // println!("{:?}", matching_items);
-                                        let mut date_check;
-                                        let mut target_path: String;
-                                        if let Some(first_item) = matching_items.first() {
-                                            let first_date_string = first_item.date_created.clone();
-                                            // println!("{first_date_string}");
-                                            date_check = DateTime::parse_from_str(
-                                                &first_date_string,
-                                                "%Y-%m-%d %H:%M:%S%.9f %z",
-                                            )
-                                            .unwrap();
-                                            target_path = first_item.target_path.clone();
-                                        } else {
-                                            panic!("There was an issue parsing the patch store! Is this a valid date: {:?}", matching_items);
-                                        }
+                                        let first_item = matching_items[0];
+                                        let first_date_string = first_item.date_created.clone();
+                                        // println!("{first_date_string}");
+                                        let mut date_check = DateTime::parse_from_str(
+                                            &first_date_string,
+                                            "%Y-%m-%d %H:%M:%S%.9f %z",
+                                        )
+                                        .map_err(|e| corrupt_store(&patch_store_file, e))?;
+                                        let mut target_path = first_item.target_path.clone();
                                         // Find correct patch to restore
                                         debug!("{:?}", matching_items);
                                         for item in matching_items {
@@ -681,7 +648,7 @@ pub fn create_diffs_multithread(
                                                 &date_check_string,
                                                 "%Y-%m-%d %H:%M:%S%.9f %z",
                                             )
-                                            .unwrap();
+                                            .map_err(|e| corrupt_store(&patch_store_file, e))?;
                                             // println!("{}", new_date_check);
                                             // println!("{}", date_check);
                                             if new_date_check > date_check {
@@ -691,15 +658,16 @@ pub fn create_diffs_multithread(
                                             }
                                         }
                                         if Path::new(&target_path).is_file() {
-                                            let patch_id = restore::restore_and_diff(
+                                            let (patch_id, bytes) = restore::restore_and_diff(
                                                 &date_check.to_string(),
                                                 &target_path,
-                                                &folder_path_clone.clone(),
+                                                &*storage,
                                                 compression_level,
                                                 &patch_store,
                                                 create_reverse,
                                                 &snapshot_mode
-                                            ).expect("There was an issue restoring a reference patch and creating a new patch, did the .time folder go corrupt?");
+                                            )?;
+                                            let _ = progress.send(patch_written(&path.path, &patch_id, bytes));
                                         
                                         {
                                             let mut patch_ids = patch_ids.lock().unwrap();
@@ -733,16 +701,17 @@ pub fn create_diffs_multithread(
                                             // let mut patch_store = patch_store.lock().unwrap();
                                             if let Some(first_item) = matching_items.first() {
                                                 if Path::new(&first_item.target_path).is_file() {
-                                                let patch_id = restore::restore_and_diff(
+                                                let (patch_id, bytes) = restore::restore_and_diff(
                                             &first_item.date_created,
                                             &first_item.target_path,
-                                            &folder_path_clone.clone(),
+                                            &*storage,
                                             compression_level,
                                             &patch_store,
                                             create_reverse,
                                             &snapshot_mode
                                             
-                                        ).expect("There was an issue restoring a reference patch and creating a new patch, did the .time folder go corrupt?");
+                                        )?;
+                                                let _ = progress.send(patch_written(&path.path, &patch_id, bytes));
                                     
                                                 {
                                                     let mut patch_ids = patch_ids.lock().unwrap();
@@ -772,25 +741,24 @@ pub fn create_diffs_multithread(
                                             } 
                                     }
                                 } else {
-                                    panic!("Did not find a valid patch in the patch store, even though there should be one!");
+                                    return Err(FtmError::MissingPatch { target_path: search_path });
                                 }
                             
                         } else if path.exists {
                             debug!("No existing patch! I will create a compressed copy of the original file. ");
                             if Path::new(&path.path).is_file() {
-                                let patch_id = create_diff(
+                                let (patch_id, bytes) = create_diff(
                                     "".to_string(),
                                     path.path.clone(),
                                     path.path.clone(),
-                                    folder_path_clone.clone() + "/.time",
+                                    &*storage,
                                     "First patch".to_string(),
                                     Vec::new(),
                                     compression_level,
                                     &patch_store,
                                     create_reverse,
-                                )
-                                .unwrap_or_else(|_| panic!("Was unable to create a diff from a new empty file and {}",
-                                    path.path));
+                                )?;
+                                let _ = progress.send(patch_written(&path.path, &patch_id, bytes));
                                 {
                                     let mut patch_ids = patch_ids.lock().unwrap();
                                     let mut target_paths = target_paths.lock().unwrap();
@@ -844,8 +812,7 @@ pub fn create_diffs_multithread(
                         // We will take a hash of the date modified and size of the file to use as an way to identify when the file has been changed.
 
                         if Path::new(&path.path).is_file() {
-                            let file_contents = std::fs::read(&path.path).unwrap_or_else(|_| panic!("Could not open {} to check if it has been modified! Do I have read permission?",
-                            path.path));
+                            let file_contents = std::fs::read(&path.path).at(&path.path)?;
                             let hash = xxh3_64(&file_contents);
                             patch_ids.push(hash.to_string());
                         } else {
@@ -856,14 +823,28 @@ pub fn create_diffs_multithread(
                         modified.push(false); 
                         // debug!("Skipping {} because it is not modified!", path.path);
                     }
+                    if path.modified {
+                        let _ = progress.send(file_processed(&path.path));
+                    }
                 }    // Code for checking existing snapshot goes here
-                pb.finish();
+                Ok(())
             }))
         }
     }
+    drop(progress_tx); // Once every child is done with its copy, the loop below ends
+    for event in progress_rx {
+        on_progress.event(event);
+    }
+    // Wait for every child before returning, so none of them is still writing patches when we report the first error
+    let mut result = Ok(());
     for handle in children {
-        // Wait for our children to die
-        handle.join().expect("There was an issue joining all the threads, did a child die?");
+        let child_result = handle
+            .join()
+            .unwrap_or_else(|_| Err(FtmError::Thread("A snapshot thread died before it finished!".to_string())));
+        if result.is_ok() {
+            result = child_result;
+        }
     }
+    result
 }
 
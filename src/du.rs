@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::ops::AddAssign;
+
+use crate::restore;
+use crate::storage::{PATCH_STORE, SNAPSHOT_STORE};
+use crate::DiffEntry;
+use crate::SnapshotEntries;
+
+#[derive(Default, Clone, Copy)]
+pub struct PatchSizes {
+    pub first: u64,   // First patches, which hold a whole file
+    pub forward: u64, // Patches from a First patch to a newer version
+    pub reverse: u64, // Patches back to the First patch, plus the placeholders First patches get
+}
+
+impl PatchSizes {
+    pub fn total(&self) -> u64 {
+        self.first + self.forward + self.reverse
+    }
+}
+
+impl AddAssign for PatchSizes {
+    fn add_assign(&mut self, other: PatchSizes) {
+        self.first += other.first;
+        self.forward += other.forward;
+        self.reverse += other.reverse;
+    }
+}
+
+pub struct Usage {
+    pub snapshots: Vec<PatchSizes>, // Same order as the snapshot store
+    pub paths: Vec<(String, PatchSizes)>, // Largest first
+    pub unreferenced: PatchSizes,   // Patches no snapshot lists, gc can remove these
+    pub stores: u64,                // patches.json, snapshots.json and metadata.json
+}
+
+fn file_size(path: &str) -> u64 {
+    std::fs::metadata(path)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0)
+}
+
+pub fn measure(
+    time_dir: &str,
+    sizes: &HashMap<String, u64>, // Size of every object in storage, see storage::sizes
+    snapshot_store: &[SnapshotEntries],
+    patch_store: &[DiffEntry],
+) -> Usage {
+    /* A patch is charged to the first snapshot that lists it as a modified file, which is the snapshot that
+    created it. Later snapshots that still have the same version only point back to it, so they cost nothing.
+    */
+    let mut created_by: HashMap<&String, usize> = HashMap::new();
+    for (index, entry) in snapshot_store.iter().enumerate() {
+        for (position, id) in entry.patch_ids.iter().enumerate() {
+            if entry.modified[position] && id.len() == 64 {
+                created_by.entry(id).or_insert(index);
+            }
+        }
+    }
+
+    let mut usage = Usage {
+        snapshots: vec![PatchSizes::default(); snapshot_store.len()],
+        paths: Vec::new(),
+        unreferenced: PatchSizes::default(),
+        stores: 0,
+    };
+    let mut paths: HashMap<&String, PatchSizes> = HashMap::new();
+
+    for patch_entry in patch_store.iter() {
+        let patch_id = restore::patch_id_of(patch_entry);
+        let patch_size = sizes.get(&patch_id).copied().unwrap_or(0);
+        let mut patch_sizes = PatchSizes {
+            reverse: sizes
+                .get(&(patch_id.clone() + "-reverse"))
+                .copied()
+                .unwrap_or(0),
+            ..Default::default()
+        };
+        if patch_entry.ref_patch == "First patch" {
+            patch_sizes.first = patch_size;
+        } else {
+            patch_sizes.forward = patch_size;
+        }
+
+        match created_by.get(&patch_id) {
+            Some(index) => usage.snapshots[*index] += patch_sizes,
+            None => usage.unreferenced += patch_sizes,
+        }
+        *paths.entry(&patch_entry.target_path).or_default() += patch_sizes;
+    }
+
+    usage.paths = paths
+        .into_iter()
+        .map(|(target_path, sizes)| (target_path.clone(), sizes))
+        .collect();
+    usage
+        .paths
+        .sort_by(|a, b| b.1.total().cmp(&a.1.total()).then(a.0.cmp(&b.0)));
+    for store in [PATCH_STORE, SNAPSHOT_STORE] {
+        usage.stores += sizes.get(store).copied().unwrap_or(0);
+    }
+    usage.stores += file_size(&(time_dir.to_string() + "/metadata.json")); // Always kept locally
+    usage
+}
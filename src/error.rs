@@ -0,0 +1,109 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum FtmError {
+    Io { path: String, source: io::Error }, // Reading, writing, creating or removing path failed
+    CorruptPatch { patch_id: String, reason: String }, // The patch file exists but could not be decompressed or applied
+    MissingPatch { target_path: String }, // The patch store has nothing to rebuild this path from
+    CorruptStore { path: String, reason: String }, // A json store or activeSnapshot could not be parsed
+    Config(String),
+    InvalidSnapshot { index: usize, count: usize }, // index starts at 1, like restore --restore-index
+    NoSnapshots,
+    UnknownTag(String), // No snapshot has this tag
+    AlreadyActive,
+    Thread(String), // A snapshot worker died without reporting what went wrong
+    Storage { location: String, reason: String }, // The storage backend refused a request or could not be reached
+    Listen { address: String, reason: String },   // serve or the daemon could not listen on address
+    Watch { path: String, reason: String }, // The file watcher could not be started or stopped working
+}
+
+impl fmt::Display for FtmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FtmError::Io { path, source } => write!(f, "{path}: {source}"),
+            FtmError::CorruptPatch { patch_id, reason } => {
+                write!(f, "Patch {patch_id} is corrupt: {reason}")
+            }
+            FtmError::MissingPatch { target_path } => {
+                write!(f, "Could not find a patch to rebuild {target_path} from")
+            }
+            FtmError::CorruptStore { path, reason } => write!(f, "{path} is corrupt: {reason}"),
+            FtmError::Config(message) => write!(f, "{message}"),
+            FtmError::InvalidSnapshot { index, count } => write!(
+                f,
+                "{index} is an invalid snapshot. Valid snapshots are 1 to {count}."
+            ),
+            FtmError::NoSnapshots => write!(f, "No snapshots have been created yet."),
+            FtmError::UnknownTag(tag) => write!(f, "No snapshot is tagged {tag}."),
+            FtmError::AlreadyActive => write!(
+                f,
+                "The snapshot you selected is already the active snapshot! Nothing to do."
+            ),
+            FtmError::Thread(message) => write!(f, "{message}"),
+            FtmError::Storage { location, reason } => write!(f, "{location}: {reason}"),
+            FtmError::Listen { address, reason } => {
+                write!(f, "Could not listen on {address}: {reason}")
+            }
+            FtmError::Watch { path, reason } => write!(f, "Could not watch {path}: {reason}"),
+        }
+    }
+}
+
+impl FtmError {
+    pub fn is_not_found(&self) -> bool {
+        // Every storage backend reports a missing object like a missing file
+        matches!(self, FtmError::Io { source, .. } if source.kind() == io::ErrorKind::NotFound)
+    }
+}
+
+impl Error for FtmError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FtmError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<walkdir::Error> for FtmError {
+    fn from(error: walkdir::Error) -> FtmError {
+        let path = error
+            .path()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+        FtmError::Io {
+            path,
+            source: error.into(),
+        }
+    }
+}
+
+pub trait IoContext<T> {
+    fn at(self, path: &str) -> Result<T, FtmError>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    // Attach the path an io::Error happened on, std only says what went wrong
+    fn at(self, path: &str) -> Result<T, FtmError> {
+        self.map_err(|source| FtmError::Io {
+            path: path.to_string(),
+            source,
+        })
+    }
+}
+
+pub fn corrupt_patch(patch_id: &str, reason: impl fmt::Display) -> FtmError {
+    FtmError::CorruptPatch {
+        patch_id: patch_id.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+pub fn corrupt_store(path: &str, reason: impl fmt::Display) -> FtmError {
+    FtmError::CorruptStore {
+        path: path.to_string(),
+        reason: reason.to_string(),
+    }
+}
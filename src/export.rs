@@ -0,0 +1,242 @@
+use chrono::{DateTime, Datelike, Local, Timelike};
+use flate2::write::GzEncoder;
+use log::debug;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use zip::write::{SimpleFileOptions, StreamWriter};
+use zip::ZipWriter;
+
+use crate::error::{FtmError, IoContext};
+use crate::restore::{self, PathVersion};
+use crate::snapshot_manager;
+use crate::storage::StorageBackend;
+use crate::DiffEntry;
+use crate::MetaFile;
+use crate::SnapshotEntries;
+
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn parse(name: &str) -> Result<ArchiveFormat, FtmError> {
+        match name {
+            "tar" => Ok(ArchiveFormat::Tar),
+            "tar.gz" | "tgz" => Ok(ArchiveFormat::TarGz),
+            "zip" => Ok(ArchiveFormat::Zip),
+            _ => Err(FtmError::Config(format!(
+                "{name} is not a supported format, use tar, tar.gz or zip"
+            ))),
+        }
+    }
+
+    pub fn extension(&self) -> &str {
+        match self {
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::Zip => "zip",
+        }
+    }
+}
+
+enum Archive<'a> {
+    Tar(tar::Builder<&'a mut dyn Write>),
+    TarGz(Box<tar::Builder<GzEncoder<&'a mut dyn Write>>>),
+    // Sizes and checksums go in a data descriptor after every file, so zip never seeks back either
+    Zip(Box<ZipWriter<StreamWriter<&'a mut dyn Write>>>),
+}
+
+fn append_tar<W: Write>(
+    builder: &mut tar::Builder<W>,
+    relative: &str,
+    contents: Option<&[u8]>,
+    mtime: i64,
+) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_mtime(mtime.max(0) as u64);
+    match contents {
+        Some(contents) => {
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_size(contents.len() as u64);
+            builder.append_data(&mut header, relative, contents)?;
+        }
+        None => {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            builder.append_data(&mut header, relative.to_string() + "/", std::io::empty())?;
+        }
+    }
+    Ok(())
+}
+
+impl Archive<'_> {
+    fn append(&mut self, relative: &str, contents: Option<&[u8]>, mtime: i64) -> io::Result<()> {
+        match self {
+            Archive::Tar(builder) => append_tar(builder, relative, contents, mtime),
+            Archive::TarGz(builder) => append_tar(builder, relative, contents, mtime),
+            Archive::Zip(writer) => {
+                // Zip stores local time, and can't represent anything before 1980
+                let date = DateTime::from_timestamp(mtime, 0)
+                    .unwrap_or_default()
+                    .with_timezone(&Local);
+                let zip_date = zip::DateTime::from_date_and_time(
+                    date.year() as u16,
+                    date.month() as u8,
+                    date.day() as u8,
+                    date.hour() as u8,
+                    date.minute() as u8,
+                    date.second() as u8,
+                )
+                .unwrap_or_default();
+                let options = SimpleFileOptions::default()
+                    .compression_method(zip::CompressionMethod::Deflated)
+                    .last_modified_time(zip_date);
+                match contents {
+                    Some(contents) => {
+                        writer.start_file(relative, options.unix_permissions(0o644))?;
+                        writer.write_all(contents)?;
+                    }
+                    None => {
+                        // add_directory skips the data descriptor a streamed entry needs, so unzip rejects the archive
+                        writer.start_file(
+                            relative.to_string() + "/",
+                            options
+                                .compression_method(zip::CompressionMethod::Stored)
+                                .unix_permissions(0o755),
+                        )?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Archive::Tar(builder) => {
+                builder.into_inner()?.flush()?;
+            }
+            Archive::TarGz(builder) => {
+                builder.into_inner()?.finish()?.flush()?;
+            }
+            Archive::Zip(writer) => {
+                writer.finish()?.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn date_to_epoch(date_created: &str) -> i64 {
+    DateTime::parse_from_str(date_created, "%Y-%m-%d %H:%M:%S%.9f %z")
+        .map(|date| date.timestamp())
+        .unwrap_or(0)
+}
+
+pub fn collect_mtimes(metadata_holder: &HashSet<MetaFile>) -> HashMap<String, i64> {
+    let mut mtimes = HashMap::new();
+    for meta in metadata_holder.iter() {
+        mtimes.insert(meta.path.clone(), meta.date_modified as i64);
+    }
+    mtimes
+}
+
+pub fn version_mtime(
+    snapshot_store: &[SnapshotEntries],
+    patch_index: &HashMap<String, DiffEntry>,
+    mtimes: &HashMap<String, i64>,
+    snapshot_index: usize,
+    target_path: &str,
+    version: &PathVersion,
+) -> i64 {
+    /* Snapshots don't store mtimes. If a file is still the same as in the most recent snapshot, the mtime in
+    metadata.json is used. Otherwise the time the patch was created is the closest we have. Directories get the
+    time of the snapshot, metadata.json only has their current mtime, which changes with anything inside them.
+    */
+    let PathVersion::Patch(id) = version else {
+        return date_to_epoch(&snapshot_store[snapshot_index].date_created);
+    };
+    let latest_index = snapshot_store.len() - 1;
+    let same_as_latest =
+        *version == restore::resolve_version(snapshot_store, latest_index, target_path);
+    match (same_as_latest, mtimes.get(target_path)) {
+        (true, Some(mtime)) => *mtime,
+        _ => date_to_epoch(&patch_index[id].date_created),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn export_snapshot(
+    snapshot_store: &[SnapshotEntries],
+    patch_index: &HashMap<String, DiffEntry>,
+    metadata_holder: &HashSet<MetaFile>,
+    storage: &dyn StorageBackend,
+    folder_path: &str,
+    snapshot_index: usize,
+    format: &ArchiveFormat,
+    output: &mut dyn Write,
+) -> Result<usize, FtmError> {
+    // Every file is rebuilt in memory and written to the archive right away, so only one file is held at a time.
+    let mtimes = collect_mtimes(metadata_holder);
+
+    let entry = &snapshot_store[snapshot_index];
+    let mut paths: Vec<&String> = entry
+        .target_path
+        .iter()
+        .enumerate()
+        .filter(|(position, _)| entry.patch_ids[*position] != "REMOVED")
+        .map(|(_, path)| path)
+        .collect();
+    paths.sort(); // Directories come before anything inside them
+
+    let mut archive = match format {
+        ArchiveFormat::Tar => Archive::Tar(tar::Builder::new(output)),
+        ArchiveFormat::TarGz => Archive::TarGz(Box::new(tar::Builder::new(GzEncoder::new(
+            output,
+            flate2::Compression::default(),
+        )))),
+        ArchiveFormat::Zip => Archive::Zip(Box::new(ZipWriter::new_stream(output))),
+    };
+    let mut count = 0;
+
+    for target_path in paths {
+        let relative = snapshot_manager::relative_path(folder_path, target_path);
+        let version = restore::resolve_version(snapshot_store, snapshot_index, target_path);
+        let mtime = version_mtime(
+            snapshot_store,
+            patch_index,
+            &mtimes,
+            snapshot_index,
+            target_path,
+            &version,
+        );
+        match version {
+            PathVersion::Directory => {
+                debug!("Adding directory {}", relative);
+                archive.append(relative, None, mtime).at(relative)?;
+            }
+            PathVersion::Patch(id) => {
+                debug!("Adding {} from {}", relative, id);
+                let contents = restore::rebuild_patch(storage, patch_index, &id)?;
+                archive
+                    .append(relative, Some(&contents), mtime)
+                    .at(relative)?;
+                count += 1;
+            }
+            PathVersion::Missing => {
+                return Err(FtmError::MissingPatch {
+                    target_path: target_path.to_string(),
+                });
+            }
+        }
+    }
+    // Nothing tells which file the archive goes to, so errors from here on only name the format
+    archive
+        .finish()
+        .at(&format!("the {} archive", format.extension()))?;
+    Ok(count)
+}
@@ -0,0 +1,130 @@
+use log::debug;
+use std::collections::{HashMap, HashSet};
+
+use crate::error::FtmError;
+use crate::restore;
+use crate::snapshot_manager;
+use crate::storage::{self, StorageBackend};
+use crate::DiffEntry;
+use crate::SnapshotEntries;
+
+pub struct Garbage {
+    pub dangling_files: Vec<String>, // Patch files in storage that are not in the patch store
+    pub unreferenced_patches: Vec<DiffEntry>, // Patch store rows that no snapshot needs
+    pub stale_files: Vec<String>,    // Temporary files left behind by a run that did not finish
+}
+
+fn is_patch_id(name: &str) -> bool {
+    name.len() == 64 && name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+pub fn referenced_patches(
+    snapshot_store: &[SnapshotEntries],
+    patch_index: &HashMap<String, DiffEntry>,
+) -> HashSet<String> {
+    /* A patch is needed if a snapshot points to it, either as its version of a file or as the patch it was
+    created from, or if a needed patch was diffed against it. Unmodified files point back to an older snapshot
+    that lists the patch itself, so they don't need to be resolved.
+    */
+    let mut referenced = HashSet::new();
+    let mut pending = Vec::new();
+    for entry in snapshot_store.iter() {
+        for id in entry.patch_ids.iter().chain(entry.ref_patch_ids.iter()) {
+            if patch_index.contains_key(id) {
+                pending.push(id.clone());
+            }
+        }
+    }
+    while let Some(id) = pending.pop() {
+        if !referenced.insert(id.clone()) {
+            continue;
+        }
+        let ref_patch = &patch_index[&id].ref_patch;
+        if patch_index.contains_key(ref_patch) {
+            pending.push(ref_patch.clone());
+        }
+    }
+    referenced
+}
+
+pub fn find_garbage(
+    storage: &dyn StorageBackend,
+    snapshot_store: &[SnapshotEntries],
+    patch_store: &[DiffEntry],
+) -> Result<Garbage, FtmError> {
+    // Only files that look like patches or temporary files are considered, anything else in storage is left alone.
+    let patch_index = restore::index_patch_store(patch_store);
+    let referenced = referenced_patches(snapshot_store, &patch_index);
+    let mut garbage = Garbage {
+        dangling_files: Vec::new(),
+        unreferenced_patches: Vec::new(),
+        stale_files: Vec::new(),
+    };
+
+    for patch_entry in patch_store.iter() {
+        if !referenced.contains(&restore::patch_id_of(patch_entry)) {
+            garbage.unreferenced_patches.push(patch_entry.clone());
+        }
+    }
+
+    for object in storage.list()? {
+        let file_name = object.name;
+        let patch_id = file_name.trim_end_matches("-reverse");
+        if is_patch_id(patch_id) {
+            if !patch_index.contains_key(patch_id) {
+                debug!("{} is not in the patch store", file_name);
+                garbage.dangling_files.push(file_name);
+            }
+        } else if file_name == "tmp_empty" || file_name.ends_with(".tmp") {
+            garbage.stale_files.push(file_name);
+        }
+    }
+    garbage.dangling_files.sort();
+    garbage.stale_files.sort();
+    Ok(garbage)
+}
+
+pub fn remove_garbage(
+    storage: &dyn StorageBackend,
+    patch_store: &[DiffEntry],
+    garbage: &Garbage,
+) -> Result<u64, FtmError> {
+    /* The patch store is rewritten before any file is deleted, so stopping halfway through only leaves more
+    dangling files for the next run. Returns how many bytes were freed.
+    */
+    let unreferenced: HashSet<String> = garbage
+        .unreferenced_patches
+        .iter()
+        .map(restore::patch_id_of)
+        .collect();
+    if !unreferenced.is_empty() {
+        let new_patch_store: Vec<DiffEntry> = patch_store
+            .iter()
+            .filter(|patch_entry| !unreferenced.contains(&restore::patch_id_of(patch_entry)))
+            .cloned()
+            .collect();
+        snapshot_manager::write_patch_store(&new_patch_store, storage)?;
+    }
+
+    let mut file_names: Vec<String> = garbage
+        .dangling_files
+        .iter()
+        .chain(garbage.stale_files.iter())
+        .cloned()
+        .collect();
+    for patch_id in unreferenced.iter() {
+        file_names.push(patch_id.clone());
+        file_names.push(patch_id.clone() + "-reverse");
+    }
+
+    let sizes = storage::sizes(storage)?;
+    let mut freed = 0;
+    for file_name in file_names.iter() {
+        if let Some(size) = sizes.get(file_name) {
+            debug!("Removing {}", storage.location(file_name));
+            storage.delete(file_name)?;
+            freed += size;
+        }
+    }
+    Ok(freed)
+}
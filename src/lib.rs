@@ -0,0 +1,178 @@
+// File Time Machine as a library. The ftm binary is a command line wrapper around Repository.
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+pub mod compare;
+pub mod compression;
+pub mod daemon;
+pub mod diffs;
+pub mod du;
+pub mod error;
+pub mod export;
+pub mod gc;
+pub mod metadata_manager;
+pub mod progress;
+pub mod prune;
+pub mod repository;
+pub mod restore;
+pub mod s3;
+pub mod schedule;
+pub mod search;
+pub mod serve;
+pub mod snapshot_manager;
+pub mod storage;
+pub mod verify;
+pub mod watch;
+
+pub use error::FtmError;
+pub use repository::Repository;
+pub use storage::StorageBackend;
+
+#[derive(Deserialize, Serialize, Hash, PartialEq, Eq, Debug, Clone)]
+
+pub struct DiffEntry {
+    // TODO: Depreceate in favor of SnapshotEntries?
+    pub date_created: String,
+    pub target_path: String,
+    pub ref_patch: String,
+}
+
+#[derive(PartialEq, Hash, Eq, Debug, Clone)]
+pub struct ModifiedList {
+    pub path: String,
+    pub exists: bool,
+    pub modified: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Hash, Eq)] // Derive Serialize for JSON serialization
+pub struct MetaFile {
+    pub date_modified: u64,
+    pub hash: String,
+    pub size: u64,
+    pub path: String,
+}
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SnapshotEntries {
+    pub date_created: String,
+    pub patch_ids: Vec<String>,
+    pub target_path: Vec<String>,
+    pub ref_patch_ids: Vec<String>,
+    pub modified: Vec<bool>,
+    #[serde(default)] // Snapshots taken before messages and tags existed have neither
+    pub message: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Config {
+    // This will grow with time
+    #[serde(default, skip_serializing_if = "String::is_empty")] // Picked with ftm --profile
+    pub name: String,
+    pub folder_path: String,
+    pub get_hashes: bool,
+    pub thread_count: u32,
+    pub brotli_compression_level: u32,
+    pub snapshot_mode: String,
+    pub its_my_fault_if_i_lose_data: bool,
+    #[serde(default, skip_serializing_if = "String::is_empty")] // Only used by ftm schedule
+    pub schedule: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    // Where .time is kept, folder_path/.time if empty
+    pub repository_path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")] // Patches and stores go here
+    pub s3: Option<S3Config>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct S3Config {
+    pub endpoint: String, // Like https://s3.amazonaws.com, or http://127.0.0.1:9000 for MinIO
+    pub bucket: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")] // Lets folders share a bucket
+    pub prefix: String,
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+impl Config {
+    pub fn new(folder_path: &str) -> Config {
+        // The same defaults ftm init uses
+        Config {
+            name: String::new(),
+            folder_path: folder_path.to_string(),
+            get_hashes: false,
+            thread_count: 0,
+            brotli_compression_level: 5,
+            snapshot_mode: "fastest".to_string(),
+            its_my_fault_if_i_lose_data: false,
+            schedule: String::new(),
+            repository_path: String::new(),
+            s3: None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), FtmError> {
+        if !Path::new(&self.folder_path).is_dir() {
+            return Err(FtmError::Config(format!(
+                "{} is not a folder!",
+                self.folder_path
+            )));
+        }
+        if self.snapshot_mode != "fastest" {
+            return Err(FtmError::Config(
+                "Only fastest snapshot mode is currently implemented!".to_string(),
+            ));
+        }
+        if !(1..=11).contains(&self.brotli_compression_level) {
+            return Err(FtmError::Config(format!(
+                "brotli_compression_level must be between 1 and 11, not {}!",
+                self.brotli_compression_level
+            )));
+        }
+        if !self.schedule.is_empty() {
+            schedule::Schedule::parse(&self.schedule)?;
+        }
+        if !self.repository_path.is_empty() {
+            let repository_path = Path::new(&self.repository_path);
+            if !repository_path.is_absolute() {
+                return Err(FtmError::Config(format!(
+                    "repository_path must be an absolute path, not {}!",
+                    self.repository_path
+                )));
+            }
+            if repository_path.is_file() {
+                return Err(FtmError::Config(format!(
+                    "{} is a file, not a folder!",
+                    self.repository_path
+                )));
+            }
+            if Path::new(&self.folder_path).starts_with(repository_path) {
+                return Err(FtmError::Config(format!(
+                    "repository_path can not contain the folder being snapshotted, {} is inside {}!",
+                    self.folder_path, self.repository_path
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn time_dir(&self) -> String {
+        // The folder holding metadata.json, and patches and stores unless s3 is set. It does not have to be inside folder_path.
+        if self.repository_path.is_empty() {
+            self.folder_path.trim_end_matches('/').to_string() + "/.time"
+        } else {
+            self.repository_path.trim_end_matches('/').to_string()
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        if self.name.is_empty() {
+            &self.folder_path
+        } else {
+            &self.name
+        }
+    }
+}
@@ -1,71 +1,77 @@
 // TODO: Optional exclude directories
 #![windows_subsystem = "windows"] // Prevents console from opening when on Windows.
-use chrono::DateTime;
 use directories::BaseDirs;
+use file_time_machine::{
+    daemon, du,
+    error::FtmError,
+    export,
+    progress::{self, ProgressSink},
+    restore::{self, FileVersion},
+    schedule, serve,
+    snapshot_manager::{self, VersionSize},
+    storage::{PATCH_STORE, SNAPSHOT_STORE},
+    watch, Config, Repository,
+};
 use gumdrop::Options;
 use inquire::Select;
 use log::{debug, warn};
-use serde::{Deserialize, Serialize};
+use similar::TextDiff;
 use std::{
     collections::HashSet,
     env,
     fs::{self, File},
-    hash::Hash,
     io::{Read, Write},
-    path::Path,
-    process,
-    sync::{Arc, Mutex},
-    thread,
+    path::{self, Path},
+    process, thread,
     time::Duration,
 };
-use xxhash_rust::xxh3::xxh3_64;
 // use std::time::Instant; // For debugging
 
-pub mod compression;
-pub mod diffs;
-pub mod metadata_manager;
-pub mod restore;
-
-#[derive(Deserialize, Serialize, Hash, PartialEq, Eq, Debug, Clone)]
-
-pub struct DiffEntry {
-    // TODO: Depreceate in favor of SnapshotEntries?
-    date_created: String,
-    target_path: String,
-    ref_patch: String,
+fn select_profile(configs: &[Config], profile: &str) -> Result<usize, String> {
+    // Without a profile, a config with a single entry uses it and a config with several has to be told which one
+    if configs.is_empty() {
+        return Err("The config file has no entries!".to_string());
+    }
+    if profile.is_empty() {
+        if configs.len() == 1 {
+            return Ok(0);
+        }
+        return Err(format!(
+            "The config file has {} entries, pick one with --profile. Names: {}",
+            configs.len(),
+            profile_names(configs)
+        ));
+    }
+    configs
+        .iter()
+        .position(|config| config.name == profile)
+        .ok_or_else(|| {
+            format!(
+                "No config entry is named {profile}. Names: {}",
+                profile_names(configs)
+            )
+        })
 }
 
-#[derive(PartialEq, Hash, Eq, Debug, Clone)]
-pub struct ModifiedList {
-    path: String,
-    exists: bool,
-    modified: bool,
+fn exit_on_error<T>(result: Result<T, FtmError>) -> T {
+    // For errors that already say what went wrong, like a corrupt store, instead of panicking
+    result.unwrap_or_else(|e| {
+        eprintln!("{e}");
+        process::exit(1);
+    })
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Hash, Eq)] // Derive Serialize for JSON serialization
-pub struct MetaFile {
-    date_modified: u64,
-    hash: String,
-    size: u64,
-    path: String,
-}
-#[derive(Debug, Deserialize, Serialize)]
-pub struct SnapshotEntries {
-    date_created: String,
-    patch_ids: Vec<String>,
-    target_path: Vec<String>,
-    ref_patch_ids: Vec<String>,
-    modified: Vec<bool>,
-}
-#[derive(Deserialize, Serialize, Debug, Clone)]
-struct Config {
-    // This will grow with time
-    folder_path: String,
-    get_hashes: bool,
-    thread_count: u32,
-    brotli_compression_level: u32,
-    snapshot_mode: String,
-    its_my_fault_if_i_lose_data: bool,
+fn profile_names(configs: &[Config]) -> String {
+    let names: Vec<&str> = configs
+        .iter()
+        .filter(|config| !config.name.is_empty())
+        .map(|config| config.name.as_str())
+        .collect();
+    if names.is_empty() {
+        "none, add a name field to the entries".to_string()
+    } else {
+        names.join(", ")
+    }
 }
 
 #[derive(Debug, Options)]
@@ -76,6 +82,13 @@ struct MyOptions {
     verbose: bool,
     #[options(help = "specify a specific config file")]
     config: String,
+    #[options(help = "only use the config entry with this name")]
+    profile: String,
+    #[options(
+        no_short,
+        help = "how to show snapshot and restore progress: bars, json or none"
+    )]
+    progress: String,
 
     // The `command` option will delegate option parsing to the command type,
     // starting at the first free argument.
@@ -94,18 +107,236 @@ enum Command {
     Snapshot(SnapshotOptions),
     #[options(help = "restore a snapshot")]
     Restore(RestoreOptions),
+    #[options(help = "list snapshots")]
+    List(ListOptions),
+    #[options(help = "show what changed between two snapshots, or a snapshot and the folder")]
+    Diff(DiffOptions),
+    #[options(help = "print a file as it was in a snapshot")]
+    Cat(CatOptions),
+    #[options(help = "show every version of a file")]
+    Log(LogOptions),
+    #[options(help = "show changes that the next snapshot would save")]
+    Status(StatusOptions),
+    #[options(help = "create a config and .time folder for a folder")]
+    Init(InitOptions),
+    #[options(help = "check that every snapshot can be restored")]
+    Verify(VerifyOptions),
+    #[options(help = "delete old snapshots using a retention policy")]
+    Prune(PruneOptions),
+    #[options(help = "find files in .time that no snapshot needs")]
+    Gc(GcOptions),
+    #[options(help = "write a snapshot to a tar or zip archive")]
+    Export(ExportOptions),
+    #[options(help = "browse every snapshot over HTTP and WebDAV")]
+    Serve(ServeOptions),
+    #[options(help = "answer JSON requests to list, snapshot, restore and check status")]
+    Daemon(DaemonOptions),
+    #[options(help = "search every version of every file for a regex")]
+    Grep(GrepOptions),
+    #[options(help = "show how much space patches take per snapshot and per file")]
+    Du(DuOptions),
+    #[options(help = "take snapshots automatically when files change")]
+    Watch(WatchOptions),
+    #[options(help = "take snapshots of every folder in the config on their schedules")]
+    Schedule(ScheduleOptions),
 }
 
 // Options accepted for the `snapshot` command
-#[derive(Debug, Options)] // TODO: Add options
-struct SnapshotOptions {}
+#[derive(Debug, Options)]
+struct SnapshotOptions {
+    #[options(help = "message to store with the snapshot")]
+    message: String,
+    #[options(help = "tag to store with the snapshot, can be given more than once")]
+    tag: Vec<String>,
+}
 
 // Options accepted for the `restore` command
 #[derive(Debug, Options)] // TODO: Add options (list snapshots, restore specific one)
 struct RestoreOptions {
-    #[options(help = "restore nth snapshot starting from most recent")]
+    #[options(help = "restore the nth snapshot, counting from 1 for the oldest like ftm list")]
     restore_index: u32,
+    #[options(help = "only restore paths matching this glob, can be given more than once")]
+    path: Vec<String>,
+    #[options(help = "restore into this empty directory instead of the snapshot folder")]
+    to: String,
+    #[options(no_short, help = "restore the most recent snapshot with this tag")]
+    tag: String,
 }
+
+// Options accepted for the `list` command
+#[derive(Debug, Options)]
+struct ListOptions {
+    #[options(help = "print the listing as JSON")]
+    json: bool,
+}
+
+// Options accepted for the `diff` command
+#[derive(Debug, Options)]
+struct DiffOptions {
+    #[options(
+        free,
+        help = "snapshot indexes to compare. With one index it is compared to the folder, with none the most recent snapshot is"
+    )]
+    snapshots: Vec<usize>,
+    #[options(help = "print a unified diff of text files")]
+    patch: bool,
+}
+
+// Options accepted for the `cat` command
+#[derive(Debug, Options)]
+struct CatOptions {
+    #[options(help = "nth snapshot to read the file from, defaults to the most recent")]
+    snapshot: usize,
+    #[options(help = "write the file here instead of to stdout")]
+    output: String,
+    #[options(free, help = "file to print, relative to the snapshot folder")]
+    path: String,
+}
+
+// Options accepted for the `log` command
+#[derive(Debug, Options)]
+struct LogOptions {
+    #[options(help = "print the version of the file from the nth snapshot")]
+    cat: usize,
+    #[options(help = "restore the version of the file from the nth snapshot")]
+    restore: usize,
+    #[options(
+        free,
+        help = "file to show the history of, relative to the snapshot folder"
+    )]
+    path: String,
+}
+
+// Options accepted for the `status` command
+#[derive(Debug, Options)]
+struct StatusOptions {}
+
+// Options accepted for the `init` command
+#[derive(Debug, Options)]
+struct InitOptions {
+    #[options(help = "folder to take snapshots of, defaults to the current directory")]
+    folder: String,
+    #[options(help = "keep .time here instead of inside the folder, like on another disk")]
+    repository: String,
+    #[options(
+        help = "snapshot mode, only fastest is currently implemented",
+        default = "fastest"
+    )]
+    mode: String,
+    #[options(help = "brotli compression level from 1 to 11", default = "5")]
+    compression_level: u32,
+    #[options(help = "threads to use, 0 picks one per CPU core")]
+    threads: u32,
+    #[options(no_short, help = "find modified files using hashes")]
+    hashes: bool,
+    #[options(help = "skip the data loss warning on every run")]
+    its_my_fault_if_i_lose_data: bool,
+    #[options(help = "take the initial snapshot right away")]
+    snapshot: bool,
+    #[options(no_short, help = "overwrite an existing config file")]
+    force: bool,
+}
+
+// Options accepted for the `verify` command
+#[derive(Debug, Options)]
+struct VerifyOptions {
+    #[options(
+        help = "rebuild every version of every file instead of only checking the patch files"
+    )]
+    deep: bool,
+}
+
+// Options accepted for the `prune` command
+#[derive(Debug, Options)]
+struct PruneOptions {
+    #[options(help = "keep the n most recent snapshots")]
+    keep_last: usize,
+    #[options(
+        help = "keep one snapshot per hour for a day, per day for a month and per week after that"
+    )]
+    time_machine: bool,
+    #[options(help = "only print what would be deleted")]
+    dry_run: bool,
+}
+
+// Options accepted for the `gc` command
+#[derive(Debug, Options)]
+struct GcOptions {
+    #[options(help = "remove what was found instead of only reporting it")]
+    apply: bool,
+}
+
+// Options accepted for the `export` command
+#[derive(Debug, Options)]
+struct ExportOptions {
+    #[options(help = "nth snapshot to export, defaults to the most recent")]
+    snapshot: usize,
+    #[options(help = "archive format: tar, tar.gz or zip", default = "tar")]
+    format: String,
+    #[options(help = "file to write the archive to, - for stdout")]
+    output: String,
+}
+
+// Options accepted for the `serve` command
+#[derive(Debug, Options)]
+struct ServeOptions {
+    #[options(help = "only allow reading, which is currently the only mode")]
+    read_only: bool,
+    #[options(help = "address to listen on", default = "127.0.0.1:8080")]
+    listen: String,
+}
+
+// Options accepted for the `daemon` command
+#[derive(Debug, Options)]
+struct DaemonOptions {
+    #[options(
+        help = "unix:/path/to.sock, or a localhost address like tcp:127.0.0.1:8081",
+        default = "tcp:127.0.0.1:8081"
+    )]
+    listen: String,
+}
+
+// Options accepted for the `grep` command
+#[derive(Debug, Options)]
+struct GrepOptions {
+    #[options(help = "only search paths matching this glob, can be given more than once")]
+    path: Vec<String>,
+    #[options(
+        help = "only search snapshots taken on or after this date, like 2025-01-31 or \"2025-01-31 14:00\""
+    )]
+    since: String,
+    #[options(free, help = "regex to search for")]
+    pattern: String,
+}
+
+// Options accepted for the `du` command
+#[derive(Debug, Options)]
+struct DuOptions {
+    #[options(help = "number of paths to show, largest first", default = "10")]
+    top: usize,
+    #[options(help = "estimate how many bytes removing the nth snapshot would free")]
+    if_removed: usize,
+}
+
+// Options accepted for the `watch` command
+#[derive(Debug, Options)]
+struct WatchOptions {
+    #[options(
+        help = "seconds without changes to wait before taking a snapshot",
+        default = "30"
+    )]
+    quiet_seconds: u64,
+    #[options(
+        help = "minutes a change may wait for a snapshot while files keep changing",
+        default = "10"
+    )]
+    max_minutes: u64,
+}
+
+// Options accepted for the `schedule` command
+#[derive(Debug, Options)]
+struct ScheduleOptions {}
+
 fn main() {
     let mut want_restore = false;
     let mut skip_snap = false;
@@ -122,8 +353,31 @@ fn main() {
 
     env_logger::init();
 
+    let progress_sink: Box<dyn ProgressSink> = match opts.progress.as_str() {
+        "" | "bars" => Box::new(progress::Bars::default()),
+        "json" => Box::new(progress::Ndjson),
+        "none" => Box::new(progress::Silent),
+        other => {
+            println!("--progress must be bars, json or none, not {other}.");
+            process::exit(1);
+        }
+    };
+
+    // Machine readable output must be the only thing on stdout
+    let quiet = opts.progress == "json"
+        || match opts.command {
+            Some(Command::List(ref list_options)) => list_options.json,
+            Some(Command::Cat(ref cat_options)) => cat_options.output.is_empty(),
+            Some(Command::Log(ref log_options)) => log_options.cat != 0,
+            Some(Command::Export(ref export_options)) => export_options.output == "-",
+            Some(Command::Grep(_)) => true,
+            _ => false,
+        };
+
     if !opts.config.is_empty() {
-        println!("Using specific config file {}!", opts.config);
+        if !quiet {
+            println!("Using specific config file {}!", opts.config);
+        }
         conf_dir = opts.config;
         man_conf = true;
     } else {
@@ -141,10 +395,34 @@ fn main() {
     }
 
     if let Some(Command::Snapshot(ref _snapshot_options)) = opts.command {
-        println!("Taking snapshot!");
+        if !quiet {
+            println!("Taking snapshot!");
+        }
     } else if let Some(Command::Restore(ref _restore_options)) = opts.command {
-        println!("Restoring!");
+        if !quiet {
+            println!("Restoring!");
+        }
         want_restore = true;
+    } else if matches!(
+        opts.command,
+        Some(Command::List(_))
+            | Some(Command::Diff(_))
+            | Some(Command::Cat(_))
+            | Some(Command::Log(_))
+            | Some(Command::Status(_))
+            | Some(Command::Init(_))
+            | Some(Command::Verify(_))
+            | Some(Command::Prune(_))
+            | Some(Command::Gc(_))
+            | Some(Command::Export(_))
+            | Some(Command::Serve(_))
+            | Some(Command::Daemon(_))
+            | Some(Command::Grep(_))
+            | Some(Command::Du(_))
+            | Some(Command::Watch(_))
+            | Some(Command::Schedule(_))
+    ) {
+        skip_snap = true;
     } else {
         println!("No valid option was provided, taking a snapshot!");
     }
@@ -162,6 +440,16 @@ fn main() {
     //     );
     // }
     // println!("{conf_dir}");
+    if let Some(Command::Init(ref init_options)) = opts.command {
+        // There is no config to load yet, init is the one writing it
+        let conf_path = if man_conf {
+            conf_dir
+        } else {
+            conf_dir + "/config.json"
+        };
+        init_repository(&conf_path, &opts.profile, init_options, &*progress_sink);
+        return;
+    }
     if !Path::new(&conf_dir).exists() {
         if man_conf {
             panic!("Could not locate config file {}!", conf_dir);
@@ -171,12 +459,11 @@ fn main() {
         );
         println!("Creating .file-time-machine");
     }
-    let conf_path;
-    if man_conf {
-        conf_path = conf_dir;
+    let conf_path = if man_conf {
+        conf_dir
     } else {
-        conf_path = conf_dir + "/config.json";
-    }
+        conf_dir + "/config.json"
+    };
     let mut config_file = File::open(Path::new(&conf_path)).expect("Could not open config file! Create one at $HOME/.file-time-machine/config.json as specified in documentation.");
 
     let mut config_file_contents = String::new();
@@ -188,55 +475,141 @@ fn main() {
     let config_holder: Vec<Config> = serde_json::from_str(&config_file_contents)
         .expect("The config file was not formatted properly and could not be read.");
 
-    let mut folder_path = config_holder[0].folder_path.clone(); // Shut up, I am tired
-    let hash_enabled = config_holder[0].get_hashes;
-    let mut thread_count = config_holder[0].thread_count;
-    let compression_level = config_holder[0].brotli_compression_level;
-    let snapshot_mode = config_holder[0].snapshot_mode.clone();
-    let supress_warn = config_holder[0].its_my_fault_if_i_lose_data;
-
-    if snapshot_mode != "fastest" {
-        println!("Only fastest snapshot mode is currently implemented!");
-        process::exit(1);
+    for (position, config) in config_holder.iter().enumerate() {
+        if !config.name.is_empty()
+            && config_holder[..position]
+                .iter()
+                .any(|other| other.name == config.name)
+        {
+            println!(
+                "The config file at {conf_path} has more than one entry named {}.",
+                config.name
+            );
+            process::exit(1);
+        }
+    }
+    // Snapshots are taken of every entry unless a profile is picked, everything else works on a single folder
+    let every_entry = opts.profile.is_empty()
+        && !config_holder.is_empty()
+        && matches!(
+            opts.command,
+            None | Some(Command::Snapshot(_)) | Some(Command::Schedule(_))
+        );
+    let targets: Vec<Config> = if every_entry {
+        config_holder.clone()
+    } else {
+        match select_profile(&config_holder, &opts.profile) {
+            Ok(position) => vec![config_holder[position].clone()],
+            Err(e) => {
+                println!("{e}");
+                process::exit(1);
+            }
+        }
+    };
+    let mut repositories = Vec::new();
+    for config in targets.iter() {
+        match Repository::open(config.clone()) {
+            Ok(repository) => repositories.push(repository),
+            Err(e) => {
+                // Opening also reaches the storage backend, so this is not always a mistake in the config
+                if targets.len() > 1 {
+                    println!(
+                        "Could not open the entry {} in the config file at {conf_path}: {e}",
+                        config.label()
+                    );
+                } else {
+                    println!("Could not open the repository from {conf_path}: {e}");
+                }
+                process::exit(1);
+            }
+        }
     }
-    debug!("Snapshot mode is {}", snapshot_mode);
+
+    let repository = &repositories[0];
+    let folder_path = repository.folder_path().to_string(); // Shut up, I am tired
+    let time_dir = repository.time_dir();
+    let storage = repository.storage();
+    let supress_warn = targets
+        .iter()
+        .all(|config| config.its_my_fault_if_i_lose_data);
+    debug!("Snapshot mode is {}", repository.config().snapshot_mode);
 
     if !supress_warn {
         warn!("\nWARNING WARNING WARNING\nThis program is NOT production ready! You probably WILL lose data using it!\nSet its_my_fault_if_i_lose_data to true to suppress this warning.\n");
         thread::sleep(Duration::from_secs(3));
     }
-
-    folder_path = folder_path.trim_end_matches('/').to_string();
-    let create_reverse; // Disabled only on first run to reduce disk usage
-
-    if thread_count == 0 {
-        thread_count = num_cpus::get() as u32;
-        debug!("thread_count automatically set to {}", thread_count);
+    if let Some(Command::List(ref list_options)) = opts.command {
+        list_snapshots(repository, list_options.json);
+    }
+    if let Some(Command::Diff(ref diff_options)) = opts.command {
+        diff_snapshots(repository, diff_options);
+    }
+    if let Some(Command::Cat(ref cat_options)) = opts.command {
+        cat_file(repository, cat_options);
+    }
+    if let Some(Command::Log(ref log_options)) = opts.command {
+        log_file(repository, log_options);
+    }
+    if let Some(Command::Status(_)) = opts.command {
+        show_status(repository);
+    }
+    if let Some(Command::Verify(ref verify_options)) = opts.command {
+        verify_repository(repository, verify_options.deep, &*progress_sink);
+    }
+    if let Some(Command::Prune(ref prune_options)) = opts.command {
+        prune_repository(repository, prune_options);
+    }
+    if let Some(Command::Gc(ref gc_options)) = opts.command {
+        collect_garbage(repository, gc_options.apply);
+    }
+    if let Some(Command::Export(ref export_options)) = opts.command {
+        export_archive(repository, export_options);
+    }
+    if let Some(Command::Serve(ref serve_options)) = opts.command {
+        if !serve_options.read_only {
+            println!("Only read-only serving is supported, run again with --read-only.");
+            process::exit(1);
+        }
+        exit_on_error(serve::serve(
+            &folder_path,
+            &time_dir,
+            repository.shared_storage(),
+            &serve_options.listen,
+        ));
+    }
+    if let Some(Command::Daemon(ref daemon_options)) = opts.command {
+        exit_on_error(daemon::run(&daemon_options.listen, repository.clone()));
+    }
+    if let Some(Command::Grep(ref grep_options)) = opts.command {
+        grep_history(repository, grep_options);
+    }
+    if let Some(Command::Du(ref du_options)) = opts.command {
+        show_usage(repository, du_options);
+    }
+    if let Some(Command::Watch(ref watch_options)) = opts.command {
+        if watch_options.quiet_seconds == 0 || watch_options.max_minutes == 0 {
+            println!("--quiet-seconds and --max-minutes have to be at least 1.");
+            process::exit(1);
+        }
+        exit_on_error(watch::run(
+            repository,
+            Duration::from_secs(watch_options.quiet_seconds),
+            Duration::from_secs(watch_options.max_minutes * 60),
+            &*progress_sink,
+        ));
+    }
+    if let Some(Command::Schedule(_)) = opts.command {
+        let configs = repositories
+            .iter()
+            .map(|repository| repository.config().clone())
+            .collect();
+        exit_on_error(schedule::run(configs));
     }
     if want_restore {
         skip_snap = true;
-        let snapshot_store_file = folder_path.clone() + "/.time/snapshots.json";
-        let snapshot_store: Vec<SnapshotEntries>;
         let mut change_count = 0;
         let mut options = Vec::new();
 
-        // println!("{}", snapshot_store_file);
-        if !Path::new(&snapshot_store_file).exists() {
-            panic!("Did not find a valid snapshot store, have you created any snapshots yet?");
-        }
-
-        let mut file = File::open(Path::new(&snapshot_store_file))
-            .unwrap_or_else(|_| panic!("Could not open {}!", snapshot_store_file));
-
-        let mut file_contents = String::new();
-        file.read_to_string(&mut file_contents)
-            .unwrap_or_else(|_| panic!("Unable to read file {}!", snapshot_store_file));
-        if !file_contents.is_empty() {
-            snapshot_store =
-                serde_json::from_str(&file_contents).expect("Snapshot store is corrupt!");
-        } else {
-            panic!("Snapshot store exists, but is empty! No snapshots available.");
-        }
         /*struct Point {
             x: f64,
             y: f64,
@@ -255,498 +628,767 @@ fn main() {
                 _ => println!("Something else"),
             }
         } */
-        let selected_item;
-        if let Some(Command::Restore(ref restore_options)) = opts.command {
-            // println!("{}", snapshot_store.len());
-            if snapshot_store.len() >= restore_options.restore_index.try_into().unwrap()
-                && 0 < restore_options.restore_index.try_into().unwrap()
-            {
-                selected_item = DateTime::parse_from_str(
-                    &snapshot_store[restore_options.restore_index as usize - 1].date_created,
-                    "%Y-%m-%d %H:%M:%S%.9f %z",
-                )
-                .unwrap();
-            } else {
-                if restore_options.restore_index != 0 {
-                    // Needed because afaik Gumdrop sets it to 0 if it wasn't passed. This is not desired behaviour.
-                    println!(
-                        "{} is an invalid snapshot. Entering interactive.",
-                        restore_options.restore_index
-                    );
-                }
-                for snapshot in &snapshot_store {
-                    for _change in snapshot.patch_ids.clone() {
-                        change_count += 1;
+        let Some(Command::Restore(ref restore_options)) = opts.command else {
+            panic!("Could not parse a valid command.");
+        };
+        // Gumdrop sets restore_index to 0 if it wasn't passed, which asks for the snapshot instead
+        let selected_index = if !restore_options.tag.is_empty() {
+            exit_on_error(repository.tagged_snapshot(&restore_options.tag))
+        } else {
+            let wanted = (restore_options.restore_index as usize).checked_sub(1);
+            match repository.resolve_snapshot(wanted) {
+                Ok(selected_index) if wanted.is_some() => selected_index,
+                Ok(_) | Err(FtmError::InvalidSnapshot { .. }) => {
+                    if restore_options.restore_index != 0 {
+                        println!(
+                            "{} is an invalid snapshot. Entering interactive.",
+                            restore_options.restore_index
+                        );
                     }
-                    let date_entry = DateTime::parse_from_str(
-                        &snapshot.date_created,
-                        "%Y-%m-%d %H:%M:%S%.9f %z",
-                    )
-                    .unwrap();
-                    let formatted_date = date_entry.format("%Y-%m-%d %H:%M:%S %z").to_string();
-                    // debug!("formatted_date is {}", formatted_date);
-                    options.push(formatted_date + " files changed: " + &change_count.to_string());
-                    change_count = 0;
+                    let snapshot_store = exit_on_error(repository.snapshots());
+                    for snapshot in &snapshot_store {
+                        for _change in snapshot.patch_ids.clone() {
+                            change_count += 1;
+                        }
+                        let formatted_date = exit_on_error(snapshot_manager::pretty_date(
+                            &snapshot.date_created,
+                            &storage.location(SNAPSHOT_STORE),
+                        ));
+                        // debug!("formatted_date is {}", formatted_date);
+                        options.push(
+                            formatted_date
+                                + " files changed: "
+                                + &change_count.to_string()
+                                + &snapshot_manager::describe_label(snapshot),
+                        );
+                        change_count = 0;
+                    }
+                    let selection =
+                        Select::new("Select a snapshot to restore:", options).raw_prompt();
+
+                    let selected_index = match selection {
+                        Ok(choice) => choice.index,
+                        Err(_) => panic!("There was an issue, please try again."),
+                    };
+                    debug!("{}", snapshot_store[selected_index].date_created);
+                    selected_index
                 }
-                let selection = Select::new("Select a snapshot to restore:", options).prompt();
+                Err(e) => exit_on_error(Err(e)),
+            }
+        };
 
-                let selected_item_pretty: String = match selection {
-                    Ok(choice) => choice,
-                    Err(_) => panic!("There was an issue, please try again."),
-                };
-                // Extract true option from human readable format
-                let selected_item_str = selected_item_pretty[0..25].to_string();
-                debug!("{selected_item_str}");
-                selected_item =
-                    DateTime::parse_from_str(&selected_item_str, "%Y-%m-%d %H:%M:%S %z")
-                        .expect("Could not correctly parse date in activeSnapshot, is it corrupt?");
+        let restore_to = restore_options.to.trim_end_matches('/').to_string();
+        let result = repository.restore(
+            selected_index,
+            &restore_options.path,
+            if restore_to.is_empty() {
+                None
+            } else {
+                Some(&restore_to)
+            },
+            &*progress_sink,
+        );
+        // With --progress json, the result was already sent as a finished or failed event
+        if let Err(e) = result {
+            if !quiet {
+                println!("{e}");
             }
+            process::exit(1);
+        }
+        if quiet {
+            return;
+        }
+        if !restore_to.is_empty() {
+            println!(
+                "Finished restoring snapshot {} into {}.",
+                selected_index + 1,
+                restore_to
+            );
+        } else if !restore_options.path.is_empty() {
+            // Partial restores leave activeSnapshot alone, since the rest of the folder is still where it was.
+            println!(
+                "Finished restoring the selected paths from snapshot {}. Everything else was left untouched.",
+                selected_index + 1
+            );
         } else {
-            panic!("Could not parse a valid command.");
+            println!("Finished restoring. You can safely make changes, but they will not be saved unless a new snapshot is created.");
+        }
+    }
+
+    if !skip_snap {
+        let (message, tags) = match opts.command {
+            Some(Command::Snapshot(ref snapshot_options)) => (
+                snapshot_options.message.clone(),
+                snapshot_options.tag.clone(),
+            ),
+            _ => (String::new(), Vec::new()),
+        };
+        let mut written = false;
+        let mut failed = false;
+        for repository in repositories.iter() {
+            if repositories.len() > 1 && !quiet {
+                println!("\nSnapshotting {}", repository.config().label());
+            }
+            // One folder failing should not stop the others from being snapshotted
+            match repository.snapshot(&message, &tags, &*progress_sink) {
+                Ok(snapshot) => written |= snapshot.is_some(),
+                Err(e) => {
+                    if !quiet {
+                        println!("Could not snapshot {}: {e}", repository.config().label());
+                    }
+                    failed = true;
+                }
+            }
+        }
+        if failed || !written {
+            process::exit(1);
         }
+    }
+}
 
-        /*
-        We have a entry that we want to restore, if it is in the past:
-        In fastest mode, restore directly, don't restore intermediates
+fn init_repository(
+    conf_path: &str,
+    profile: &str,
+    init_options: &InitOptions,
+    progress_sink: &dyn ProgressSink,
+) {
+    /* With a profile, the entry is added to the existing config instead of replacing it, and only an entry with
+    the same name needs --force to be overwritten.
+    */
+    let mut configs: Vec<Config> = Vec::new();
+    if Path::new(conf_path).exists() {
+        if !profile.is_empty() {
+            configs = serde_json::from_str(
+                &fs::read_to_string(conf_path)
+                    .unwrap_or_else(|_| panic!("Could not read the config file at {conf_path}")),
+            )
+            .expect("The config file was not formatted properly and could not be read.");
+        }
+        let replaces_entry =
+            profile.is_empty() || configs.iter().any(|config| config.name == profile);
+        if replaces_entry && !init_options.force {
+            if profile.is_empty() {
+                println!(
+                    "A config file already exists at {conf_path}! Pass --force to overwrite it."
+                );
+            } else {
+                println!("{conf_path} already has an entry named {profile}! Pass --force to overwrite it.");
+            }
+            process::exit(1);
+        }
+        configs.retain(|config| config.name != profile);
+    }
+    let folder = if init_options.folder.is_empty() {
+        "."
+    } else {
+        &init_options.folder
+    };
+    // Stored paths are built from folder_path, so make it absolute to keep them valid from anywhere
+    let folder_path = fs::canonicalize(folder)
+        .unwrap_or_else(|_| panic!("Could not find the folder {folder}!"))
+        .to_str()
+        .expect("The folder path is not valid UTF-8!")
+        .to_string();
+    // The repository may not exist yet, so it can only be made absolute, not canonicalized
+    let repository_path = if init_options.repository.is_empty() {
+        String::new()
+    } else {
+        path::absolute(&init_options.repository)
+            .unwrap_or_else(|_| panic!("Could not find the folder {}!", init_options.repository))
+            .to_str()
+            .expect("The repository path is not valid UTF-8!")
+            .to_string()
+    };
 
-        if it is in the future:
-        Restore up until we restore the proper patch.
-         */
+    let config = Config {
+        name: profile.to_string(),
+        folder_path: folder_path.clone(),
+        get_hashes: init_options.hashes,
+        thread_count: init_options.threads,
+        brotli_compression_level: init_options.compression_level,
+        snapshot_mode: init_options.mode.clone(),
+        its_my_fault_if_i_lose_data: init_options.its_my_fault_if_i_lose_data,
+        schedule: String::new(),
+        repository_path,
+        s3: None,
+    };
+    let repository = Repository::init(config.clone()).unwrap_or_else(|e| {
+        println!("Could not create a config: {e}");
+        process::exit(1);
+    });
 
-        let active_snapshot_path = folder_path.clone() + "/.time/activeSnapshot";
+    if let Some(parent) = Path::new(conf_path).parent() {
+        if !parent.as_os_str().is_empty() && !parent.exists() {
+            fs::create_dir_all(parent)
+                .unwrap_or_else(|_| panic!("Could not create {}!", parent.display()));
+        }
+    }
+    configs.push(config);
+    let json = serde_json::to_string_pretty(&configs).expect("Unable to serialize the config!");
+    fs::write(conf_path, json)
+        .unwrap_or_else(|_| panic!("Unable to write the config file at {conf_path}"));
+    println!("Wrote config to {conf_path}");
 
-        if !Path::new(&active_snapshot_path).exists() {
-            debug!("No activeSnapshot found, assuming target has to be in past.");
+    if init_options.snapshot {
+        if let Err(e) = repository.snapshot("", &[], progress_sink) {
+            println!("Could not take the initial snapshot: {e}");
+            process::exit(1);
+        }
+    } else {
+        if profile.is_empty() {
+            println!("Run ftm -c {conf_path} snapshot to take the initial snapshot.");
+        } else {
+            println!(
+                "Run ftm -c {conf_path} --profile {profile} snapshot to take the initial snapshot."
+            );
+        }
+    }
+}
 
-            // In fastest, restore_snapshot_until will NOT iterate. In this case, the name is misleading.
+fn list_snapshots(repository: &Repository, json: bool) {
+    let snapshot_store = repository.snapshots().unwrap_or_else(|e| {
+        println!("Could not read the snapshot store: {e}");
+        process::exit(1);
+    });
+    let summaries = snapshot_manager::summarize_snapshots(&snapshot_store);
 
-            restore::restore_snapshot_until(
-                snapshot_store,
-                &folder_path,
-                &selected_item,
-                true,
-                &snapshot_mode,
+    if json {
+        let json =
+            serde_json::to_string_pretty(&summaries).expect("Unable to serialize snapshot list!");
+        println!("{json}");
+    } else if summaries.is_empty() {
+        println!("No snapshots have been created yet.");
+    } else {
+        for summary in summaries {
+            println!(
+                "{:>4}  {}  added: {} modified: {} removed: {} unchanged: {}{}",
+                summary.index,
+                exit_on_error(snapshot_manager::pretty_date(
+                    &summary.date_created,
+                    &repository.storage().location(SNAPSHOT_STORE)
+                )),
+                summary.added,
+                summary.modified,
+                summary.removed,
+                summary.unchanged,
+                snapshot_manager::describe_label(&snapshot_store[summary.index - 1])
             );
+        }
+    }
+}
 
-            let mut active_snapshot = File::create(Path::new(&active_snapshot_path))
-                .unwrap_or_else(|_| {
-                    panic!("Could not create {active_snapshot_path}, do I have write permission?")
-                });
-            active_snapshot
-                .write_all(selected_item.to_string().as_bytes())
-                .unwrap_or_else(|_| {
-                    panic!("Unable to write to active_snapshot file at {active_snapshot_path}")
-                });
-        } else {
-            let mut file = File::open(Path::new(&(folder_path.clone() + "/.time/activeSnapshot")))
-                .unwrap_or_else(|_| {
-                    panic!(
-                        "Could not read {}!",
-                        folder_path.clone() + "/.time/activeSnapshot"
-                    )
-                });
-
-            let mut file_contents = String::new();
-            file.read_to_string(&mut file_contents).unwrap_or_else(|_| {
-                panic!(
-                    "Could not read from {}! Do I have correct permissions?",
-                    folder_path.clone() + "/.time/activeSnapshot"
-                )
+fn diff_snapshots(repository: &Repository, diff_options: &DiffOptions) {
+    let folder_path = repository.folder_path();
+    if diff_options.snapshots.contains(&0) {
+        println!("0 is an invalid snapshot, snapshots are numbered from 1.");
+        process::exit(1);
+    }
+    // None means the folder itself
+    let (from_index, to_index) = match diff_options.snapshots.as_slice() {
+        [] => (None, None),
+        [from] => (Some(from - 1), None),
+        [from, to] => (Some(from - 1), Some(to - 1)),
+        _ => {
+            println!("diff takes at most two snapshots!");
+            process::exit(1);
+        }
+    };
+    let from_index = exit_on_error(repository.resolve_snapshot(from_index));
+    let changes = exit_on_error(repository.diff(from_index, to_index));
+
+    for (path, kind) in changes.iter() {
+        println!(
+            "{:<9} {}",
+            kind.to_string(),
+            snapshot_manager::relative_path(folder_path, path)
+        );
+    }
+
+    if diff_options.patch {
+        let changed_paths: Vec<String> = changes
+            .iter()
+            .filter(|(_, kind)| **kind != snapshot_manager::ChangeKind::Unchanged)
+            .map(|(path, _)| path.clone())
+            .collect();
+        let result =
+            repository.diff_contents(from_index, to_index, &changed_paths, |path, old, new| {
+                let (old, new) = match (old, new) {
+                    (FileVersion::File(old), FileVersion::File(new)) => (old, new),
+                    (FileVersion::File(old), _) => (old, Vec::new()),
+                    (_, FileVersion::File(new)) => (Vec::new(), new),
+                    _ => return, // Directory
+                };
+                let relative = snapshot_manager::relative_path(folder_path, path);
+
+                match (std::str::from_utf8(&old), std::str::from_utf8(&new)) {
+                    (Ok(old_text), Ok(new_text)) if !old.contains(&0) && !new.contains(&0) => {
+                        let text_diff = TextDiff::from_lines(old_text, new_text);
+                        print!(
+                            "{}",
+                            text_diff
+                                .unified_diff()
+                                .header(&format!("a/{relative}"), &format!("b/{relative}"))
+                        );
+                    }
+                    _ => println!("Binary files a/{relative} and b/{relative} differ"),
+                }
             });
+        exit_on_error(result);
+    }
+}
 
-            let active_snapshot_date_stupid = // Please fix me this is stupid
-                DateTime::parse_from_str(&file_contents, "%Y-%m-%d %H:%M:%S%.9f %z")
-                    .unwrap()
-                    .format("%Y-%m-%d %H:%M:%S %z")
-                    .to_string();
-            let active_snapshot_date =
-                DateTime::parse_from_str(&active_snapshot_date_stupid, "%Y-%m-%d %H:%M:%S %z")
-                    .unwrap();
-
-            if selected_item > active_snapshot_date {
-                debug!("Snapshot is in future!");
-                restore::restore_snapshot_until(
-                    snapshot_store,
-                    &folder_path,
-                    &selected_item,
-                    false,
-                    &snapshot_mode,
-                );
-                fs::remove_file(&active_snapshot_path).unwrap_or_else(|_| {
-                    panic!(
-                        "Could not remove {}, it needs to be writeable!",
-                        active_snapshot_path
-                    )
-                });
-                let mut active_snapshot = File::create(Path::new(&active_snapshot_path))
-                    .unwrap_or_else(|_| {
-                        panic!(
-                            "Could not create {active_snapshot_path}, do I have write permission?"
-                        )
-                    });
-                active_snapshot
-                    .write_all(selected_item.to_string().as_bytes())
-                    .unwrap_or_else(|_| {
-                        panic!("Unable to write to activeSnapshot file at {active_snapshot_path}")
-                    });
-            } else if selected_item < active_snapshot_date {
-                debug!("Snapshot is in past!");
-                restore::restore_snapshot_until(
-                    snapshot_store,
-                    &folder_path,
-                    &selected_item,
-                    true,
-                    &snapshot_mode,
-                );
-                fs::remove_file(&active_snapshot_path).unwrap_or_else(|_| {
-                    panic!(
-                        "Could not remove {}, it needs to be writeable!",
-                        active_snapshot_path
-                    )
-                });
-                let mut active_snapshot = File::create(Path::new(&active_snapshot_path))
-                    .unwrap_or_else(|_| {
-                        panic!(
-                            "Could not create {active_snapshot_path}, do I have write permission?"
-                        )
-                    });
-                active_snapshot
-                    .write_all(selected_item.to_string().as_bytes())
-                    .unwrap_or_else(|_| {
-                        panic!("Unable to write to activeSnapshot file at {active_snapshot_path}")
-                    });
-            } else {
-                println!(
-                    "The snapshot you selected is already the active snapshot! Nothing to do."
-                );
+fn cat_file(repository: &Repository, cat_options: &CatOptions) {
+    // Messages go to stderr, stdout may be the file itself
+    if cat_options.path.is_empty() {
+        eprintln!("No file was given! Usage: ftm cat --snapshot N path/to/file");
+        process::exit(1);
+    }
+    // Gumdrop sets snapshot to 0 if it wasn't passed, which picks the most recent one
+    let snapshot_index =
+        exit_on_error(repository.resolve_snapshot(cat_options.snapshot.checked_sub(1)));
+
+    let contents = match exit_on_error(repository.read_file(snapshot_index, &cat_options.path)) {
+        FileVersion::File(contents) => contents,
+        FileVersion::Directory => {
+            eprintln!(
+                "{} is a directory in snapshot {}!",
+                cat_options.path,
+                snapshot_index + 1
+            );
+            process::exit(1);
+        }
+        FileVersion::Missing => {
+            eprintln!(
+                "{} does not exist in snapshot {}!",
+                cat_options.path,
+                snapshot_index + 1
+            );
+            process::exit(1);
+        }
+    };
+
+    if cat_options.output.is_empty() {
+        std::io::stdout()
+            .write_all(&contents)
+            .expect("Unable to write to stdout!");
+    } else {
+        fs::write(&cat_options.output, &contents)
+            .unwrap_or_else(|_| panic!("Unable to write to {}!", cat_options.output));
+        println!(
+            "Wrote {} as it was in snapshot {} to {}",
+            cat_options.path,
+            snapshot_index + 1,
+            cat_options.output
+        );
+    }
+}
+
+fn log_file(repository: &Repository, log_options: &LogOptions) {
+    if log_options.path.is_empty() {
+        println!("No file was given! Usage: ftm log path/to/file");
+        process::exit(1);
+    }
+    if log_options.cat != 0 {
+        cat_file(
+            repository,
+            &CatOptions {
+                snapshot: log_options.cat,
+                output: String::new(),
+                path: log_options.path.clone(),
+            },
+        );
+        return;
+    }
+    if log_options.restore != 0 {
+        repository
+            .restore_file(log_options.restore - 1, &log_options.path)
+            .unwrap_or_else(|e| {
+                println!("Could not restore {}: {e}", log_options.path);
                 process::exit(1);
+            });
+        println!(
+            "Restored {} as it was in snapshot {}.",
+            log_options.path, log_options.restore
+        );
+        return;
+    }
+
+    let history = exit_on_error(repository.log(&log_options.path));
+    for entry in history.iter() {
+        let change = match entry.change {
+            snapshot_manager::ChangeKind::Added => "created",
+            snapshot_manager::ChangeKind::Modified => "modified",
+            snapshot_manager::ChangeKind::Removed => "removed",
+            snapshot_manager::ChangeKind::Unchanged => "unchanged",
+        };
+        let size = match entry.size {
+            VersionSize::Bytes(bytes) => bytes.to_string() + " bytes",
+            VersionSize::Directory => "directory".to_string(),
+            VersionSize::Missing => "-".to_string(),
+            VersionSize::Unrecoverable => "unrecoverable".to_string(),
+        };
+        println!(
+            "{:>4}  {}  {:<8}  {}",
+            entry.snapshot_index + 1,
+            exit_on_error(snapshot_manager::pretty_date(
+                &entry.date_created,
+                &repository.storage().location(SNAPSHOT_STORE)
+            )),
+            change,
+            size
+        );
+    }
+
+    if history.is_empty() {
+        println!("{} was never part of a snapshot.", log_options.path);
+        process::exit(1);
+    }
+    println!("Use --cat N to print or --restore N to restore the version from snapshot N.");
+}
+
+fn show_status(repository: &Repository) {
+    /* Same change detection as taking a snapshot, but nothing is written. Exits with 0 if there is nothing to
+    snapshot, 1 if there are pending changes and 2 if the folder could not be checked, so scripts can check it.
+    */
+    let folder_path = repository.folder_path();
+    if !Path::new(&(repository.time_dir() + "/metadata.json")).exists() {
+        println!("No snapshots have been taken yet, every file is new.");
+    }
+    let changes = repository.status().unwrap_or_else(|e| {
+        println!("Could not check for changes: {e}");
+        process::exit(2);
+    });
+
+    if changes.is_empty() {
+        println!("No files changed, nothing to snapshot.");
+        process::exit(0);
+    }
+    for (path, change) in changes.iter() {
+        println!(
+            "{:<9} {}",
+            change,
+            snapshot_manager::relative_path(folder_path, path)
+        );
+    }
+    process::exit(1);
+}
+
+fn verify_repository(repository: &Repository, deep: bool, progress_sink: &dyn ProgressSink) {
+    let problems = exit_on_error(repository.verify(deep, progress_sink));
+
+    if problems.is_empty() {
+        println!("No problems found.");
+        return;
+    }
+    let mut unrecoverable = HashSet::new();
+    for problem in problems.iter() {
+        let relative = snapshot_manager::relative_path(repository.folder_path(), &problem.path);
+        match problem.snapshot {
+            Some(index) => {
+                unrecoverable.insert(index);
+                println!("snapshot {}: {}: {}", index + 1, relative, problem.reason);
             }
+            None => println!("patch store: {}: {}", relative, problem.reason),
         }
-        println!("Finished restoring. You can safely make changes, but they will not be saved unless a new snapshot is created.");
     }
+    let mut unrecoverable: Vec<usize> = unrecoverable.into_iter().map(|index| index + 1).collect();
+    unrecoverable.sort();
+    println!(
+        "Found {} problems. Unrecoverable snapshots: {:?}",
+        problems.len(),
+        unrecoverable
+    );
+    process::exit(1);
+}
 
-    if !skip_snap {
-        let mut initial_run = false;
-        debug!("take snapshot");
-        if !Path::new(&(folder_path.clone() + "/.time/metadata.json")).exists() {
-            debug!("{folder_path}/.time/metadata.json");
-            if !Path::new(&(folder_path.clone() + "/.time")).exists() {
-                fs::create_dir(folder_path.clone() + "/.time").unwrap_or_else(|_| {
-                    panic!(
-                        "Unable to create a .time folder at {}!",
-                        folder_path.clone() + "/.time"
-                    )
-                });
-            }
-            File::create(Path::new(&(folder_path.clone() + "/.time/tmp_empty"))).unwrap_or_else(
-                |_| {
-                    panic!(
-                        "Unable to create a temporary empty file at {}!",
-                        folder_path.clone() + "/.time/tmp_empty"
-                    )
+fn prune_repository(repository: &Repository, prune_options: &PruneOptions) {
+    if prune_options.keep_last == 0 && !prune_options.time_machine {
+        println!("No retention policy given, use --keep-last or --time-machine.");
+        process::exit(1);
+    }
+    let keep = exit_on_error(
+        repository.prune_selection(prune_options.keep_last, prune_options.time_machine),
+    );
+    if !keep.contains(&false) {
+        println!("Every snapshot is kept by the retention policy, nothing to prune.");
+        return;
+    }
+    let snapshot_store = exit_on_error(repository.snapshots());
+    for (index, (entry, kept)) in snapshot_store.iter().zip(keep.iter()).enumerate() {
+        if !kept {
+            println!(
+                "{} snapshot {}: {}{}",
+                if prune_options.dry_run {
+                    "Would remove"
+                } else {
+                    "Removing"
                 },
+                index + 1,
+                exit_on_error(snapshot_manager::pretty_date(
+                    &entry.date_created,
+                    &repository.storage().location(SNAPSHOT_STORE)
+                )),
+                snapshot_manager::describe_label(entry)
             );
-            println!("No .time or metadata found, creating.");
+        }
+    }
+    let report = repository
+        .prune(&keep, prune_options.dry_run)
+        .unwrap_or_else(|e| {
+            eprintln!("Could not prune snapshots: {e}");
+            process::exit(1);
+        });
 
-            println!("Collecting metadata of: {folder_path}");
-            if hash_enabled {
-                warn!("Hashes are enabled. Collecting metadata may take a while.");
-            }
+    if prune_options.dry_run {
+        println!(
+            "Would remove {} snapshots, rewrite {} patches and delete {} patches, freeing about {} bytes.",
+            report.removed_snapshots,
+            report.rewritten_patches,
+            report.deleted_patches,
+            report.freed_bytes
+        );
+    } else {
+        println!(
+            "Removed {} snapshots, rewrote {} patches and deleted {} patches, freeing {} bytes.",
+            report.removed_snapshots,
+            report.rewritten_patches,
+            report.deleted_patches,
+            report.freed_bytes
+        );
+    }
+}
 
-            // hash(folder_path).expect("msg");
-            let metadata_holder: HashSet<MetaFile> = HashSet::new();
-            let metadata_holder =
-                diffs::get_properties(&folder_path, metadata_holder, hash_enabled)
-                    .expect("Issue getting hashes of files in folder {folder_path}");
-            metadata_manager::write_metadata_to_file(
-                &metadata_holder,
-                &(folder_path.clone() + "/.time/metadata.json"),
-            );
+fn show_usage(repository: &Repository, du_options: &DuOptions) {
+    if du_options.if_removed != 0 {
+        let report = repository
+            .removal_cost(du_options.if_removed - 1)
+            .unwrap_or_else(|e| {
+                eprintln!("Could not estimate the removal: {e}");
+                process::exit(1);
+            });
+        println!(
+            "Removing snapshot {} would free about {} bytes, rewriting {} patches and deleting {}.",
+            du_options.if_removed,
+            report.freed_bytes,
+            report.rewritten_patches,
+            report.deleted_patches
+        );
+        return;
+    }
 
-            debug!("Running a initial snapshot...");
-            initial_run = true; // Use to indicate that despite there being zero changes, we still want to run on all the files
-        }
-        println!("Existing .time folder found, looking for changes...");
-        debug!("Looking for changes in directory {}", folder_path);
-        let metafile = folder_path.clone() + "/.time/metadata.json";
-        let mut metadata_holder: HashSet<MetaFile> = HashSet::new();
-
-        if !initial_run {
-            debug!("initial_run is false, reading metadata!");
-            metadata_holder = metadata_manager::read_metadata_from_file(&metafile)
-                .unwrap_or_else(|_| panic!("Couldn't read the metadata file at {metafile}"));
-        }
-        let changed_files = diffs::get_diffs(false, &metadata_holder, &folder_path)
-            .expect("Couldn't check for diffs! No files have been written.");
-        // for meta in changed_files {
-        //     println!("File Path: {}", meta.path);
-        // }
-        File::create(Path::new(&(folder_path.clone() + "/.time/tmp_empty"))).unwrap_or_else(|_| {
-            panic!(
-                "Unable to create a temporary empty file at {}!",
-                folder_path.clone() + "/.time/tmp_empty"
-            )
+    let usage = exit_on_error(repository.usage());
+    let snapshot_store = exit_on_error(repository.snapshots());
+    let mut total = du::PatchSizes::default();
+
+    println!("Per snapshot:");
+    for (index, sizes) in usage.snapshots.iter().enumerate() {
+        total += *sizes;
+        println!(
+            "{:>4}  {}  {:>12}  first: {} forward: {} reverse: {}",
+            index + 1,
+            exit_on_error(snapshot_manager::pretty_date(
+                &snapshot_store[index].date_created,
+                &repository.storage().location(SNAPSHOT_STORE)
+            )),
+            sizes.total(),
+            sizes.first,
+            sizes.forward,
+            sizes.reverse
+        );
+    }
+    println!("\nLargest paths:");
+    for (target_path, sizes) in usage.paths.iter().take(du_options.top) {
+        println!(
+            "{:>12}  first: {} forward: {} reverse: {}  {}",
+            sizes.total(),
+            sizes.first,
+            sizes.forward,
+            sizes.reverse,
+            snapshot_manager::relative_path(repository.folder_path(), target_path)
+        );
+    }
+    total += usage.unreferenced;
+    println!(
+        "\nTotal: {} bytes in patches (first: {} forward: {} reverse: {}) and {} bytes in the stores.",
+        total.total(),
+        total.first,
+        total.forward,
+        total.reverse,
+        usage.stores
+    );
+    if usage.unreferenced.total() > 0 {
+        println!(
+            "{} bytes are in patches no snapshot lists, run ftm gc to find them.",
+            usage.unreferenced.total()
+        );
+    }
+}
+
+fn collect_garbage(repository: &Repository, apply: bool) {
+    let storage = repository.storage();
+    let garbage = exit_on_error(repository.find_garbage());
+
+    for file_name in garbage.dangling_files.iter() {
+        println!("dangling patch file: {file_name}");
+    }
+    for patch_entry in garbage.unreferenced_patches.iter() {
+        println!(
+            "unreferenced patch: {} ({}, created {})",
+            restore::patch_id_of(patch_entry),
+            snapshot_manager::relative_path(repository.folder_path(), &patch_entry.target_path),
+            exit_on_error(snapshot_manager::pretty_date(
+                &patch_entry.date_created,
+                &storage.location(PATCH_STORE)
+            ))
+        );
+    }
+    for file_name in garbage.stale_files.iter() {
+        println!("stale temporary file: {file_name}");
+    }
+
+    let found = garbage.dangling_files.len()
+        + garbage.unreferenced_patches.len()
+        + garbage.stale_files.len();
+    if found == 0 {
+        println!("Nothing to clean up.");
+    } else if apply {
+        let freed = repository.remove_garbage(&garbage).unwrap_or_else(|e| {
+            eprintln!("Could not clean up {}: {e}", storage.location(""));
+            process::exit(1);
         });
-        diffs::update_metadata(&mut metadata_holder, &changed_files, hash_enabled)
-            .expect("Something went wrong when collecting metadata. Do you have read permission?");
-        if !initial_run {
-            debug!("initial_run is false, writing metadata!");
-            metadata_manager::write_metadata_to_file(&metadata_holder, &metafile);
-        }
-        println!("Finished updating metadata.");
-
-        println!("Creating snapshot with {} threads...", thread_count);
-        let mut patch_store: Arc<Mutex<Vec<DiffEntry>>> = Arc::new(Mutex::new(Vec::new()));
-        let patch_store_file = folder_path.clone() + "/.time/patches.json";
-        let snapshot_store_file = folder_path.clone() + "/.time/snapshots.json";
-        let patch_ids = Arc::new(Mutex::new(Vec::new())); // These need to be communicated through threads, thus Arc and Mutex.
-        let target_paths = Arc::new(Mutex::new(Vec::new()));
-        let ref_patch_ids = Arc::new(Mutex::new(Vec::new()));
-        let modified = Arc::new(Mutex::new(Vec::new()));
-
-        let mut snapshot_store: Vec<SnapshotEntries> = Vec::new();
-
-        if !Path::new(&snapshot_store_file).exists() {
-            File::create(Path::new(&snapshot_store_file)).unwrap_or_else(|_| {
-                panic!(
-                    "Could not create snapshot store at {}!",
-                    snapshot_store_file
-                )
-            });
-        } else {
-            let mut file = File::open(Path::new(&snapshot_store_file))
-                .unwrap_or_else(|_| panic!("Could not open {}!", snapshot_store_file));
-
-            let mut file_contents = String::new();
-            file.read_to_string(&mut file_contents)
-                .unwrap_or_else(|_| panic!("Unable to read file {}!", snapshot_store_file));
-            if !file_contents.is_empty() {
-                snapshot_store =
-                    serde_json::from_str(&file_contents).expect("Snapshot store is corrupt!");
-            }
-        }
+        println!("Removed {found} items, freeing {freed} bytes.");
+    } else {
+        println!("Found {found} items. Run again with --apply to remove them.");
+    }
+}
 
-        if !Path::new(&patch_store_file).exists() {
-            println!("Did not find patch store! An original compressed copy of every file will be made to use as reference.");
-            create_reverse = false; // Since this is the first snapshot, there is no need to create a reverse snapshot and use 2*n storage
-                                    // Split here if changed_files is greater than thread count!
-            let mut changed_files_vec: Vec<ModifiedList> = Vec::new();
-            let mut changed_count: u32 = 0;
-
-            File::create(Path::new(&patch_store_file)).unwrap_or_else(|_| {
-                panic!(
-                    "Unable to create patch store at {}",
-                    patch_store_file.clone() + "/patches.json"
-                )
-            });
-            let mut patch_store_path =
-                File::open(Path::new(&patch_store_file)).expect("Unable to open patch store file!");
-
-            let mut patch_store_contents = String::new();
-            patch_store_path
-                .read_to_string(&mut patch_store_contents)
-                .expect("Unable to open patch store file!");
-            patch_store = Arc::new(Mutex::new(Vec::new()));
-
-            for item in &changed_files {
-                // Allows us to split the Vec to give to threads
-                changed_count += 1;
-                changed_files_vec.push(ModifiedList {
-                    path: item.path.clone(),
-                    exists: item.exists,
-                    modified: item.modified,
-                });
-            }
-            if changed_files_vec.len() > thread_count.try_into().unwrap() {
-                debug!("Running as initial run!");
-                diffs::create_diffs_multithread(
-                    &patch_ids,
-                    &ref_patch_ids,
-                    &target_paths,
-                    &modified,
-                    &folder_path,
-                    changed_files_vec,
-                    changed_count,
-                    thread_count,
-                    compression_level,
-                    &patch_store,
-                    create_reverse,
-                    true, // Inital run
-                    &snapshot_mode,
+fn export_archive(repository: &Repository, export_options: &ExportOptions) {
+    // Like cat, nothing inside the folder or .time is written. Messages go to stderr in case the archive is on stdout.
+    let format = exit_on_error(export::ArchiveFormat::parse(&export_options.format));
+    let snapshot_index =
+        exit_on_error(repository.resolve_snapshot(export_options.snapshot.checked_sub(1)));
+
+    let output_path = if export_options.output.is_empty() {
+        let folder_name = Path::new(repository.folder_path())
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or("snapshot".to_string());
+        format!(
+            "{}-{}.{}",
+            folder_name,
+            snapshot_index + 1,
+            format.extension()
+        )
+    } else {
+        export_options.output.clone()
+    };
+    let mut output: Box<dyn Write> = if output_path == "-" {
+        Box::new(std::io::stdout().lock())
+    } else {
+        Box::new(
+            File::create(&output_path)
+                .unwrap_or_else(|_| panic!("Could not create the archive at {output_path}!")),
+        )
+    };
+
+    let count = repository
+        .export(snapshot_index, &format, &mut output)
+        .unwrap_or_else(|e| {
+            eprintln!("Could not export snapshot {}: {e}", snapshot_index + 1);
+            process::exit(1);
+        });
+    if output_path != "-" {
+        println!(
+            "Exported {} files from snapshot {} to {}.",
+            count,
+            snapshot_index + 1,
+            output_path
+        );
+    }
+}
+
+fn grep_history(repository: &Repository, grep_options: &GrepOptions) {
+    // Prints first-last:path:line for every matching line, with the first and last snapshot that have it.
+    // Exits with 1 if nothing matched, like grep.
+    if grep_options.pattern.is_empty() {
+        println!("No regex was given! Usage: ftm grep [--path glob] [--since date] regex");
+        process::exit(1);
+    }
+    let regex = regex::Regex::new(&grep_options.pattern).unwrap_or_else(|e| {
+        println!("{} is not a valid regex: {e}", grep_options.pattern);
+        process::exit(1);
+    });
+    let since = if grep_options.since.is_empty() {
+        None
+    } else {
+        match snapshot_manager::parse_user_date(&grep_options.since) {
+            Some(since) => Some(since),
+            None => {
+                println!(
+                    "{} is not a valid date, use something like 2025-01-31 or \"2025-01-31 14:00\".",
+                    grep_options.since
                 );
-            } else {
-                // Run regularily here!
-                debug!("Run regularily");
-                for path in changed_files.iter() {
-                    /*
-                    Get relative path of backup directory, go through changed_files, and reference relative path of backup directory. ModifiedList will handle removed files.
-                    A non-existing file can be passed, and it will be handled within get_diffs.
-                    */
-                    if path.modified {
-                        if Path::new(&path.path).is_file() {
-                            let patch_id = diffs::create_diff(
-                                "".to_string(), // This will never exist, so we can always create a temp file instead.
-                                path.path.clone(),
-                                path.path.clone(),
-                                folder_path.clone() + "/.time",
-                                "First patch".to_string(),
-                                Vec::new(),
-                                compression_level,
-                                &patch_store,
-                                create_reverse,
-                            )
-                            .unwrap_or_else(|_| {
-                                panic!(
-                                    "Was unable to create a diff between a new empty file and {}",
-                                    path.path
-                                )
-                            });
-                            {
-                                let mut patch_ids = patch_ids.lock().unwrap();
-                                let mut target_paths = target_paths.lock().unwrap();
-                                let mut ref_patch_ids = ref_patch_ids.lock().unwrap();
-                                let mut modified = modified.lock().unwrap();
-
-                                patch_ids.push(patch_id);
-                                target_paths.push(path.path.clone());
-                                ref_patch_ids.push("First patch".to_string());
-                                modified.push(path.modified);
-                            }
-                        } else {
-                            let mut patch_ids = patch_ids.lock().unwrap();
-                            let mut target_paths = target_paths.lock().unwrap();
-                            let mut ref_patch_ids = ref_patch_ids.lock().unwrap();
-                            let mut modified = modified.lock().unwrap();
-
-                            patch_ids.push("DIR".to_string());
-                            target_paths.push(path.path.clone());
-                            ref_patch_ids.push("DIR".to_string());
-                            modified.push(true);
-                        }
-                    } else {
-                        let mut patch_ids = patch_ids.lock().unwrap();
-                        let mut target_paths = target_paths.lock().unwrap();
-                        let mut ref_patch_ids = ref_patch_ids.lock().unwrap();
-                        let mut modified = modified.lock().unwrap();
-
-                        if Path::new(&path.path).is_file() {
-                            let file_contents = std::fs::read(&path.path).unwrap_or_else(|_| panic!("Could not open {} to check if it has been modified! Do I have read permission?",
-                            path.path));
-                            let hash = xxh3_64(&file_contents);
-                            patch_ids.push(hash.to_string());
-                        } else {
-                            patch_ids.push("UNMODIFIED_DIRECTORY".to_string());
-                        }
-                        target_paths.push(path.path.clone());
-                        ref_patch_ids.push("UNMODIFIED".to_string());
-                        modified.push(false);
-                        // debug!("Skipping {} because it is not modified!", path.path);
-                    }
-                }
-            }
-        } else {
-            debug!("Found patch store!");
-            // let path_temp_hold: HashSet<ModifiedList> = HashSet::new();
-            let mut patch_store_path = File::open(Path::new(&patch_store_file))
-                .unwrap_or_else(|_| panic!("Could not open {patch_store_file}!"));
-
-            let mut patch_store_contents = String::new();
-            patch_store_path
-                .read_to_string(&mut patch_store_contents)
-                .expect("Patch store contains non UTF-8 characters which are unsupported!");
-            {
-                let mut patch_store = patch_store.lock().unwrap();
-
-                *patch_store = serde_json::from_str(&patch_store_contents)
-                    .expect("Patch store is corrupt. Sorgy :(");
-            }
-            /*
-            Cycle through changed files, and check if a snapshot exists. If it does, restore snapshot to memory, to use as reference file.
-            Then we create a new patch from the two.
-
-            If no snapshot exists yet, use backup directory as reference file to create snapshot.
-            */
-            // REMEMBER TO PASS patch_store
-            // println!("{:?}", path_temp_hold);
-            // println!("fdsfsd");
-            // println!("{:?}", changed_files); // populate patch_store and pass it
-            let mut changed_files_vec: Vec<ModifiedList> = Vec::new();
-            let mut changed_count: u32 = 0;
-            for item in &changed_files {
-                // Allows us to split the Vec to give to threads
-                changed_files_vec.push(ModifiedList {
-                    path: item.path.clone(),
-                    exists: item.exists,
-                    modified: item.modified,
-                });
-                changed_count += 1;
+                process::exit(1);
             }
-            debug!("Inital run is false!");
-            let real_thread_count = if changed_count >= thread_count {
-                thread_count
-            } else {
-                1
-            }; // Only do true multithreading if necessary
-            debug!("real_thread_count is {real_thread_count}");
-            diffs::create_diffs_multithread(
-                &patch_ids,
-                &ref_patch_ids,
-                &target_paths,
-                &modified,
-                &folder_path,
-                changed_files_vec,
-                changed_count,
-                real_thread_count,
-                compression_level,
-                &patch_store,
-                false,
-                false,
-                &snapshot_mode,
-            );
         }
+    };
+    let matches = repository
+        .grep(&regex, &grep_options.path, since)
+        .unwrap_or_else(|e| {
+            eprintln!("Could not search the snapshots: {e}");
+            process::exit(1);
+        });
+    for found in matches.iter() {
+        let first = found.snapshot_indexes[0] + 1;
+        let last = found.snapshot_indexes[found.snapshot_indexes.len() - 1] + 1;
+        let snapshots = if first == last {
+            first.to_string()
+        } else {
+            format!("{first}-{last}")
+        };
+        println!(
+            "{}:{}:{}",
+            snapshots,
+            snapshot_manager::relative_path(repository.folder_path(), &found.target_path),
+            found.line
+        );
+    }
+    if matches.is_empty() {
+        process::exit(1);
+    }
+}
 
-        {
-            // Create a new scope to unlock mutex
-            debug!("Writing snapshot to store!");
-            let patch_ids = patch_ids.lock().unwrap();
-            let target_paths = target_paths.lock().unwrap();
-            let ref_patch_ids = ref_patch_ids.lock().unwrap();
-            let modified = modified.lock().unwrap();
-            if patch_ids.len() > 0 {
-                // println!("Writing snapshot to store!");
-                let current_time: String = chrono::offset::Local::now().to_string();
-                snapshot_store.push(SnapshotEntries {
-                    date_created: current_time,
-                    patch_ids: patch_ids.to_vec(),
-                    target_path: target_paths.to_vec(),
-                    ref_patch_ids: ref_patch_ids.to_vec(),
-                    modified: modified.to_vec(),
-                });
-
-                let json = serde_json::to_string_pretty(&snapshot_store)
-                    .expect("Unable to serialize metadata!");
-
-                // Write the JSON string to a file
-                let mut file = File::create(Path::new(&snapshot_store_file)).unwrap_or_else(|_| {
-                    panic!("Unable to open snapshot file at {}", snapshot_store_file)
-                });
-                file.write_all(json.as_bytes()).unwrap_or_else(|_| {
-                    panic!(
-                        "Unable to write to metadata file at {}",
-                        snapshot_store_file
-                    )
-                });
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn named(names: &[&str]) -> Vec<Config> {
+        names
+            .iter()
+            .map(|name| Config {
+                name: name.to_string(),
+                ..Config::new("/tmp")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn select_profile_by_name() {
+        let configs = named(&["work", "photos", "music"]);
+        assert_eq!(select_profile(&configs, "photos"), Ok(1));
+        assert_eq!(select_profile(&configs, "music"), Ok(2));
+        let error = select_profile(&configs, "games").unwrap_err();
+        assert!(error.contains("work, photos, music"), "{error}");
+    }
 
-        // for meta in metadata_holder {
-        //     println!("File Path: {}", meta.path);
-        //     println!("File Hash: {}", meta.hash);
-        //     println!("File Size: {} bytes", meta.size);
-        //     println!("Last Modified Time: {} seconds since UNIX epoch", meta.date_modified);
-        // }
-        // Remove our tmp file we used
-        fs::remove_file(folder_path.clone() + "/.time/tmp_empty")
-            .expect("Unable to remove old tmp file");
+    #[test]
+    fn select_profile_without_a_name() {
+        // A single entry doesn't need a name, several do
+        assert_eq!(select_profile(&named(&[""]), ""), Ok(0));
+        let error = select_profile(&named(&["work", ""]), "").unwrap_err();
+        assert!(error.contains("--profile"), "{error}");
+        assert!(select_profile(&[], "").is_err());
+        let error = select_profile(&named(&["", ""]), "work").unwrap_err();
+        assert!(error.contains("add a name field"), "{error}");
     }
 }
@@ -0,0 +1,127 @@
+use indicatif::{ProgressBar, ProgressState, ProgressStyle};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::io::{self, Write};
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Progress {
+    Scanning, // Looking for changed files
+    Indexing {
+        files: usize,
+    }, // First snapshot, collecting metadata of this many paths
+    Writing {
+        files: usize,
+    }, // Creating patches for this many changed paths
+    FileProcessed {
+        path: String,
+        bytes: u64,
+    }, // One path was indexed or saved, bytes is its size
+    PatchWritten {
+        path: String,
+        patch_id: String,
+        bytes: u64,
+    }, // bytes is the compressed size of the patch
+    Restoring {
+        snapshot: usize,
+    },
+    RestoreStep {
+        path: String,
+    }, // One path was written, created or removed while restoring
+    Finished {
+        snapshot: usize,
+    }, // Index of the snapshot that was written or restored
+    NothingChanged,
+    Failed {
+        message: String,
+    },
+    Message {
+        text: String,
+    }, // Human readable status line, printed by the command line
+}
+
+pub trait ProgressSink {
+    fn event(&self, event: Progress);
+}
+
+impl<F: Fn(Progress)> ProgressSink for F {
+    fn event(&self, event: Progress) {
+        self(event)
+    }
+}
+
+pub struct Silent;
+
+impl ProgressSink for Silent {
+    fn event(&self, _event: Progress) {}
+}
+
+pub struct Ndjson;
+
+impl ProgressSink for Ndjson {
+    // One JSON object per line on stdout, for programs that drive ftm. Nothing else is printed to stdout.
+    fn event(&self, event: Progress) {
+        let line = serde_json::to_string(&event).expect("Unable to serialize event!");
+        let mut stdout = io::stdout().lock();
+        // Whoever reads this going away should not stop a snapshot halfway through
+        let _ = writeln!(stdout, "{line}").and_then(|_| stdout.flush());
+    }
+}
+
+#[derive(Default)]
+pub struct Bars {
+    bar: RefCell<Option<ProgressBar>>,
+}
+
+impl Bars {
+    fn start(&self, bar: ProgressBar) {
+        if let Some(old) = self.bar.replace(Some(bar)) {
+            old.finish();
+        }
+    }
+
+    fn finish(&self) {
+        if let Some(bar) = self.bar.take() {
+            bar.finish();
+        }
+    }
+}
+
+impl ProgressSink for Bars {
+    // Progress bars on the terminal, the default for the command line
+    fn event(&self, event: Progress) {
+        match event {
+            Progress::Indexing { files } | Progress::Writing { files } => {
+                let bar = ProgressBar::new(files as u64);
+                bar.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos:>3}/{len:3} ({eta})")
+                    .unwrap()
+                    .with_key("eta", |state: &ProgressState, w: &mut dyn std::fmt::Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
+                    .progress_chars("#>-"));
+                self.start(bar);
+            }
+            Progress::Restoring { .. } => {
+                let bar = ProgressBar::new_spinner();
+                bar.set_style(
+                    ProgressStyle::with_template(
+                        "{spinner:.green} [{elapsed_precise}] restored {pos} paths",
+                    )
+                    .unwrap(),
+                );
+                self.start(bar);
+            }
+            Progress::FileProcessed { .. } | Progress::RestoreStep { .. } => {
+                if let Some(bar) = self.bar.borrow().as_ref() {
+                    bar.inc(1);
+                }
+            }
+            Progress::Finished { .. } | Progress::NothingChanged | Progress::Failed { .. } => {
+                self.finish()
+            }
+            Progress::Message { text } => match self.bar.borrow().as_ref() {
+                Some(bar) => bar.suspend(|| println!("{text}")),
+                None => println!("{text}"),
+            },
+            Progress::Scanning | Progress::PatchWritten { .. } => {}
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use bsdiff::diff;
+use chrono::{DateTime, Local};
+use log::debug;
+use std::collections::{HashMap, HashSet};
+
+use crate::compression;
+use crate::error::{corrupt_patch, FtmError, IoContext};
+use crate::restore::{self, PathVersion};
+use crate::snapshot_manager;
+use crate::storage::{self, StorageBackend, PATCH_STORE, SNAPSHOT_STORE};
+use crate::DiffEntry;
+use crate::SnapshotEntries;
+
+pub struct PruneReport {
+    pub removed_snapshots: usize,
+    pub rewritten_patches: usize,
+    pub deleted_patches: usize,
+    pub freed_bytes: i64,
+}
+
+pub fn select_snapshots(
+    storage: &dyn StorageBackend, // Only to say where a corrupt date came from
+    snapshot_store: &[SnapshotEntries],
+    keep_last: usize,
+    time_machine: bool,
+    now: DateTime<Local>,
+) -> Result<Vec<bool>, FtmError> {
+    /* Work out which snapshots survive. Time Machine style keeps the newest snapshot of every hour for the last
+    day, of every day for the last month, and of every week before that. The most recent and any tagged snapshot
+    are always kept.
+    */
+    let mut keep = vec![false; snapshot_store.len()];
+    let mut buckets: HashMap<String, usize> = HashMap::new();
+
+    for (index, entry) in snapshot_store.iter().enumerate() {
+        if index + keep_last >= snapshot_store.len() || !entry.tags.is_empty() {
+            keep[index] = true;
+        }
+        if time_machine {
+            let date = snapshot_manager::parse_date(
+                &entry.date_created,
+                &storage.location(SNAPSHOT_STORE),
+            )?
+            .with_timezone(&Local);
+            let age = now.signed_duration_since(date);
+            let bucket = if age.num_hours() < 24 {
+                date.format("hour %Y-%m-%d %H").to_string()
+            } else if age.num_days() < 30 {
+                date.format("day %Y-%m-%d").to_string()
+            } else {
+                date.format("week %G-%V").to_string()
+            };
+            buckets.insert(bucket, index); // Snapshots are in order, so the newest one in a bucket wins
+        }
+    }
+    for index in buckets.values() {
+        keep[*index] = true;
+    }
+    if let Some(last) = keep.last_mut() {
+        *last = true;
+    }
+    Ok(keep)
+}
+
+pub fn prune_snapshots(
+    storage: &dyn StorageBackend,
+    snapshot_store: &[SnapshotEntries],
+    keep: &[bool],
+    compression_level: u32,
+    dry_run: bool,
+) -> Result<PruneReport, FtmError> {
+    /* Removing a snapshot can remove the First patch that later versions of a file were diffed against. For every
+    path, the oldest surviving version becomes the new First patch and every other surviving version is diffed
+    against it again. Rewritten patches get new IDs instead of overwriting the old files, and the stores are only
+    swapped once every new patch is on disk. Old patch files are deleted last, so stopping halfway through at worst
+    leaves files behind for gc to clean up.
+    */
+    let patch_store = snapshot_manager::read_patch_store(storage)?;
+    let patch_index = restore::index_patch_store(&patch_store);
+    let mut new_store: Vec<SnapshotEntries> = Vec::new();
+    let mut versions: HashMap<String, Vec<String>> = HashMap::new(); // Surviving patch IDs of every path
+    let mut previous_versions: HashMap<String, String> = HashMap::new();
+    let mut previous_paths: HashSet<String> = HashSet::new();
+
+    for (index, entry) in snapshot_store.iter().enumerate() {
+        if !keep.get(index).copied().unwrap_or(true) {
+            continue; // A snapshot taken after keep was worked out is kept
+        }
+        let mut new_entry = SnapshotEntries {
+            date_created: entry.date_created.clone(),
+            patch_ids: Vec::new(),
+            target_path: Vec::new(),
+            ref_patch_ids: Vec::new(),
+            modified: Vec::new(),
+            message: entry.message.clone(),
+            tags: entry.tags.clone(),
+        };
+        let mut current_versions = HashMap::new();
+        let mut current_paths = HashSet::new();
+
+        for (position, target_path) in entry.target_path.iter().enumerate() {
+            let id = &entry.patch_ids[position];
+            let mut new_id = id.clone();
+            let mut modified = entry.modified[position];
+
+            if id == "REMOVED" {
+                if !previous_paths.contains(target_path) {
+                    continue; // It was already gone in the previous surviving snapshot
+                }
+            } else {
+                current_paths.insert(target_path.clone());
+                if let PathVersion::Patch(patch_id) =
+                    restore::resolve_version(snapshot_store, index, target_path)
+                {
+                    let path_versions = versions.entry(target_path.clone()).or_default();
+                    if !path_versions.contains(&patch_id) {
+                        path_versions.push(patch_id.clone());
+                    }
+                    // An unmodified file can only keep pointing backwards if the previous surviving snapshot has the same version
+                    if modified || previous_versions.get(target_path) != Some(&patch_id) {
+                        new_id = patch_id.clone();
+                        modified = true;
+                    }
+                    current_versions.insert(target_path.clone(), patch_id);
+                }
+            }
+            new_entry.patch_ids.push(new_id);
+            new_entry.target_path.push(target_path.clone());
+            new_entry
+                .ref_patch_ids
+                .push(entry.ref_patch_ids[position].clone());
+            new_entry.modified.push(modified);
+        }
+        previous_versions = current_versions;
+        previous_paths = current_paths;
+        new_store.push(new_entry);
+    }
+
+    let mut report = PruneReport {
+        removed_snapshots: snapshot_store.len() - new_store.len(),
+        rewritten_patches: 0,
+        deleted_patches: 0,
+        freed_bytes: 0,
+    };
+
+    // Rebase every path whose surviving versions no longer hang off a surviving First patch
+    let mut renamed: HashMap<String, String> = HashMap::new(); // Old patch ID to new patch ID
+    let mut new_patches: Vec<DiffEntry> = Vec::new();
+    let mut first_patches: HashMap<String, String> = HashMap::new(); // Path to the ID of its First patch
+    let mut surviving: HashSet<String> = HashSet::new();
+
+    for (target_path, path_versions) in versions.iter() {
+        let mut dated_versions = Vec::new();
+        for patch_id in path_versions.iter() {
+            let date = snapshot_manager::parse_date(
+                &patch_index[patch_id].date_created,
+                &storage.location(PATCH_STORE),
+            )?;
+            dated_versions.push((date, patch_id.clone()));
+        }
+        dated_versions.sort();
+        let path_versions: Vec<String> = dated_versions
+            .into_iter()
+            .map(|(_, patch_id)| patch_id)
+            .collect();
+        let base_id = path_versions[0].clone();
+        let needs_rebase = patch_index[&base_id].ref_patch != "First patch"
+            || path_versions[1..]
+                .iter()
+                .any(|patch_id| patch_index[patch_id].ref_patch != base_id);
+
+        if !needs_rebase {
+            first_patches.insert(target_path.clone(), base_id.clone());
+            surviving.extend(path_versions);
+            continue;
+        }
+        debug!("Rebasing {} onto {}", target_path, base_id);
+        let base_file = restore::rebuild_patch(storage, &patch_index, &base_id)?;
+        let mut new_base_id = String::new();
+
+        for patch_id in path_versions.iter() {
+            let new_file = restore::rebuild_patch(storage, &patch_index, patch_id)?;
+            let is_base = *patch_id == base_id;
+            let mut patch_entry = DiffEntry {
+                date_created: chrono::offset::Local::now().to_string(),
+                target_path: target_path.clone(),
+                ref_patch: if is_base {
+                    "First patch".to_string()
+                } else {
+                    new_base_id.clone()
+                },
+            };
+            while patch_index.contains_key(&restore::patch_id_of(&patch_entry))
+                || renamed
+                    .values()
+                    .any(|id| *id == restore::patch_id_of(&patch_entry))
+            {
+                patch_entry.date_created = chrono::offset::Local::now().to_string();
+            }
+            let new_id = restore::patch_id_of(&patch_entry);
+
+            // Patches are also built on a dry run, so the reported size is what the rewrite would really take
+            let mut patch = Vec::new();
+            let mut patch_reverse = Vec::new();
+            if is_base {
+                diff(&[], &new_file, &mut patch).map_err(|e| corrupt_patch(&new_id, e))?;
+            } else {
+                diff(&base_file, &new_file, &mut patch).map_err(|e| corrupt_patch(&new_id, e))?;
+                diff(&new_file, &base_file, &mut patch_reverse)
+                    .map_err(|e| corrupt_patch(&new_id, e))?;
+            }
+            let patch = compression::compress_data(patch, compression_level)
+                .at(&storage.location(&new_id))?;
+            let patch_reverse = if is_base {
+                b":3".to_vec() // Going back from the first version is never needed
+            } else {
+                compression::compress_data(patch_reverse, compression_level)
+                    .at(&storage.location(&(new_id.clone() + "-reverse")))?
+            };
+            report.freed_bytes -= (patch.len() + patch_reverse.len()) as i64;
+            if !dry_run {
+                storage.put(&new_id, &patch)?;
+                storage.put(&(new_id.clone() + "-reverse"), &patch_reverse)?;
+            }
+            if is_base {
+                new_base_id = new_id.clone();
+                first_patches.insert(target_path.clone(), new_id.clone());
+            }
+            renamed.insert(patch_id.clone(), new_id.clone());
+            surviving.insert(new_id);
+            new_patches.push(patch_entry);
+            report.rewritten_patches += 1;
+        }
+    }
+
+    for entry in new_store.iter_mut() {
+        for position in 0..entry.patch_ids.len() {
+            let id = entry.patch_ids[position].clone();
+            if let Some(new_id) = renamed.get(&id) {
+                entry.patch_ids[position] = new_id.clone();
+            }
+            if entry.modified[position] && entry.patch_ids[position].len() == 64 {
+                let first_patch = &first_patches[&entry.target_path[position]];
+                entry.ref_patch_ids[position] = if *first_patch == entry.patch_ids[position] {
+                    "First patch".to_string()
+                } else {
+                    first_patch.clone()
+                };
+            }
+        }
+    }
+
+    let mut new_patch_store: Vec<DiffEntry> = Vec::new();
+    let mut deleted = Vec::new();
+    for patch_entry in patch_store.iter() {
+        let patch_id = restore::patch_id_of(patch_entry);
+        if surviving.contains(&patch_id) {
+            new_patch_store.push(patch_entry.clone());
+        } else {
+            deleted.push(patch_id);
+        }
+    }
+    new_patch_store.extend(new_patches);
+    report.deleted_patches = deleted.len();
+
+    let sizes = storage::sizes(storage)?;
+    for patch_id in deleted.iter() {
+        for name in [patch_id.clone(), patch_id.clone() + "-reverse"] {
+            report.freed_bytes += sizes.get(&name).copied().unwrap_or(0) as i64;
+        }
+    }
+    if dry_run {
+        return Ok(report);
+    }
+
+    snapshot_manager::write_patch_store(&new_patch_store, storage)?;
+    snapshot_manager::write_snapshot_store(&new_store, storage)?;
+
+    for patch_id in deleted.iter() {
+        for name in [patch_id.clone(), patch_id.clone() + "-reverse"] {
+            if sizes.contains_key(&name) {
+                debug!("Removing {}", storage.location(&name));
+                storage.delete(&name)?;
+            }
+        }
+    }
+    Ok(report)
+}
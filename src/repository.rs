@@ -0,0 +1,1096 @@
+use chrono::{DateTime, Local};
+use log::{debug, warn};
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::compare;
+use crate::diffs;
+use crate::du::{self, Usage};
+use crate::error::{corrupt_store, FtmError, IoContext};
+use crate::export::{self, ArchiveFormat};
+use crate::gc::{self, Garbage};
+use crate::metadata_manager;
+use crate::progress::{self, Progress, ProgressSink};
+use crate::prune::{self, PruneReport};
+use crate::restore::{self, FileVersion, PathVersion};
+use crate::search::{self, Match};
+use crate::snapshot_manager::{self, ChangeKind, LogEntry, SnapshotSummary, VersionSize};
+use crate::storage::{self, StorageBackend, PATCH_STORE, SNAPSHOT_STORE};
+use crate::verify::{self, Problem};
+use crate::Config;
+use crate::DiffEntry;
+use crate::MetaFile;
+use crate::ModifiedList;
+use crate::SnapshotEntries;
+
+#[derive(Clone)]
+pub struct Repository {
+    config: Config,
+    thread_count: u32,
+    storage: Arc<dyn StorageBackend>,
+}
+
+impl Repository {
+    pub fn open(mut config: Config) -> Result<Repository, FtmError> {
+        // Checks the config. A folder without .time is fine, the first snapshot creates it.
+        config.validate()?;
+        config.folder_path = config.folder_path.trim_end_matches('/').to_string();
+        let thread_count = if config.thread_count == 0 {
+            debug!("thread_count automatically set to {}", num_cpus::get());
+            num_cpus::get() as u32
+        } else {
+            config.thread_count
+        };
+        let storage = storage::open(&config)?;
+        Ok(Repository {
+            config,
+            thread_count,
+            storage,
+        })
+    }
+
+    pub fn init(config: Config) -> Result<Repository, FtmError> {
+        // Same as open, but also creates the .time folder right away
+        let repository = Repository::open(config)?;
+        let time_dir = repository.time_dir();
+        if !Path::new(&time_dir).exists() {
+            fs::create_dir(&time_dir).at(&time_dir)?;
+        }
+        Ok(repository)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn folder_path(&self) -> &str {
+        &self.config.folder_path
+    }
+
+    pub fn thread_count(&self) -> u32 {
+        self.thread_count
+    }
+
+    pub fn time_dir(&self) -> String {
+        self.config.time_dir()
+    }
+
+    pub fn storage(&self) -> &dyn StorageBackend {
+        // Where patches and the stores live. metadata.json and activeSnapshot always stay in time_dir.
+        &*self.storage
+    }
+
+    pub fn shared_storage(&self) -> Arc<dyn StorageBackend> {
+        Arc::clone(&self.storage)
+    }
+
+    pub fn snapshot(
+        &self,
+        message: &str,
+        tags: &[String],
+        on_progress: &dyn ProgressSink,
+    ) -> Result<Option<usize>, FtmError> {
+        /* Returns the number of the new snapshot, or None if nothing changed since the last one. On an error the
+        snapshot store and metadata are left as they were, so the next snapshot picks up the same changes again.
+        */
+        let result = self.lock().and_then(|_lock| {
+            take_snapshot(
+                &self.config.folder_path,
+                &self.time_dir(),
+                &self.storage,
+                self.config.get_hashes,
+                self.thread_count,
+                self.config.brotli_compression_level,
+                &self.config.snapshot_mode,
+                message,
+                tags,
+                on_progress,
+            )
+        });
+        if let Err(e) = &result {
+            on_progress.event(Progress::Failed {
+                message: e.to_string(),
+            });
+        }
+        result
+    }
+
+    pub fn snapshots(&self) -> Result<Vec<SnapshotEntries>, FtmError> {
+        // Every snapshot, oldest first. Empty if none has been taken yet.
+        if self.storage.exists(SNAPSHOT_STORE)? {
+            snapshot_manager::read_snapshot_store(self.storage())
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    pub fn list(&self) -> Result<Vec<SnapshotSummary>, FtmError> {
+        Ok(snapshot_manager::summarize_snapshots(&self.snapshots()?))
+    }
+
+    pub fn status(&self) -> Result<Vec<(String, &'static str)>, FtmError> {
+        pending_changes(&self.config.folder_path, &self.time_dir())
+    }
+
+    pub fn restore(
+        &self,
+        snapshot_index: usize,
+        paths: &[String],
+        restore_to: Option<&str>,
+        on_progress: &dyn ProgressSink,
+    ) -> Result<(), FtmError> {
+        /* snapshot_index starts at 0. paths are globs relative to the folder. With restore_to, the snapshot is
+        written into that empty folder. Otherwise only the paths matching paths are restored in place, or the
+        whole folder if there are none. Only a whole folder restore moves activeSnapshot.
+        */
+        let result = path_patterns(paths).and_then(|patterns| {
+            self.restore_snapshot(snapshot_index, &patterns, restore_to, on_progress)
+        });
+        match &result {
+            Ok(()) => on_progress.event(Progress::Finished {
+                snapshot: snapshot_index + 1,
+            }),
+            Err(e) => on_progress.event(Progress::Failed {
+                message: e.to_string(),
+            }),
+        }
+        result
+    }
+
+    pub fn resolve_snapshot(&self, snapshot_index: Option<usize>) -> Result<usize, FtmError> {
+        // Checks snapshot_index against the snapshot store. None picks the most recent snapshot.
+        let snapshot_store = self.snapshots_or_error()?;
+        select_index(&snapshot_store, snapshot_index)
+    }
+
+    pub fn tagged_snapshot(&self, tag: &str) -> Result<usize, FtmError> {
+        // The most recent snapshot with this tag
+        self.snapshots_or_error()?
+            .iter()
+            .rposition(|snapshot| snapshot.tags.iter().any(|other| other == tag))
+            .ok_or_else(|| FtmError::UnknownTag(tag.to_string()))
+    }
+
+    pub fn diff(
+        &self,
+        from_index: usize,
+        to_index: Option<usize>,
+    ) -> Result<BTreeMap<String, ChangeKind>, FtmError> {
+        // Every path with how it changed between the two snapshots. Without to_index, from_index is compared to the folder.
+        let snapshot_store = self.snapshots_or_error()?;
+        let from_index = select_index(&snapshot_store, Some(from_index))?;
+        match to_index {
+            Some(to_index) => {
+                let to_index = select_index(&snapshot_store, Some(to_index))?;
+                Ok(compare::compare_snapshots(
+                    &snapshot_store,
+                    from_index,
+                    to_index,
+                ))
+            }
+            None => compare::compare_with_folder(
+                &snapshot_store,
+                &self.patch_index()?,
+                from_index,
+                &self.config.folder_path,
+                &self.time_dir(),
+                self.storage(),
+            ),
+        }
+    }
+
+    pub fn diff_contents(
+        &self,
+        from_index: usize,
+        to_index: Option<usize>,
+        target_paths: &[String],
+        mut on_path: impl FnMut(&str, FileVersion, FileVersion),
+    ) -> Result<(), FtmError> {
+        // Both versions of every path, one path at a time so only one file is held in memory
+        let snapshot_store = self.snapshots_or_error()?;
+        let from_index = select_index(&snapshot_store, Some(from_index))?;
+        let to_index = match to_index {
+            Some(to_index) => Some(select_index(&snapshot_store, Some(to_index))?),
+            None => None,
+        };
+        let patch_index = self.patch_index()?;
+        for target_path in target_paths.iter() {
+            let old = restore::read_version(
+                &snapshot_store,
+                &patch_index,
+                self.storage(),
+                from_index,
+                target_path,
+            )?;
+            let new = match to_index {
+                Some(to_index) => restore::read_version(
+                    &snapshot_store,
+                    &patch_index,
+                    self.storage(),
+                    to_index,
+                    target_path,
+                )?,
+                None => read_from_folder(target_path)?,
+            };
+            on_path(target_path, old, new);
+        }
+        Ok(())
+    }
+
+    pub fn read_file(&self, snapshot_index: usize, path: &str) -> Result<FileVersion, FtmError> {
+        // path can be relative to the folder or absolute. Nothing inside the folder or .time is written.
+        let snapshot_store = self.snapshots_or_error()?;
+        let snapshot_index = select_index(&snapshot_store, Some(snapshot_index))?;
+        restore::read_version(
+            &snapshot_store,
+            &self.patch_index()?,
+            self.storage(),
+            snapshot_index,
+            &snapshot_manager::resolve_target_path(&self.config.folder_path, path),
+        )
+    }
+
+    pub fn log(&self, path: &str) -> Result<Vec<LogEntry>, FtmError> {
+        // Every snapshot that created, modified or removed path, oldest first. Empty if it was never snapshotted.
+        let snapshot_store = self.snapshots_or_error()?;
+        let patch_index = self.patch_index()?;
+        let target_path = snapshot_manager::resolve_target_path(&self.config.folder_path, path);
+        let mut history = Vec::new();
+
+        for (index, entry) in snapshot_store.iter().enumerate() {
+            let Some(position) = entry
+                .target_path
+                .iter()
+                .position(|path| *path == target_path)
+            else {
+                continue;
+            };
+            let previous = if index > 0 {
+                Some(&snapshot_store[index - 1])
+            } else {
+                None
+            };
+            let change = snapshot_manager::classify_entries(previous, entry)[position];
+            if change == ChangeKind::Unchanged {
+                continue;
+            }
+            let size = match restore::resolve_version(&snapshot_store, index, &target_path) {
+                PathVersion::Patch(id) => {
+                    match restore::rebuild_patch(self.storage(), &patch_index, &id) {
+                        Ok(contents) => VersionSize::Bytes(contents.len() as u64),
+                        Err(_) => VersionSize::Unrecoverable,
+                    }
+                }
+                PathVersion::Directory => VersionSize::Directory,
+                PathVersion::Missing => VersionSize::Missing,
+            };
+            history.push(LogEntry {
+                snapshot_index: index,
+                date_created: entry.date_created.clone(),
+                change,
+                size,
+            });
+        }
+        Ok(history)
+    }
+
+    pub fn restore_file(&self, snapshot_index: usize, path: &str) -> Result<(), FtmError> {
+        // Puts a single path back in place as it was in the snapshot. activeSnapshot stays where it was.
+        let target_path = snapshot_manager::resolve_target_path(&self.config.folder_path, path);
+        let relative_path = snapshot_manager::relative_path(&self.config.folder_path, &target_path);
+        let pattern = glob::Pattern::escape(relative_path);
+        self.restore(snapshot_index, &[pattern], None, &progress::Silent)
+    }
+
+    pub fn verify(
+        &self,
+        deep: bool,
+        on_progress: &dyn ProgressSink,
+    ) -> Result<Vec<Problem>, FtmError> {
+        // Nothing is repaired, every problem found is returned. See verify for what is checked.
+        let snapshot_store = self.snapshots_or_error()?;
+        let patch_store = snapshot_manager::read_patch_store(self.storage())?;
+        let patch_index = restore::index_patch_store(&patch_store);
+
+        on_progress.event(Progress::Message {
+            text: format!("Checking {} patches...", patch_store.len()),
+        });
+        let mut problems = verify::verify_patches(self.storage(), &patch_store);
+        if deep {
+            on_progress.event(Progress::Message {
+                text: format!(
+                    "Rebuilding every file in {} snapshots...",
+                    snapshot_store.len()
+                ),
+            });
+        }
+        problems.extend(verify::verify_snapshots(
+            self.storage(),
+            &snapshot_store,
+            &patch_index,
+            deep,
+        ));
+        Ok(problems)
+    }
+
+    pub fn prune_selection(
+        &self,
+        keep_last: usize,
+        time_machine: bool,
+    ) -> Result<Vec<bool>, FtmError> {
+        /* Which snapshots the retention policy keeps, see prune::select_snapshots. The snapshot the folder was last
+        restored to is never pruned either.
+        */
+        let snapshot_store = self.snapshots()?;
+        let mut keep = prune::select_snapshots(
+            self.storage(),
+            &snapshot_store,
+            keep_last,
+            time_machine,
+            Local::now(),
+        )?;
+        let active_snapshot_path = self.time_dir() + "/activeSnapshot";
+        if let Ok(active_date) = fs::read_to_string(&active_snapshot_path) {
+            let active_date =
+                DateTime::parse_from_str(active_date.trim(), "%Y-%m-%d %H:%M:%S%.9f %z").ok();
+            for (index, entry) in snapshot_store.iter().enumerate() {
+                if active_date.is_some()
+                    && active_date
+                        == DateTime::parse_from_str(&entry.date_created, "%Y-%m-%d %H:%M:%S%.9f %z")
+                            .ok()
+                {
+                    keep[index] = true;
+                }
+            }
+        }
+        Ok(keep)
+    }
+
+    pub fn prune(&self, keep: &[bool], dry_run: bool) -> Result<PruneReport, FtmError> {
+        /* Removes every snapshot keep is false for. With dry_run, only reports what would be removed. Holds the same
+        lock as snapshot, so a snapshot can't write stores based on the ones from before the prune.
+        */
+        let _lock = self.lock()?;
+        prune::prune_snapshots(
+            self.storage(),
+            &self.snapshots()?,
+            keep,
+            self.config.brotli_compression_level,
+            dry_run,
+        )
+    }
+
+    pub fn removal_cost(&self, snapshot_index: usize) -> Result<PruneReport, FtmError> {
+        // What pruning only this snapshot would free, without writing anything
+        let snapshot_store = self.snapshots_or_error()?;
+        let snapshot_index = select_index(&snapshot_store, Some(snapshot_index))?;
+        let mut keep = vec![true; snapshot_store.len()];
+        keep[snapshot_index] = false;
+        prune::prune_snapshots(
+            self.storage(),
+            &snapshot_store,
+            &keep,
+            self.config.brotli_compression_level,
+            true,
+        )
+    }
+
+    pub fn usage(&self) -> Result<Usage, FtmError> {
+        // How much space every snapshot and path takes, see du::measure
+        let snapshot_store = self.snapshots_or_error()?;
+        let patch_store = snapshot_manager::read_patch_store(self.storage())?;
+        let sizes = storage::sizes(self.storage())?;
+        Ok(du::measure(
+            &self.time_dir(),
+            &sizes,
+            &snapshot_store,
+            &patch_store,
+        ))
+    }
+
+    pub fn find_garbage(&self) -> Result<Garbage, FtmError> {
+        // Only looks, remove_garbage deletes what this found. Waits for a snapshot that is being taken to finish.
+        let _lock = self.lock()?;
+        self.garbage()
+    }
+
+    pub fn remove_garbage(&self, garbage: &Garbage) -> Result<u64, FtmError> {
+        /* Returns how many bytes were freed. Only what is still garbage is removed, a snapshot taken since
+        find_garbage may point at a patch that was dangling back then.
+        */
+        let _lock = self.lock()?;
+        let current = self.garbage()?;
+        let garbage = Garbage {
+            dangling_files: current
+                .dangling_files
+                .into_iter()
+                .filter(|file_name| garbage.dangling_files.contains(file_name))
+                .collect(),
+            unreferenced_patches: current
+                .unreferenced_patches
+                .into_iter()
+                .filter(|patch_entry| garbage.unreferenced_patches.contains(patch_entry))
+                .collect(),
+            stale_files: current
+                .stale_files
+                .into_iter()
+                .filter(|file_name| garbage.stale_files.contains(file_name))
+                .collect(),
+        };
+        let patch_store = snapshot_manager::read_patch_store(self.storage())?;
+        gc::remove_garbage(self.storage(), &patch_store, &garbage)
+    }
+
+    pub fn export(
+        &self,
+        snapshot_index: usize,
+        format: &ArchiveFormat,
+        output: &mut dyn Write,
+    ) -> Result<usize, FtmError> {
+        // Writes the snapshot as an archive to output and returns how many files it has
+        let snapshot_store = self.snapshots_or_error()?;
+        let snapshot_index = select_index(&snapshot_store, Some(snapshot_index))?;
+        let metadata_holder =
+            metadata_manager::read_metadata_from_file(&(self.time_dir() + "/metadata.json"))
+                .unwrap_or_default();
+        export::export_snapshot(
+            &snapshot_store,
+            &self.patch_index()?,
+            &metadata_holder,
+            self.storage(),
+            &self.config.folder_path,
+            snapshot_index,
+            format,
+            output,
+        )
+    }
+
+    pub fn grep(
+        &self,
+        regex: &Regex,
+        paths: &[String],
+        since: Option<DateTime<Local>>,
+    ) -> Result<Vec<Match>, FtmError> {
+        // Every line matching regex in every version of the files matching the paths globs, see search::search_history
+        search::search_history(
+            &self.snapshots_or_error()?,
+            &self.patch_index()?,
+            self.storage(),
+            &self.config.folder_path,
+            regex,
+            &path_patterns(paths)?,
+            since,
+        )
+    }
+
+    fn lock(&self) -> Result<File, FtmError> {
+        /* A snapshot writes its patch files before the stores point at them, and gc and prune delete patch files
+        the stores don't list. Snapshots, gc and prune hold this lock on time_dir/lock, so watch, schedule or the
+        daemon can't take a snapshot halfway through one of them. It is released when the file is dropped.
+        */
+        let time_dir = self.time_dir();
+        fs::create_dir_all(&time_dir).at(&time_dir)?;
+        let lock_path = time_dir + "/lock";
+        let lock = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .at(&lock_path)?;
+        lock.lock().at(&lock_path)?;
+        Ok(lock)
+    }
+
+    fn garbage(&self) -> Result<Garbage, FtmError> {
+        let snapshot_store = self.snapshots_or_error()?;
+        let patch_store = snapshot_manager::read_patch_store(self.storage())?;
+        gc::find_garbage(self.storage(), &snapshot_store, &patch_store)
+    }
+
+    fn snapshots_or_error(&self) -> Result<Vec<SnapshotEntries>, FtmError> {
+        // Like snapshots, for everything that has nothing to work with before the first snapshot
+        let snapshot_store = self.snapshots()?;
+        if snapshot_store.is_empty() {
+            return Err(FtmError::NoSnapshots);
+        }
+        Ok(snapshot_store)
+    }
+
+    fn patch_index(&self) -> Result<HashMap<String, DiffEntry>, FtmError> {
+        Ok(restore::index_patch_store(
+            &snapshot_manager::read_patch_store(self.storage())?,
+        ))
+    }
+
+    fn restore_snapshot(
+        &self,
+        snapshot_index: usize,
+        patterns: &[glob::Pattern],
+        restore_to: Option<&str>,
+        on_progress: &dyn ProgressSink,
+    ) -> Result<(), FtmError> {
+        let snapshot_store = self.snapshots()?;
+        if snapshot_index >= snapshot_store.len() {
+            return Err(FtmError::InvalidSnapshot {
+                index: snapshot_index + 1,
+                count: snapshot_store.len(),
+            });
+        }
+        on_progress.event(Progress::Restoring {
+            snapshot: snapshot_index + 1,
+        });
+        let folder_path = &self.config.folder_path;
+        let time_dir = &self.time_dir();
+        let storage = self.storage();
+        match restore_to {
+            Some(restore_to) => restore_into(
+                &snapshot_store,
+                snapshot_index,
+                folder_path,
+                time_dir,
+                storage,
+                restore_to.trim_end_matches('/'),
+                patterns,
+                on_progress,
+            ),
+            None if !patterns.is_empty() => restore::restore_paths(
+                &snapshot_store,
+                snapshot_index,
+                folder_path,
+                time_dir,
+                storage,
+                folder_path,
+                patterns,
+                on_progress,
+            ),
+            None => {
+                if restore_full(
+                    folder_path,
+                    time_dir,
+                    storage,
+                    snapshot_store,
+                    snapshot_index,
+                    &self.config.snapshot_mode,
+                    on_progress,
+                )? {
+                    Ok(())
+                } else {
+                    Err(FtmError::AlreadyActive)
+                }
+            }
+        }
+    }
+}
+
+fn select_index(
+    snapshot_store: &[SnapshotEntries],
+    snapshot_index: Option<usize>,
+) -> Result<usize, FtmError> {
+    match snapshot_index {
+        None if !snapshot_store.is_empty() => Ok(snapshot_store.len() - 1),
+        Some(snapshot_index) if snapshot_index < snapshot_store.len() => Ok(snapshot_index),
+        _ => Err(FtmError::InvalidSnapshot {
+            index: snapshot_index.unwrap_or(0) + 1,
+            count: snapshot_store.len(),
+        }),
+    }
+}
+
+fn path_patterns(paths: &[String]) -> Result<Vec<glob::Pattern>, FtmError> {
+    // Globs relative to the folder, "./docs/" matches the same as "docs"
+    let mut patterns = Vec::new();
+    for path in paths.iter() {
+        let pattern = glob::Pattern::new(path.trim_start_matches("./").trim_end_matches('/'))
+            .map_err(|e| FtmError::Config(format!("{path} is not a valid glob pattern: {e}")))?;
+        patterns.push(pattern);
+    }
+    Ok(patterns)
+}
+
+fn read_from_folder(target_path: &str) -> Result<FileVersion, FtmError> {
+    // The version of a path that is in the folder right now
+    let path = Path::new(target_path);
+    if path.is_file() {
+        Ok(FileVersion::File(fs::read(path).at(target_path)?))
+    } else if path.is_dir() {
+        Ok(FileVersion::Directory)
+    } else {
+        Ok(FileVersion::Missing)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn restore_into(
+    snapshot_store: &[SnapshotEntries],
+    selected_index: usize,
+    folder_path: &str,
+    time_dir: &str,
+    storage: &dyn StorageBackend,
+    restore_to: &str,
+    restore_patterns: &[glob::Pattern],
+    on_progress: &dyn ProgressSink,
+) -> Result<(), FtmError> {
+    // Extracting never touches folder_path or activeSnapshot, so the timeline stays where it is.
+    if Path::new(restore_to).exists() {
+        let mut contents = fs::read_dir(restore_to).at(restore_to)?;
+        if contents.next().is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::DirectoryNotEmpty,
+                "the folder is not empty, refusing to restore into it",
+            ))
+            .at(restore_to);
+        }
+    } else {
+        fs::create_dir_all(restore_to).at(restore_to)?;
+    }
+    restore::restore_paths(
+        snapshot_store,
+        selected_index,
+        folder_path,
+        time_dir,
+        storage,
+        restore_to,
+        restore_patterns,
+        on_progress,
+    )
+}
+
+fn restore_full(
+    folder_path: &str,
+    time_dir: &str,
+    storage: &dyn StorageBackend,
+    snapshot_store: Vec<SnapshotEntries>,
+    selected_index: usize,
+    snapshot_mode: &str,
+    on_progress: &dyn ProgressSink,
+) -> Result<bool, FtmError> {
+    // Restores the whole folder and moves activeSnapshot. Returns false if the snapshot is already the active one.
+    let folder_path = folder_path.to_string();
+    let snapshot_mode = snapshot_mode.to_string();
+    // Use the stored date instead of the human readable one, since restore_snapshot_until compares them down to the nanosecond
+    let selected_item = DateTime::parse_from_str(
+        &snapshot_store[selected_index].date_created,
+        "%Y-%m-%d %H:%M:%S%.9f %z",
+    )
+    .map_err(|e| corrupt_store(&storage.location(SNAPSHOT_STORE), e))?;
+
+    /*
+    We have a entry that we want to restore, if it is in the past:
+    In fastest mode, restore directly, don't restore intermediates
+
+    if it is in the future:
+    Restore up until we restore the proper patch.
+     */
+
+    let active_snapshot_path = time_dir.to_string() + "/activeSnapshot";
+
+    if !Path::new(&active_snapshot_path).exists() {
+        debug!("No activeSnapshot found, assuming target has to be in past.");
+
+        // In fastest, restore_snapshot_until will NOT iterate. In this case, the name is misleading.
+
+        restore::restore_snapshot_until(
+            snapshot_store,
+            &folder_path,
+            time_dir,
+            storage,
+            &selected_item,
+            true,
+            &snapshot_mode,
+            on_progress,
+        )?;
+
+        let mut active_snapshot =
+            File::create(Path::new(&active_snapshot_path)).at(&active_snapshot_path)?;
+        active_snapshot
+            .write_all(selected_item.to_string().as_bytes())
+            .at(&active_snapshot_path)?;
+    } else {
+        let mut file = File::open(Path::new(&active_snapshot_path)).at(&active_snapshot_path)?;
+
+        let mut file_contents = String::new();
+        file.read_to_string(&mut file_contents)
+            .at(&active_snapshot_path)?;
+
+        let active_snapshot_date_stupid = // Please fix me this is stupid
+            DateTime::parse_from_str(&file_contents, "%Y-%m-%d %H:%M:%S%.9f %z")
+                .map_err(|e| corrupt_store(&active_snapshot_path, e))?
+                .format("%Y-%m-%d %H:%M:%S %z")
+                .to_string();
+        let active_snapshot_date =
+            DateTime::parse_from_str(&active_snapshot_date_stupid, "%Y-%m-%d %H:%M:%S %z")
+                .map_err(|e| corrupt_store(&active_snapshot_path, e))?;
+
+        if selected_item > active_snapshot_date {
+            debug!("Snapshot is in future!");
+            restore::restore_snapshot_until(
+                snapshot_store,
+                &folder_path,
+                time_dir,
+                storage,
+                &selected_item,
+                false,
+                &snapshot_mode,
+                on_progress,
+            )?;
+            fs::remove_file(&active_snapshot_path).at(&active_snapshot_path)?;
+            let mut active_snapshot =
+                File::create(Path::new(&active_snapshot_path)).at(&active_snapshot_path)?;
+            active_snapshot
+                .write_all(selected_item.to_string().as_bytes())
+                .at(&active_snapshot_path)?;
+        } else if selected_item < active_snapshot_date {
+            debug!("Snapshot is in past!");
+            restore::restore_snapshot_until(
+                snapshot_store,
+                &folder_path,
+                time_dir,
+                storage,
+                &selected_item,
+                true,
+                &snapshot_mode,
+                on_progress,
+            )?;
+            fs::remove_file(&active_snapshot_path).at(&active_snapshot_path)?;
+            let mut active_snapshot =
+                File::create(Path::new(&active_snapshot_path)).at(&active_snapshot_path)?;
+            active_snapshot
+                .write_all(selected_item.to_string().as_bytes())
+                .at(&active_snapshot_path)?;
+        } else {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn take_snapshot(
+    folder_path: &str,
+    time_dir: &str,
+    storage: &Arc<dyn StorageBackend>,
+    hash_enabled: bool,
+    thread_count: u32,
+    compression_level: u32,
+    snapshot_mode: &str,
+    message: &str,
+    tags: &[String],
+    on_progress: &dyn ProgressSink,
+) -> Result<Option<usize>, FtmError> {
+    // Returns the number of the new snapshot, or None if nothing changed and no snapshot was written.
+    let folder_path = folder_path.to_string();
+    let message_event = |text: String| on_progress.event(Progress::Message { text });
+    let create_reverse; // Disabled only on first run to reduce disk usage
+    let mut initial_run = false;
+    let time_dir = time_dir.to_string();
+    let metafile = time_dir.clone() + "/metadata.json";
+    let mut initial_metadata = None; // Only written once the first snapshot is, so a failed one is retried as the first
+    debug!("take snapshot");
+    if !Path::new(&metafile).exists() {
+        debug!("{metafile}");
+        if !Path::new(&time_dir).exists() {
+            fs::create_dir(&time_dir).at(&time_dir)?;
+        }
+        message_event("No .time or metadata found, creating.".to_string());
+
+        message_event(format!("Collecting metadata of: {folder_path}"));
+        if hash_enabled {
+            warn!("Hashes are enabled. Collecting metadata may take a while.");
+        }
+
+        // hash(folder_path).expect("msg");
+        let metadata_holder: HashSet<MetaFile> = HashSet::new();
+        initial_metadata = Some(diffs::get_properties(
+            &folder_path,
+            &time_dir,
+            metadata_holder,
+            hash_enabled,
+            on_progress,
+        )?);
+
+        debug!("Running a initial snapshot...");
+        initial_run = true; // Use to indicate that despite there being zero changes, we still want to run on all the files
+    }
+    message_event("Existing .time folder found, looking for changes...".to_string());
+    on_progress.event(Progress::Scanning);
+    debug!("Looking for changes in directory {}", folder_path);
+    let mut metadata_holder: HashSet<MetaFile> = HashSet::new();
+
+    if !initial_run {
+        debug!("initial_run is false, reading metadata!");
+        metadata_holder = metadata_manager::read_metadata_from_file(&metafile)?;
+    }
+    let changed_files = diffs::get_diffs(false, &metadata_holder, &folder_path, &time_dir)?;
+    // for meta in changed_files {
+    //     println!("File Path: {}", meta.path);
+    // }
+    let changed = diffs::update_metadata(
+        &mut metadata_holder,
+        &changed_files,
+        hash_enabled,
+        on_progress,
+    )?;
+    if !changed {
+        message_event("No files changed, nothing to do!".to_string());
+        on_progress.event(Progress::NothingChanged);
+        return Ok(None);
+    }
+    message_event("Finished updating metadata.".to_string());
+
+    message_event(format!(
+        "Creating snapshot with {} threads...",
+        thread_count
+    ));
+    on_progress.event(Progress::Writing {
+        files: changed_files.iter().filter(|path| path.modified).count(),
+    });
+    let mut patch_store: Arc<Mutex<Vec<DiffEntry>>> = Arc::new(Mutex::new(Vec::new()));
+    let patch_ids = Arc::new(Mutex::new(Vec::new())); // These need to be communicated through threads, thus Arc and Mutex.
+    let target_paths = Arc::new(Mutex::new(Vec::new()));
+    let ref_patch_ids = Arc::new(Mutex::new(Vec::new()));
+    let modified = Arc::new(Mutex::new(Vec::new()));
+
+    let mut snapshot_store: Vec<SnapshotEntries> = Vec::new();
+
+    if storage.exists(SNAPSHOT_STORE)? {
+        snapshot_store = snapshot_manager::read_snapshot_store(&**storage)?;
+    }
+
+    if !storage.exists(PATCH_STORE)? {
+        message_event("Did not find patch store! An original compressed copy of every file will be made to use as reference.".to_string());
+        create_reverse = false; // Since this is the first snapshot, there is no need to create a reverse snapshot and use 2*n storage
+                                // Split here if changed_files is greater than thread count!
+        let mut changed_files_vec: Vec<ModifiedList> = Vec::new();
+        let mut changed_count: u32 = 0;
+
+        patch_store = Arc::new(Mutex::new(Vec::new()));
+
+        for item in &changed_files {
+            // Allows us to split the Vec to give to threads
+            changed_count += 1;
+            changed_files_vec.push(ModifiedList {
+                path: item.path.clone(),
+                exists: item.exists,
+                modified: item.modified,
+            });
+        }
+        if changed_files_vec.len() > thread_count.try_into().unwrap() {
+            debug!("Running as initial run!");
+            diffs::create_diffs_multithread(
+                &patch_ids,
+                &ref_patch_ids,
+                &target_paths,
+                &modified,
+                storage,
+                changed_files_vec,
+                changed_count,
+                thread_count,
+                compression_level,
+                &patch_store,
+                create_reverse,
+                true, // Inital run
+                snapshot_mode,
+                on_progress,
+            )?;
+        } else {
+            // Run regularily here!
+            debug!("Run regularily");
+            for path in changed_files.iter() {
+                /*
+                Get relative path of backup directory, go through changed_files, and reference relative path of backup directory. ModifiedList will handle removed files.
+                A non-existing file can be passed, and it will be handled within get_diffs.
+                */
+                if path.modified {
+                    if Path::new(&path.path).is_file() {
+                        let (patch_id, bytes) = diffs::create_diff(
+                            "".to_string(), // This will never exist, so the patch is made against an empty file.
+                            path.path.clone(),
+                            path.path.clone(),
+                            &**storage,
+                            "First patch".to_string(),
+                            Vec::new(),
+                            compression_level,
+                            &patch_store,
+                            create_reverse,
+                        )?;
+                        on_progress.event(diffs::patch_written(&path.path, &patch_id, bytes));
+                        {
+                            let mut patch_ids = patch_ids.lock().unwrap();
+                            let mut target_paths = target_paths.lock().unwrap();
+                            let mut ref_patch_ids = ref_patch_ids.lock().unwrap();
+                            let mut modified = modified.lock().unwrap();
+
+                            patch_ids.push(patch_id);
+                            target_paths.push(path.path.clone());
+                            ref_patch_ids.push("First patch".to_string());
+                            modified.push(path.modified);
+                        }
+                    } else {
+                        let mut patch_ids = patch_ids.lock().unwrap();
+                        let mut target_paths = target_paths.lock().unwrap();
+                        let mut ref_patch_ids = ref_patch_ids.lock().unwrap();
+                        let mut modified = modified.lock().unwrap();
+
+                        patch_ids.push("DIR".to_string());
+                        target_paths.push(path.path.clone());
+                        ref_patch_ids.push("DIR".to_string());
+                        modified.push(true);
+                    }
+                    on_progress.event(diffs::file_processed(&path.path));
+                } else {
+                    let mut patch_ids = patch_ids.lock().unwrap();
+                    let mut target_paths = target_paths.lock().unwrap();
+                    let mut ref_patch_ids = ref_patch_ids.lock().unwrap();
+                    let mut modified = modified.lock().unwrap();
+
+                    if Path::new(&path.path).is_file() {
+                        let file_contents = std::fs::read(&path.path).at(&path.path)?;
+                        let hash = xxh3_64(&file_contents);
+                        patch_ids.push(hash.to_string());
+                    } else {
+                        patch_ids.push("UNMODIFIED_DIRECTORY".to_string());
+                    }
+                    target_paths.push(path.path.clone());
+                    ref_patch_ids.push("UNMODIFIED".to_string());
+                    modified.push(false);
+                    // debug!("Skipping {} because it is not modified!", path.path);
+                }
+            }
+        }
+    } else {
+        debug!("Found patch store!");
+        // let path_temp_hold: HashSet<ModifiedList> = HashSet::new();
+        {
+            let mut patch_store = patch_store.lock().unwrap();
+
+            *patch_store = snapshot_manager::read_patch_store(&**storage)?;
+        }
+        /*
+        Cycle through changed files, and check if a snapshot exists. If it does, restore snapshot to memory, to use as reference file.
+        Then we create a new patch from the two.
+
+        If no snapshot exists yet, use backup directory as reference file to create snapshot.
+        */
+        // REMEMBER TO PASS patch_store
+        // println!("{:?}", path_temp_hold);
+        // println!("fdsfsd");
+        // println!("{:?}", changed_files); // populate patch_store and pass it
+        let mut changed_files_vec: Vec<ModifiedList> = Vec::new();
+        let mut changed_count: u32 = 0;
+        for item in &changed_files {
+            // Allows us to split the Vec to give to threads
+            changed_files_vec.push(ModifiedList {
+                path: item.path.clone(),
+                exists: item.exists,
+                modified: item.modified,
+            });
+            changed_count += 1;
+        }
+        debug!("Inital run is false!");
+        let real_thread_count = if changed_count >= thread_count {
+            thread_count
+        } else {
+            1
+        }; // Only do true multithreading if necessary
+        debug!("real_thread_count is {real_thread_count}");
+        diffs::create_diffs_multithread(
+            &patch_ids,
+            &ref_patch_ids,
+            &target_paths,
+            &modified,
+            storage,
+            changed_files_vec,
+            changed_count,
+            real_thread_count,
+            compression_level,
+            &patch_store,
+            false,
+            false,
+            snapshot_mode,
+            on_progress,
+        )?;
+    }
+
+    {
+        // Create a new scope to unlock mutex
+        debug!("Writing snapshot to store!");
+        let patch_ids = patch_ids.lock().unwrap();
+        let target_paths = target_paths.lock().unwrap();
+        let ref_patch_ids = ref_patch_ids.lock().unwrap();
+        let modified = modified.lock().unwrap();
+        if !patch_ids.is_empty() {
+            // println!("Writing snapshot to store!");
+            let current_time: String = chrono::offset::Local::now().to_string();
+            snapshot_store.push(SnapshotEntries {
+                date_created: current_time,
+                patch_ids: patch_ids.to_vec(),
+                target_path: target_paths.to_vec(),
+                ref_patch_ids: ref_patch_ids.to_vec(),
+                modified: modified.to_vec(),
+                message: message.to_string(),
+                tags: tags.to_vec(),
+            });
+
+            // The patch store goes first, so the snapshot store never points at patches it does not know about
+            snapshot_manager::write_patch_store(&patch_store.lock().unwrap(), &**storage)?;
+            snapshot_manager::write_snapshot_store(&snapshot_store, &**storage)?;
+        }
+    }
+    // Metadata goes last, if anything above failed the next snapshot still sees these files as changed
+    match initial_metadata {
+        Some(initial_metadata) => {
+            metadata_manager::write_metadata_to_file(&initial_metadata, &metafile)?
+        }
+        None => {
+            debug!("initial_run is false, writing metadata!");
+            metadata_manager::write_metadata_to_file(&metadata_holder, &metafile)?
+        }
+    }
+
+    // for meta in metadata_holder {
+    //     println!("File Path: {}", meta.path);
+    //     println!("File Hash: {}", meta.hash);
+    //     println!("File Size: {} bytes", meta.size);
+    //     println!("Last Modified Time: {} seconds since UNIX epoch", meta.date_modified);
+    // }
+    on_progress.event(Progress::Finished {
+        snapshot: snapshot_store.len(),
+    });
+    Ok(Some(snapshot_store.len()))
+}
+
+fn pending_changes(
+    folder_path: &str,
+    time_dir: &str,
+) -> Result<Vec<(String, &'static str)>, FtmError> {
+    // Every path the next snapshot would save, with whether it is new, modified or deleted
+    let metafile = time_dir.to_string() + "/metadata.json";
+    let metadata_holder: HashSet<MetaFile> = if Path::new(&metafile).exists() {
+        metadata_manager::read_metadata_from_file(&metafile)?
+    } else {
+        HashSet::new()
+    };
+    let known_paths: HashSet<&str> = metadata_holder
+        .iter()
+        .map(|meta| meta.path.as_str())
+        .collect();
+    let changed_files = diffs::get_diffs(false, &metadata_holder, folder_path, time_dir)?;
+
+    let mut changes = Vec::new();
+    for path in changed_files.iter() {
+        if !path.exists {
+            changes.push((path.path.clone(), "deleted"));
+        } else if !known_paths.contains(path.path.as_str()) {
+            changes.push((path.path.clone(), "new"));
+        } else if path.modified {
+            changes.push((path.path.clone(), "modified"));
+        }
+    }
+    changes.sort();
+    Ok(changes)
+}
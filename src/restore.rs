@@ -1,11 +1,11 @@
 use bsdiff::patch; // TODO: In fastest mode, we can restore directly the target since the reference is always just the original file. So restore_until needs to implement this.
 use chrono::DateTime; // TODO: Snapshots should include a list of every single file at it's current state. This way we can actually ensure we get to the correct state.
 use chrono::FixedOffset;
+use glob::Pattern;
 use log::debug;
 use sha2::{Digest, Sha256};
-use std::error::Error;
+use std::collections::{HashMap, HashSet};
 use std::fs::{create_dir_all, exists, remove_dir_all, remove_file, File};
-use std::io::Read;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 use walkdir::WalkDir;
@@ -13,18 +13,29 @@ use xxhash_rust::xxh3::xxh3_64;
 
 use crate::compression;
 use crate::diffs;
+use crate::error::{corrupt_patch, corrupt_store, FtmError, IoContext};
+use crate::progress::{Progress, ProgressSink};
+use crate::snapshot_manager;
+use crate::storage::{StorageBackend, SNAPSHOT_STORE};
 use crate::DiffEntry;
 use crate::SnapshotEntries;
 
+#[derive(Debug, PartialEq)]
+pub enum PathVersion {
+    Patch(String), // ID of the patch that rebuilds this version of the file
+    Directory,
+    Missing,
+}
+
 pub fn restore_and_diff(
     _date_created: &String,
     target_path: &String,
-    folder_path: &String,
+    storage: &dyn StorageBackend,
     compression_level: u32,
     patch_store: &Arc<Mutex<Vec<DiffEntry>>>,
     create_reverse: bool,
     snapshot_mode: &String,
-) -> Result<String, Box<dyn Error>> {
+) -> Result<(String, u64), FtmError> {
     debug!("Creating a patch using reference patch!");
     let mut target_date = "".to_string();
     let mut valid_target_path = "".to_string();
@@ -41,91 +52,60 @@ pub fn restore_and_diff(
                 }
             }
             if target_date.is_empty() || valid_target_path.is_empty() {
-                panic!("Could not find a valid initial patch for {}!", target_path);
+                return Err(FtmError::MissingPatch {
+                    target_path: target_path.clone(),
+                });
             }
         }
     } else {
-        panic!("Invalid snapshot mode {}!", snapshot_mode);
+        return Err(FtmError::Config(format!(
+            "Invalid snapshot mode {snapshot_mode}!"
+        )));
     }
     let mut sha256 = Sha256::new();
     sha256.update(target_date + &valid_target_path); // Generate an ID to identify the patch. This can be derived from the data stored in DiffEntry, which can then be used to identify where the patch file is.
     let patch_id: String = format!("{:X}", sha256.finalize()); // We now have the ID of the patch, so we can restore it.
-    let patch_file;
     let target_file;
 
-    let mut patch_file_compressed = std::fs::read(
-        folder_path.clone() + "/.time/" + &patch_id + "-reverse",
-    )
-    .unwrap_or_else(|_| {
-        panic!(
-            "Could not open patch file! Try removing {} from the patch store.",
-            &target_path
-        )
-    });
+    let patch_file_compressed = storage.get(&(patch_id.clone() + "-reverse"))?;
+    let patch_file_id;
     if patch_file_compressed == [58, 51] {
         debug!("Detected fake patch!");
         // Not a valid patch, so we need to recover original file to use as reference.
-        patch_file_compressed = std::fs::read(folder_path.clone() + "/.time/" + &patch_id)
-            .unwrap_or_else(|_| {
-                panic!(
-                    "Could not open patch file! Try removing {} from the patch store.",
-                    &target_path
-                )
-            });
-        patch_file = compression::decompress_data(patch_file_compressed).unwrap_or_else(|_| {
-            panic!(
-                "Could not decompress patch file {}! Is it corrupt?",
-                target_path
-            )
-        });
+        patch_file_id = patch_id.clone();
         target_file = Vec::new();
     } else {
-        patch_file = compression::decompress_data(patch_file_compressed).unwrap_or_else(|_| {
-            panic!(
-                "Could not decompress patch file {}! Is it corrupt?",
-                target_path
-            )
-        });
-        target_file = std::fs::read(target_path).unwrap_or_else(|_| {
-            panic!(
-                "Could not open {} to restore reference patch! Metadata needs updating!",
-                &target_path
-            )
-        });
+        patch_file_id = patch_id.clone() + "-reverse";
+        target_file = std::fs::read(target_path).at(target_path)?;
     }
-    let mut ref_file = Vec::new();
-
-    patch(&target_file, &mut patch_file.as_slice(), &mut ref_file).unwrap_or_else(|_| {
-        panic!(
-            "There was an error restoring a reference patch to memory! Target file was {}",
-            &target_path
-        )
-    });
+    let ref_file = apply_patch(storage, &patch_file_id, &target_file)?;
 
-    let patch_id = diffs::create_diff(
+    diffs::create_diff(
         "".to_string(),
         target_path.clone(),
         target_path.clone(),
-        folder_path.clone() + "/.time",
+        storage,
         patch_id,
         ref_file,
         compression_level,
         patch_store,
         create_reverse,
     )
-    .expect("There was an issue while creating a diff!");
-    Ok(patch_id)
 }
 
 pub fn restore_snapshot(
     entry: &SnapshotEntries,
-    time_dir: String,
+    folder_path: &str,
+    time_dir: &str,
+    storage: &dyn StorageBackend,
     past: bool,
     snapshot_mode: &String,
-) {
-    let mut patch_path = "".to_string();
+    on_progress: &dyn ProgressSink,
+) -> Result<(), FtmError> {
     let mut first_cycle = true;
-    println!("Restoring snapshot {}!", entry.date_created);
+    on_progress.event(Progress::Message {
+        text: format!("Restoring snapshot {}!", entry.date_created),
+    });
     let mut dirs_to_remove = Vec::new(); // Remove dirs at the end since we need to cleanup the insides first
                                          // println!("{}", entry.patch_ids.len());
                                          // println!("{}", entry.ref_patch_ids.len());
@@ -143,93 +123,46 @@ pub fn restore_snapshot(
                 // Going to past where file used to exist, so we need to restore upwards to recreate it.
                 // Open patch store so we can restore
 
-                let patch_store_file = time_dir.clone() + "/patches.json";
-
                 // let path_temp_hold: HashSet<ModifiedList> = HashSet::new();
-                let mut patch_store_path = File::open(Path::new(&patch_store_file))
-                    .unwrap_or_else(|_| panic!("Could not open {patch_store_file}!"));
-
-                let mut patch_store_contents = String::new();
-
-                patch_store_path
-                    .read_to_string(&mut patch_store_contents)
-                    .expect("Patch store contains non UTF-8 characters which are unsupported!");
-                let patch_store: Vec<DiffEntry> = serde_json::from_str(&patch_store_contents)
-                    .expect("Patch store is corrupt. Sorgy :(");
+                let patch_store = snapshot_manager::read_patch_store(storage)?;
 
                 for patch_entry in patch_store.iter() {
                     let mut sha256 = Sha256::new();
                     // As long as patch store is properly ordered, we can go through and restore all matching paths.
                     if patch_entry.target_path == entry.target_path[index_counter] {
                         if &patch_entry.ref_patch == "First patch" {
-                            let mut new_file: Vec<u8> = Vec::new();
                             if first_cycle {
-                                check_and_create(&patch_entry.target_path);
+                                check_and_create(&patch_entry.target_path)?;
                                 first_cycle = false;
                             } //else {
                               // panic!("Detected patches.json is out of order! Cannot safely continue.");
                               // }
-                            check_and_create(&patch_entry.target_path);
-                            let target_file = std::fs::read(&patch_entry.target_path).unwrap();
+                            check_and_create(&patch_entry.target_path)?;
+                            let target_file = std::fs::read(&patch_entry.target_path)
+                                .at(&patch_entry.target_path)?;
                             sha256.update(
                                 patch_entry.date_created.clone() + &patch_entry.target_path,
                             );
                             let patch_id = format!("{:X}", sha256.finalize());
-                            let patch_path = time_dir.clone() + "/" + &patch_id;
-                            let patch_file_compressed =
-                                std::fs::read(&patch_path).unwrap_or_else(|_| panic!("Could not open {} to restore snapshot! Do I have read permission?",
-                                patch_path));
-                            let patch_file = compression::decompress_data(patch_file_compressed)
-                                .unwrap_or_else(|_| {
-                                    panic!(
-                                        "Could not decompress data in file {}! Is it corrupt?",
-                                        patch_path
-                                    )
-                                });
-                            patch(&target_file, &mut patch_file.as_slice(), &mut new_file)
-                                .unwrap_or_else(|_| {
-                                    panic!("Unable to restore patch {}! Is it corrupt?", patch_id)
-                                });
-                            std::fs::write(&patch_entry.target_path, &new_file).unwrap_or_else(
-                                |_| {
-                                    panic!(
-                                        "Unable to open file for writing: {}",
-                                        &patch_entry.target_path
-                                    )
-                                },
-                            );
+                            let new_file = apply_patch(storage, &patch_id, &target_file)?;
+                            std::fs::write(&patch_entry.target_path, &new_file)
+                                .at(&patch_entry.target_path)?;
+                            on_progress.event(Progress::RestoreStep {
+                                path: patch_entry.target_path.to_string(),
+                            });
                         } else if &patch_entry.ref_patch != "NONE" {
-                            let mut new_file: Vec<u8> = Vec::new();
-                            let target_file =
-                                std::fs::read(&patch_entry.target_path).unwrap_or_else(|_| panic!("Could not open {} to restore snapshot. Metadata needs updating!",
-                                &patch_entry.target_path));
+                            let target_file = std::fs::read(&patch_entry.target_path)
+                                .at(&patch_entry.target_path)?;
                             sha256.update(
                                 patch_entry.date_created.clone() + &patch_entry.target_path,
                             );
                             let patch_id = format!("{:X}", sha256.finalize());
-                            let patch_path = time_dir.clone() + "/" + &patch_id;
-                            let patch_file_compressed =
-                                std::fs::read(&patch_path).unwrap_or_else(|_| panic!("Could not open {} to restore snapshot! Do I have read permission?",
-                                patch_path));
-                            let patch_file = compression::decompress_data(patch_file_compressed)
-                                .unwrap_or_else(|_| {
-                                    panic!(
-                                        "Could not decompress data in file {}! Is it corrupt?",
-                                        patch_path
-                                    )
-                                });
-                            patch(&target_file, &mut patch_file.as_slice(), &mut new_file)
-                                .unwrap_or_else(|_| {
-                                    panic!("Unable to restore patch {}! Is it corrupt?", patch_id)
-                                });
-                            std::fs::write(&patch_entry.target_path, &new_file).unwrap_or_else(
-                                |_| {
-                                    panic!(
-                                        "Unable to open file for writing: {}",
-                                        &patch_entry.target_path
-                                    )
-                                },
-                            );
+                            let new_file = apply_patch(storage, &patch_id, &target_file)?;
+                            std::fs::write(&patch_entry.target_path, &new_file)
+                                .at(&patch_entry.target_path)?;
+                            on_progress.event(Progress::RestoreStep {
+                                path: patch_entry.target_path.to_string(),
+                            });
                         } else {
                             debug!("Skipping file since ref_id is NONE");
                         }
@@ -246,8 +179,10 @@ pub fn restore_snapshot(
                     let true_path = Path::new(target_file);
                     if true_path.exists() {
                         debug!("Removing file {}", target_file);
-                        remove_file(Path::new(&target_file))
-                            .unwrap_or_else(|_| panic!("Could not remove file {}!", &target_file));
+                        remove_file(Path::new(&target_file)).at(target_file)?;
+                        on_progress.event(Progress::RestoreStep {
+                            path: target_file.to_string(),
+                        });
                     }
                 }
             }
@@ -261,13 +196,15 @@ pub fn restore_snapshot(
             let dir = Path::new(&entry.target_path[index_counter]);
 
             if !dir.exists() {
-                create_dir_all(dir)
-                    .unwrap_or_else(|_| panic!("Could not create directory {:?}!", dir));
+                create_dir_all(dir).at(&entry.target_path[index_counter])?;
+                on_progress.event(Progress::RestoreStep {
+                    path: entry.target_path[index_counter].to_string(),
+                });
             }
         } else if id.len() < 64 {
             // Assume this is a unmodified file hash. As such, check if the file is modified, and if it is, restore the original file.
-            let file_contents = std::fs::read(&entry.target_path[index_counter]).unwrap_or_else(|_| panic!("Could not open {} to check if it has been modified! Do I have read permission?",
-            entry.target_path[index_counter]));
+            let file_contents = std::fs::read(&entry.target_path[index_counter])
+                .at(&entry.target_path[index_counter])?;
             let hash = xxh3_64(&file_contents);
 
             if &hash.to_string() == id {
@@ -292,22 +229,8 @@ pub fn restore_snapshot(
                 // So we load this and then apply our patch to it. Thus we are fast, but also hog disk usage.
                 if snapshot_mode == "fastest" {
                     debug!("Going towards future in fastest mode");
-                    let mut patch_store_file =
-                        File::open(Path::new(&(time_dir.clone() + "/patches.json")))
-                            .unwrap_or_else(|_| {
-                                panic!(
-                                    "Unable to open patch store at {}!",
-                                    time_dir.clone() + "/patches.json"
-                                )
-                            });
-
-                    let mut patch_store_contents = String::new();
-                    patch_store_file
-                        .read_to_string(&mut patch_store_contents)
-                        .expect("Unable to open patch store file!");
                     // patch_path = time_dir.clone() + "/" + &id + "-reverse";
-                    let patch_store: Vec<DiffEntry> = serde_json::from_str(&patch_store_contents)
-                        .expect("Patch store is corrupt. Sorgy :(");
+                    let patch_store = snapshot_manager::read_patch_store(storage)?;
                     // let mut iter = patch_store.iter().peekable(); // Wrong mode dipshit, you can use this in the future for other modes.
                     // let mut target_id: String = "".to_string();
                     // while let Some(patch) = iter.next() {
@@ -349,15 +272,15 @@ pub fn restore_snapshot(
                     let true_path = Path::new(&entry.target_path[index_counter]);
                     if true_path.is_dir() {
                         debug!("Got First patch on a directory, creating {:?}", true_path);
-                        create_dir_all(true_path).unwrap_or_else(|_| {
-                            panic!("Unable to create directory {:?}!", true_path)
+                        create_dir_all(true_path).at(&entry.target_path[index_counter])?;
+                        on_progress.event(Progress::RestoreStep {
+                            path: entry.target_path[index_counter].to_string(),
                         });
                     } else {
                         if target_date.is_empty() || valid_target_path.is_empty() {
-                            panic!(
-                                "Could not find a valid initial patch in the patch store for {}",
-                                entry.target_path[index_counter]
-                            )
+                            return Err(FtmError::MissingPatch {
+                                target_path: entry.target_path[index_counter].clone(),
+                            });
                         }
 
                         let mut sha256 = Sha256::new();
@@ -368,83 +291,47 @@ pub fn restore_snapshot(
                         debug!("Applying patch found from patch store");
 
                         debug!("Checking if file exists");
-                        if !exists(&entry.target_path[index_counter]).unwrap_or_else(|_| {
-                            panic!(
-                                "Could not check if file exists at {}",
-                                entry.target_path[index_counter]
-                            )
-                        }) {
+                        if !exists(&entry.target_path[index_counter])
+                            .at(&entry.target_path[index_counter])?
+                        {
                             debug!(
                                 "File doesn't exist yet, creating {}",
                                 entry.target_path[index_counter]
                             );
-                            check_and_create(&entry.target_path[index_counter]);
+                            check_and_create(&entry.target_path[index_counter])?;
                         }
 
-                        let mut final_file = Vec::new();
-                        let mut ref_file: Vec<u8> = Vec::new();
-                        let patch_path = time_dir.clone() + "/" + &patch_id; // Note that this will never be the first patch, so we don't need to handle that case.
-                        let patch_final = time_dir.clone() + "/" + &id;
+                        // Note that patch_id will never be the first patch, so we don't need to handle that case.
                         let target_path = &entry.target_path[index_counter];
                         // let target_file = std::fs::read(&target_path).expect(&format!(
                         //     "Could not open {} to restore snapshot. Metadata needs updating!",
                         //     &target_path
                         // ));
-                        let patch_file_compressed = std::fs::read(&patch_path).unwrap_or_else(|_| panic!("Could not open {} to restore snapshot! Do I have read permission?",
-                            patch_path));
-                        let patch_file = compression::decompress_data(patch_file_compressed)
-                            .unwrap_or_else(|_| {
-                                panic!(
-                                    "Could not decompress data in file {}! Is it corrupt?",
-                                    patch_path
-                                )
-                            });
-                        let patch_final_file_compressed =
-                            std::fs::read(&patch_final).unwrap_or_else(|_| panic!("Could not open {} to restore snapshot! Do I have read permission?",
-                                patch_path));
-                        let patch_file_final =
-                            compression::decompress_data(patch_final_file_compressed)
-                                .unwrap_or_else(|_| {
-                                    panic!(
-                                        "Could not decompress data in file {}! Is it corrupt?",
-                                        patch_path
-                                    )
-                                });
                         // Generate initial version of file to be used as the reference
-                        patch(&final_file, &mut patch_file.as_slice(), &mut ref_file)
-                            .unwrap_or_else(|_| {
-                                panic!("There was an issue applying patch {}!", patch_path)
-                            });
-
-                        patch(&ref_file, &mut patch_file_final.as_slice(), &mut final_file)
-                            .unwrap_or_else(|_| {
-                                panic!("There was an issue applying patch {}!", patch_path)
-                            });
+                        let ref_file = apply_patch(storage, &patch_id, &[])?;
+                        let final_file = apply_patch(storage, id, &ref_file)?;
                         debug!("Writing final target file");
-                        std::fs::write(target_path, &final_file)
-                            .unwrap_or_else(|_| panic!("Unable to write to {}!", target_path));
+                        std::fs::write(target_path, &final_file).at(target_path)?;
+                        on_progress.event(Progress::RestoreStep {
+                            path: target_path.to_string(),
+                        });
                         // index_counter += 1;
                     }
                 } else {
-                    panic!(
-                        "Only fastest snapshot mode supported, not {}!",
-                        snapshot_mode
-                    );
+                    return Err(FtmError::Config(format!(
+                        "Only fastest snapshot mode supported, not {snapshot_mode}!"
+                    )));
                 }
             } else if entry.modified[index_counter] {
                 // Target is in past. Currently works for "fastest" mode. Others untested
                 let mut ref_patch_compressed: Vec<u8> = [58, 51].to_vec(); // The default state will fail the validity check, so we don't need a brand new variable to track if this is "First patch" or not.
-                let mut ref_path = "".to_string();
+                let ref_patch_id;
                 if &entry.ref_patch_ids[index_counter] != "First patch" {
                     debug!("Restoring into the past!");
-                    patch_path = time_dir.clone() + "/" + &id;
 
-                    ref_path =
-                        time_dir.clone() + "/" + &entry.ref_patch_ids[index_counter] + "-reverse";
-                    debug!("Found reference patch {}", ref_path);
-                    ref_patch_compressed = std::fs::read(&ref_path).unwrap_or_else(|_| {
-                        panic!("Could not read reference patch at {}!", ref_path)
-                    });
+                    let ref_name = entry.ref_patch_ids[index_counter].clone() + "-reverse";
+                    debug!("Found reference patch {}", storage.location(&ref_name));
+                    ref_patch_compressed = storage.get(&ref_name)?;
                 }
 
                 if ref_patch_compressed == [58, 51] {
@@ -454,22 +341,8 @@ pub fn restore_snapshot(
                         // First patch, we need to get the proper id to restore. Unfortunately, this means we need to load and process patches.json.
                         debug!("Got a first patch, loading patches.json...");
 
-                        let patch_store_file = time_dir.clone() + "/patches.json";
-
                         // let path_temp_hold: HashSet<ModifiedList> = HashSet::new();
-                        let mut patch_store_path = File::open(Path::new(&patch_store_file))
-                            .unwrap_or_else(|_| panic!("Could not open {patch_store_file}!"));
-
-                        let mut patch_store_contents = String::new();
-
-                        patch_store_path
-                            .read_to_string(&mut patch_store_contents)
-                            .expect(
-                                "Patch store contains non UTF-8 characters which are unsupported!",
-                            );
-                        let patch_store: Vec<DiffEntry> =
-                            serde_json::from_str(&patch_store_contents)
-                                .expect("Patch store is corrupt. Sorgy :(");
+                        let patch_store = snapshot_manager::read_patch_store(storage)?;
                         let mut target_id = "".to_string();
                         for item in patch_store.iter() {
                             if item.target_path == entry.target_path[index_counter] {
@@ -480,89 +353,48 @@ pub fn restore_snapshot(
                             }
                         }
                         if target_id.is_empty() {
-                            panic!(
-                                "Could not find a target_id that should exist for file {:?}",
-                                &entry.target_path
-                            );
+                            return Err(FtmError::MissingPatch {
+                                target_path: entry.target_path[index_counter].clone(),
+                            });
                         }
-                        ref_path = time_dir.clone() + "/" + &target_id;
-                        debug!("Got ref_path as {}", ref_path);
+                        debug!("Got ref patch as {}", target_id);
+                        ref_patch_id = target_id;
                     } else {
                         // Read a false patch, so remove the reverse and restore it
-                        ref_path = time_dir.clone() + "/" + &entry.ref_patch_ids[index_counter];
+                        ref_patch_id = entry.ref_patch_ids[index_counter].clone();
                     }
-                    ref_patch_compressed = std::fs::read(&ref_path).unwrap_or_else(|_| {
-                        panic!("Could not read reference patch at {}!", ref_path)
-                    });
-                    let mut final_target: Vec<u8> = Vec::new();
-                    let empty: Vec<u8> = Vec::new();
-                    let ref_patch_full_file = compression::decompress_data(ref_patch_compressed)
-                        .unwrap_or_else(|_| {
-                            panic!("There was an error decompressing {}!", ref_path)
-                        });
-                    patch(
-                        &empty,
-                        &mut ref_patch_full_file.as_slice(),
-                        &mut final_target,
-                    )
-                    .unwrap_or_else(|_| {
-                        panic!(
-                            "There was an error applying patch {} to an empty vec!",
-                            ref_path
-                        )
-                    });
+                    let final_target = apply_patch(storage, &ref_patch_id, &[])?;
                     let target_path = &entry.target_path[index_counter];
-                    check_and_create(target_path);
+                    check_and_create(target_path)?;
                     debug!("Restoring original file {}", target_path);
-                    std::fs::write(target_path, &final_target)
-                        .unwrap_or_else(|_| panic!("Unable to write to {}!", target_path));
+                    std::fs::write(target_path, &final_target).at(target_path)?;
+                    on_progress.event(Progress::RestoreStep {
+                        path: target_path.to_string(),
+                    });
                 } else {
                     // This is a valid patch/regular case
                     // TODO: Detect if we are going to the original version and skip the middle steps.
+                    ref_patch_id = entry.ref_patch_ids[index_counter].clone() + "-reverse";
                     let mut ref_file: Vec<u8> = Vec::new();
-                    let mut final_target: Vec<u8> = Vec::new();
-                    let target_file;
                     {
                         let ref_patch = compression::decompress_data(ref_patch_compressed)
-                            .unwrap_or_else(|_| {
-                                panic!("There was an issue decompressing {}!", ref_path)
-                            });
+                            .map_err(|e| corrupt_patch(&ref_patch_id, e))?;
 
                         let target_path = &entry.target_path[index_counter];
 
-                        target_file = std::fs::read(target_path).unwrap_or_else(|_| {
-                            panic!(
-                                "Could not open {} to restore snapshot. Metadata needs updating!",
-                                &target_path
-                            )
-                        });
+                        let target_file = std::fs::read(target_path).at(target_path)?;
 
                         patch(&target_file, &mut ref_patch.as_slice(), &mut ref_file)
-                            .unwrap_or_else(|_| {
-                                panic!("There was an issue applying reference patch {}!", ref_path)
-                            }); // TODO: This is impossible, right? We cannot apply this patch against a new unkown file. We need to build upwards.
+                            .map_err(|e| corrupt_patch(&ref_patch_id, e))?; // TODO: This is impossible, right? We cannot apply this patch against a new unkown file. We need to build upwards.
                     }
-                    let patch_file_compressed = std::fs::read(&patch_path).unwrap_or_else(|_| {
-                        panic!(
-                            "Could not open {} to restore snapshot! Do I have read permission?",
-                            patch_path
-                        )
-                    });
-                    let patch_file = compression::decompress_data(patch_file_compressed)
-                        .unwrap_or_else(|_| {
-                            panic!(
-                                "Could not decompress data in file {}! Is it corrupt?",
-                                patch_path
-                            )
-                        });
-                    patch(&ref_file, &mut patch_file.as_slice(), &mut final_target).unwrap_or_else(
-                        |_| panic!("There was an issue applying patch {}!", patch_path),
-                    );
+                    let final_target = apply_patch(storage, id, &ref_file)?;
                     let target_path = &entry.target_path[index_counter];
 
                     debug!("Restoring file {}", target_path);
-                    std::fs::write(target_path, &final_target)
-                        .unwrap_or_else(|_| panic!("Unable to write to {}!", target_path));
+                    std::fs::write(target_path, &final_target).at(target_path)?;
+                    on_progress.event(Progress::RestoreStep {
+                        path: target_path.to_string(),
+                    });
                 }
             } else {
                 debug!("{:?} is not modified, leaving it alone!", entry.target_path);
@@ -571,88 +403,66 @@ pub fn restore_snapshot(
     }
 
     // We need to do a walkthrough of the directory and remove any files that are not part of the snapshot. This way files added in the future won't be there when we restore a past snapshot.
-    let folder_path = Path::new(&time_dir).parent();
-    match folder_path {
-        // Ok what the fuck is even going on :< clearly I need to read the rust book better
-        Some(x) => {
-            for path in WalkDir::new(x) {
-                match path {
-                    Ok(v) => {
-                        let v_parent = v.path().parent();
-                        match v_parent {
-                            Some(vp) => {
-                                if !entry.target_path.contains(&v.path().display().to_string())
-                                    && v.path() != x
-                                    && v.path() != Path::new(&time_dir)
-                                    && vp != Path::new(&time_dir)
-                                {
-                                    // println!("{:?}", v.path());
-                                    if v.path().is_file() {
-                                        debug!("Removing {}", v.path().display());
-                                        remove_file(v.path()).unwrap_or_else(|_| {
-                                            panic!("Unable to remove {}!", v.path().display())
-                                        })
-                                    // } else if v
-                                    //     .path()
-                                    //     .read_dir()
-                                    //     .unwrap_or_else(|_| {
-                                    //         panic!("Could not peek into directory {:?}", v.path())
-                                    //     })
-                                    //     .next()
-                                    //     .is_none()
-                                    } else {
-                                        // println!("{}", v.path().display());
-                                        // Check if directory to be removed is referenced in list at all, and if the reference is NOT to remove it, and if so, don't remove it.
-                                        let mut id_count = 0;
-                                        for path in entry.target_path.iter() {
-                                            // This ensures we don't accidentally remove some empty directory that we want to keep.
-                                            if !path.contains(&v.path().display().to_string())
-                                                && v.path().exists()
-                                                && entry.patch_ids[id_count] != "REMOVED"
-                                            {
-                                                debug!("Removing {}", v.path().display());
-                                                remove_dir_all(v.path()).unwrap_or_else(|_| {
-                                                    panic!(
-                                                        "Unable to remove {}!",
-                                                        v.path().display()
-                                                    )
-                                                });
-                                            }
-                                            id_count += 1;
-                                        }
-                                    }
-                                }
+    // Only paths that snapshots track are candidates, so .time is left alone whether or not it is inside the folder.
+    for path in WalkDir::new(folder_path) {
+        match path {
+            Ok(v) => {
+                let path_str = v.path().display().to_string();
+                if !entry.target_path.contains(&path_str)
+                    && diffs::is_tracked(&path_str, folder_path, time_dir)
+                {
+                    // println!("{:?}", v.path());
+                    if v.path().is_file() {
+                        debug!("Removing {}", path_str);
+                        remove_file(v.path()).at(&path_str)?;
+                        on_progress.event(Progress::RestoreStep { path: path_str });
+                    } else {
+                        // Check if directory to be removed is referenced in list at all, and if the reference is NOT to remove it, and if so, don't remove it.
+                        for (id_count, path) in entry.target_path.iter().enumerate() {
+                            // This ensures we don't accidentally remove some empty directory that we want to keep.
+                            if !path.contains(&path_str)
+                                && v.path().exists()
+                                && entry.patch_ids[id_count] != "REMOVED"
+                            {
+                                debug!("Removing {}", path_str);
+                                remove_dir_all(v.path()).at(&path_str)?;
+                                on_progress.event(Progress::RestoreStep {
+                                    path: path_str.clone(),
+                                });
                             }
-                            None => panic!("Error parsing {:?}", v_parent),
                         }
                     }
-                    Err(e) => println!("Error parsing {}", e),
                 }
             }
+            Err(e) => eprintln!("Error parsing {}", e),
         }
-        None => panic!(
-            "There was an issue trying to get the parent directory of {:?}!",
-            folder_path
-        ),
     }
 
     for path in dirs_to_remove.iter() {
         let true_path = Path::new(path);
         if true_path.exists() {
-            remove_dir_all(path).unwrap_or_else(|_| panic!("Could not remove dir {}!", path));
+            remove_dir_all(path).at(path)?;
+            on_progress.event(Progress::RestoreStep {
+                path: path.to_string(),
+            });
         }
         // We can do all, since we know at this point the only remaining directories will just have other empty directories in it (assuming nothing went wrong when collecting metadata.)
     }
+    Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn restore_snapshot_until(
     // In fastest mode, reference always being the initial file means we can restore directly when going forward or backward, making restoring much much faster.
     snapshot_store: Vec<SnapshotEntries>,
-    folder_path: &String,
+    folder_path: &str,
+    time_dir: &str,
+    storage: &dyn StorageBackend,
     selected_item: &DateTime<FixedOffset>,
     in_past: bool,
     snapshot_mode: &String,
-) {
+    on_progress: &dyn ProgressSink,
+) -> Result<(), FtmError> {
     if snapshot_mode == "fastest" {
         // If we are in fastest mode, we don't care about restoring anything in between since the reference is alwyas the initial version of the file.
         debug!("restoring_until in fastest mode. Skipping intermediates.");
@@ -660,34 +470,40 @@ pub fn restore_snapshot_until(
         for snapshot in snapshot_store.iter() {
             let date_entry =
                 DateTime::parse_from_str(&snapshot.date_created, "%Y-%m-%d %H:%M:%S%.9f %z")
-                    .unwrap();
+                    .map_err(|e| corrupt_store(&storage.location(SNAPSHOT_STORE), e))?;
             let formatted_date = date_entry.format("%Y-%m-%d %H:%M:%S%.9f %z").to_string();
             debug!("formatted_date is {}", formatted_date);
             if formatted_date == *selected_item.format("%Y-%m-%d %H:%M:%S%.9f %z").to_string() {
                 debug!("Found correct snapshot to restore in fastest mode.");
                 restore_snapshot(
                     snapshot,
-                    folder_path.clone() + "/.time",
+                    folder_path,
+                    time_dir,
+                    storage,
                     in_past,
                     snapshot_mode,
-                );
+                    on_progress,
+                )?;
             }
         }
     } else if in_past {
         for snapshot in snapshot_store.iter().rev() {
             let date_entry =
                 DateTime::parse_from_str(&snapshot.date_created, "%Y-%m-%d %H:%M:%S%.9f %z")
-                    .unwrap();
+                    .map_err(|e| corrupt_store(&storage.location(SNAPSHOT_STORE), e))?;
 
             if date_entry == *selected_item {
                 break;
             }
             restore_snapshot(
                 snapshot,
-                folder_path.clone() + "/.time",
+                folder_path,
+                time_dir,
+                storage,
                 in_past,
                 snapshot_mode,
-            );
+                on_progress,
+            )?;
             // Past is true since we want to restore the reverse patch
         }
     } else {
@@ -696,42 +512,258 @@ pub fn restore_snapshot_until(
         for snapshot in snapshot_store.iter() {
             let date_entry =
                 DateTime::parse_from_str(&snapshot.date_created, "%Y-%m-%d %H:%M:%S%.9f %z")
-                    .unwrap();
+                    .map_err(|e| corrupt_store(&storage.location(SNAPSHOT_STORE), e))?;
 
             if date_entry == *selected_item {
                 break;
             }
             restore_snapshot(
                 snapshot,
-                folder_path.clone() + "/.time",
+                folder_path,
+                time_dir,
+                storage,
                 in_past,
                 snapshot_mode,
-            );
+                on_progress,
+            )?;
             // Past is true since we want to restore the reverse patch
         }
     }
+    Ok(())
 }
 
-fn check_and_create(target_path: &String) {
-    if !exists(target_path)
-        .unwrap_or_else(|_| panic!("Could not check if file exists at {}", target_path))
-    {
+fn check_and_create(target_path: &String) -> Result<(), FtmError> {
+    if !exists(target_path).at(target_path)? {
         let true_path = Path::new(target_path).parent(); // Turns target_path into a Path. I know I should do this everywhere.
-        match true_path {
-            Some(x) => {
-                if !exists(x).unwrap() {
-                    debug!("Parent directory doesn't exist, creating {:?}", true_path);
-                    create_dir_all(x)
-                        .unwrap_or_else(|_| panic!("Could not create parent directory at {:?}", x));
-                }
+        if let Some(x) = true_path {
+            if !exists(x).at(target_path)? {
+                debug!("Parent directory doesn't exist, creating {:?}", true_path);
+                create_dir_all(x).at(&x.display().to_string())?;
             }
-            None => panic!(
-                "There was an issue trying to get the parent directory of {}!",
-                target_path
-            ),
         }
         debug!("File doesn't exist yet, creating {}", target_path);
-        File::create(Path::new(&target_path))
-            .unwrap_or_else(|_| panic!("Could not create file at {}!", target_path));
+        File::create(Path::new(&target_path)).at(target_path)?;
+    }
+    Ok(())
+}
+
+pub fn patch_id_of(patch_entry: &DiffEntry) -> String {
+    let mut sha256 = Sha256::new();
+    sha256.update(patch_entry.date_created.clone() + &patch_entry.target_path);
+    format!("{:X}", sha256.finalize())
+}
+
+pub fn index_patch_store(patch_store: &[DiffEntry]) -> HashMap<String, DiffEntry> {
+    // Lets us go from a patch ID straight to its entry, instead of hashing the whole store for every lookup
+    let mut patch_index = HashMap::new();
+    for patch_entry in patch_store.iter() {
+        patch_index.insert(patch_id_of(patch_entry), patch_entry.clone());
+    }
+    patch_index
+}
+
+pub fn resolve_version(
+    snapshot_store: &[SnapshotEntries],
+    snapshot_index: usize,
+    target_path: &str,
+) -> PathVersion {
+    /* Unmodified files only store a hash of their contents, so we walk backwards through the snapshots until
+    we find the one where this version of the file was actually written.
+    */
+    for entry in snapshot_store[..=snapshot_index].iter().rev() {
+        let position = match entry
+            .target_path
+            .iter()
+            .position(|path| path == target_path)
+        {
+            Some(position) => position,
+            None => return PathVersion::Missing,
+        };
+        let id = &entry.patch_ids[position];
+
+        if id == "REMOVED" {
+            return PathVersion::Missing;
+        } else if id == "DIR" || id == "UNMODIFIED_DIRECTORY" {
+            return PathVersion::Directory;
+        } else if entry.modified[position] && id.len() == 64 {
+            return PathVersion::Patch(id.clone());
+        }
+        debug!(
+            "{} is unmodified in {}, looking further back",
+            target_path, entry.date_created
+        );
+    }
+    PathVersion::Missing
+}
+
+pub enum FileVersion {
+    File(Vec<u8>),
+    Directory,
+    Missing, // Not part of the snapshot, or removed in it
+}
+
+pub fn read_version(
+    snapshot_store: &[SnapshotEntries],
+    patch_index: &HashMap<String, DiffEntry>,
+    storage: &dyn StorageBackend,
+    snapshot_index: usize,
+    target_path: &str,
+) -> Result<FileVersion, FtmError> {
+    // Only this one file is rebuilt, in memory. Nothing inside the folder or .time is written.
+    match resolve_version(snapshot_store, snapshot_index, target_path) {
+        PathVersion::Patch(id) => Ok(FileVersion::File(rebuild_patch(storage, patch_index, &id)?)),
+        PathVersion::Directory => Ok(FileVersion::Directory),
+        PathVersion::Missing => Ok(FileVersion::Missing),
+    }
+}
+
+pub fn rebuild_patch(
+    storage: &dyn StorageBackend,
+    patch_index: &HashMap<String, DiffEntry>,
+    patch_id: &str,
+) -> Result<Vec<u8>, FtmError> {
+    // Rebuild a file entirely in memory, starting from an empty file and applying its reference patch first if it has one.
+    let patch_entry = match patch_index.get(patch_id) {
+        Some(patch_entry) => patch_entry,
+        None => return Err(corrupt_patch(patch_id, "it is not in the patch store")),
+    };
+    let ref_file = if patch_entry.ref_patch == "First patch" {
+        Vec::new()
+    } else {
+        rebuild_patch(storage, patch_index, &patch_entry.ref_patch)?
+    };
+    apply_patch(storage, patch_id, &ref_file)
+}
+
+pub fn apply_patch(
+    storage: &dyn StorageBackend,
+    patch_id: &str,
+    ref_file: &[u8],
+) -> Result<Vec<u8>, FtmError> {
+    // Apply a single patch on top of an already rebuilt reference file. patch_id may end in -reverse.
+    let patch_file_compressed = storage.get(patch_id)?;
+    let patch_file = compression::decompress_data(patch_file_compressed)
+        .map_err(|e| corrupt_patch(patch_id, e))?;
+
+    let mut new_file = Vec::new();
+    patch(ref_file, &mut patch_file.as_slice(), &mut new_file)
+        .map_err(|e| corrupt_patch(patch_id, e))?;
+    Ok(new_file)
+}
+
+pub fn path_matches(patterns: &[Pattern], relative_path: &str) -> bool {
+    // A pattern matching a directory also matches everything inside of it
+    let mut current = Some(Path::new(relative_path));
+    while let Some(path) = current {
+        if let Some(path_str) = path.to_str() {
+            if !path_str.is_empty() && patterns.iter().any(|pattern| pattern.matches(path_str)) {
+                return true;
+            }
+        }
+        current = path.parent();
+    }
+    false
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn restore_paths(
+    snapshot_store: &[SnapshotEntries],
+    snapshot_index: usize,
+    folder_path: &str,
+    time_dir: &str,
+    storage: &dyn StorageBackend,
+    target_root: &str,
+    patterns: &[Pattern],
+    on_progress: &dyn ProgressSink,
+) -> Result<(), FtmError> {
+    /* Restore the paths matching patterns (or every path if there are none) into target_root, rebuilding every
+    file from its patches instead of stepping the whole folder through restore_snapshot. Stored paths are remapped
+    from folder_path to target_root, which is just folder_path for an in place restore. Anything that does not
+    match is never created, written or removed.
+    */
+    let patch_store = snapshot_manager::read_patch_store(storage)?;
+    let patch_index = index_patch_store(&patch_store);
+    let entry = &snapshot_store[snapshot_index];
+    let mut snapshot_paths = HashSet::new();
+
+    for (index, stored_path) in entry.target_path.iter().enumerate() {
+        let id = &entry.patch_ids[index];
+        let relative_path = snapshot_manager::relative_path(folder_path, stored_path);
+        if id == "REMOVED" || (!patterns.is_empty() && !path_matches(patterns, relative_path)) {
+            continue;
+        }
+        let target_path = target_root.to_string() + "/" + relative_path;
+        snapshot_paths.insert(target_path.clone());
+
+        match resolve_version(snapshot_store, snapshot_index, stored_path) {
+            PathVersion::Directory => {
+                debug!("Creating dir if not exists: {}", target_path);
+                create_dir_all(&target_path).at(&target_path)?;
+                on_progress.event(Progress::RestoreStep {
+                    path: target_path.to_string(),
+                });
+            }
+            PathVersion::Patch(patch_id) => {
+                if !entry.modified[index] && Path::new(&target_path).is_file() {
+                    // Unmodified files store a hash of their contents, so we can skip rebuilding them if they match
+                    if xxh3_64(&std::fs::read(&target_path).at(&target_path)?).to_string() == *id {
+                        debug!("{} is unmodified, leaving it alone!", target_path);
+                        continue;
+                    }
+                }
+                let new_file = rebuild_patch(storage, &patch_index, &patch_id)?;
+                if Path::new(&target_path).is_file()
+                    && std::fs::read(&target_path).at(&target_path)? == new_file
+                {
+                    continue;
+                }
+                check_and_create(&target_path)?;
+                debug!("Restoring file {}", target_path);
+                std::fs::write(&target_path, &new_file).at(&target_path)?;
+                on_progress.event(Progress::RestoreStep {
+                    path: target_path.to_string(),
+                });
+            }
+            PathVersion::Missing => {
+                return Err(FtmError::MissingPatch {
+                    target_path: stored_path.clone(),
+                })
+            }
+        }
+    }
+
+    // Remove matching paths that did not exist yet when the snapshot was taken. Deepest paths go first.
+    let mut paths_to_remove = Vec::new();
+    for path in WalkDir::new(target_root) {
+        let path = path?;
+        if let Some(path_str) = path.path().to_str() {
+            if diffs::is_tracked(path_str, target_root, time_dir)
+                && !snapshot_paths.contains(path_str)
+                && (patterns.is_empty()
+                    || path_matches(
+                        patterns,
+                        snapshot_manager::relative_path(target_root, path_str),
+                    ))
+            {
+                paths_to_remove.push(path_str.to_string());
+            }
+        }
+    }
+    for path in paths_to_remove.iter().rev() {
+        let true_path = Path::new(path);
+        if true_path.is_dir() {
+            debug!("Removing {}", path);
+            remove_dir_all(true_path).at(path)?;
+            on_progress.event(Progress::RestoreStep {
+                path: path.to_string(),
+            });
+        } else if true_path.exists() {
+            debug!("Removing {}", path);
+            remove_file(true_path).at(path)?;
+            on_progress.event(Progress::RestoreStep {
+                path: path.to_string(),
+            });
+        }
     }
+    Ok(())
 }
@@ -0,0 +1,294 @@
+use chrono::{DateTime, Datelike, Local, Timelike};
+use std::thread;
+use std::time::Duration;
+
+use crate::error::FtmError;
+use crate::progress::Progress;
+use crate::Config;
+use crate::Repository;
+
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    Interval(Duration),
+    Cron {
+        allowed: Vec<Vec<bool>>, // Allowed values of minute, hour, day of month, month and day of week
+        any_day: bool, // One of the day fields starts with *, so a day has to match both of them
+    },
+}
+
+const CRON_RANGES: [(u32, u32); 5] = [(0, 59), (0, 23), (1, 31), (1, 12), (0, 7)];
+
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<Vec<bool>, String> {
+    // Supports *, */n, a, a-b, a-b/n and lists of those separated by commas
+    let mut allowed = vec![false; max as usize + 1];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or(format!("{step} is not a valid step"))?,
+            ),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (
+                start
+                    .parse()
+                    .map_err(|_| format!("{start} is not a number"))?,
+                end.parse().map_err(|_| format!("{end} is not a number"))?,
+            )
+        } else {
+            let value = range
+                .parse()
+                .map_err(|_| format!("{range} is not a number"))?;
+            (value, if step > 1 { max } else { value })
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("{part} is outside of {min}-{max}"));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            allowed[value as usize] = true;
+        }
+    }
+    Ok(allowed)
+}
+
+impl Schedule {
+    pub fn parse(schedule: &str) -> Result<Schedule, FtmError> {
+        /* Either an interval like 30m, 2h or 1d, or a cron expression with five fields like "0 * * * 1-5".
+        Intervals are counted from the end of the previous run.
+        */
+        let schedule = schedule.trim();
+        let fields: Vec<&str> = schedule.split_whitespace().collect();
+        if fields.len() == 5 {
+            let mut allowed = Vec::new();
+            for (field, (min, max)) in fields.iter().zip(CRON_RANGES) {
+                allowed.push(parse_cron_field(field, min, max).map_err(|e| {
+                    FtmError::Config(format!("{schedule} is not a valid schedule: {e}"))
+                })?);
+            }
+            return Ok(Schedule::Cron {
+                allowed,
+                any_day: fields[2].starts_with('*') || fields[4].starts_with('*'),
+            });
+        }
+
+        let invalid = || {
+            FtmError::Config(format!(
+                "{schedule} is not a valid schedule, use an interval like 30m or a cron expression"
+            ))
+        };
+        let unit = schedule.chars().last().ok_or_else(invalid)?;
+        let amount: u64 = schedule[..schedule.len() - unit.len_utf8()]
+            .parse()
+            .map_err(|_| invalid())?;
+        let seconds = match unit {
+            's' => amount,
+            'm' => amount * 60,
+            'h' => amount * 60 * 60,
+            'd' => amount * 60 * 60 * 24,
+            _ => return Err(invalid()),
+        };
+        if seconds == 0 {
+            return Err(invalid());
+        }
+        Ok(Schedule::Interval(Duration::from_secs(seconds)))
+    }
+
+    pub fn next_after(&self, after: DateTime<Local>) -> DateTime<Local> {
+        match self {
+            Schedule::Interval(interval) => {
+                after + chrono::Duration::from_std(*interval).unwrap_or(chrono::Duration::MAX)
+            }
+            Schedule::Cron { allowed, any_day } => {
+                let mut candidate = after
+                    .with_second(0)
+                    .and_then(|date| date.with_nanosecond(0))
+                    .unwrap_or(after)
+                    + chrono::Duration::minutes(1);
+                // Every minute of the next four years, enough to reach any day of month and day of week combination
+                for _ in 0..(4 * 366 * 24 * 60) {
+                    let weekday = candidate.weekday().num_days_from_sunday() as usize;
+                    let day_of_month = allowed[2][candidate.day() as usize];
+                    let day_of_week = allowed[4][weekday] || (weekday == 0 && allowed[4][7]);
+                    // Like cron, if neither day field starts with * a day matching either of them is enough
+                    let day = if *any_day {
+                        day_of_month && day_of_week
+                    } else {
+                        day_of_month || day_of_week
+                    };
+                    if allowed[0][candidate.minute() as usize]
+                        && allowed[1][candidate.hour() as usize]
+                        && allowed[3][candidate.month() as usize]
+                        && day
+                    {
+                        return candidate;
+                    }
+                    candidate += chrono::Duration::minutes(1);
+                }
+                candidate // Only reached by a date that never exists, like the 31st of February
+            }
+        }
+    }
+}
+
+fn log(config: &Config, message: &str) {
+    println!(
+        "{} {}: {}",
+        Local::now().format("%Y-%m-%d %H:%M:%S"),
+        config.folder_path,
+        message
+    );
+}
+
+pub fn run(configs: Vec<Config>) -> Result<(), FtmError> {
+    /* Every config entry with a schedule gets its own next run time. Runs happen one at a time on this thread,
+    so two entries never snapshot at once. An entry that comes due while another one is running waits for it.
+    */
+    let mut jobs = Vec::new();
+    for config in configs {
+        if config.schedule.is_empty() {
+            log(&config, "no schedule set, skipping");
+            continue;
+        }
+        let schedule = Schedule::parse(&config.schedule)?;
+        let next_run = schedule.next_after(Local::now());
+        log(
+            &config,
+            &format!(
+                "scheduled \"{}\", next snapshot at {}",
+                config.schedule,
+                next_run.format("%Y-%m-%d %H:%M:%S")
+            ),
+        );
+        jobs.push((config, schedule, next_run));
+    }
+    if jobs.is_empty() {
+        return Err(FtmError::Config(
+            "No config entry has a schedule".to_string(),
+        ));
+    }
+
+    loop {
+        let (position, next_run) = jobs
+            .iter()
+            .enumerate()
+            .map(|(position, job)| (position, job.2))
+            .min_by_key(|(_, next_run)| *next_run)
+            .expect("There is always at least one job");
+        if let Ok(wait) = next_run.signed_duration_since(Local::now()).to_std() {
+            thread::sleep(wait);
+        }
+
+        let (config, schedule, next_run) = &mut jobs[position];
+        // Opened again for every run, so a folder that went missing is only skipped until it is back
+        match Repository::open(config.clone()) {
+            Err(e) => log(config, &format!("skipped, the config is not valid: {e}")),
+            Ok(repository) => {
+                // Errors arrive as a Failed event too, and the next run simply tries again
+                let _ = repository.snapshot("", &[], &|event| match event {
+                    Progress::Finished { snapshot } => {
+                        log(config, &format!("took snapshot {snapshot}"))
+                    }
+                    Progress::NothingChanged => log(config, "nothing changed"),
+                    Progress::Failed { message } => log(config, &format!("failed: {message}")),
+                    _ => {}
+                });
+            }
+        }
+        *next_run = schedule.next_after(Local::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .single()
+            .unwrap()
+    }
+
+    fn next(schedule: &str, after: DateTime<Local>) -> DateTime<Local> {
+        Schedule::parse(schedule).unwrap().next_after(after)
+    }
+
+    #[test]
+    fn parses_intervals() {
+        let after = local(2025, 1, 31, 12, 0);
+        assert_eq!(next("30m", after), local(2025, 1, 31, 12, 30));
+        assert_eq!(next(" 2h ", after), local(2025, 1, 31, 14, 0));
+        assert_eq!(next("1d", after), local(2025, 2, 1, 12, 0));
+        for invalid in ["", "0m", "10", "5x", "m", "-5m", "1 2 3"] {
+            assert!(Schedule::parse(invalid).is_err(), "{invalid} was accepted");
+        }
+    }
+
+    #[test]
+    fn refuses_invalid_cron_fields() {
+        for invalid in [
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(Schedule::parse(invalid).is_err(), "{invalid} was accepted");
+        }
+    }
+
+    #[test]
+    fn steps_ranges_and_lists() {
+        let after = local(2025, 1, 31, 12, 7);
+        assert_eq!(next("*/15 * * * *", after), local(2025, 1, 31, 12, 15));
+        assert_eq!(next("5/20 * * * *", after), local(2025, 1, 31, 12, 25));
+        assert_eq!(next("0 9-17/4 * * *", after), local(2025, 1, 31, 13, 0));
+        assert_eq!(next("3,7,50 * * * *", after), local(2025, 1, 31, 12, 50));
+        // The next run is always after the given time, even if that time matches
+        assert_eq!(next("7 12 * * *", after), local(2025, 2, 1, 12, 7));
+    }
+
+    #[test]
+    fn rolls_over_months_and_years() {
+        assert_eq!(
+            next("0 0 1 * *", local(2025, 12, 15, 8, 0)),
+            local(2026, 1, 1, 0, 0)
+        );
+        assert_eq!(
+            next("0 0 31 * *", local(2025, 4, 1, 0, 0)),
+            local(2025, 5, 31, 0, 0)
+        );
+        // Only leap years have a 29th of February
+        assert_eq!(
+            next("0 0 29 2 *", local(2025, 3, 1, 0, 0)),
+            local(2028, 2, 29, 0, 0)
+        );
+    }
+
+    #[test]
+    fn day_fields_match_like_cron() {
+        // 2025-01-31 is a Friday
+        let after = local(2025, 1, 31, 12, 0);
+        // Both restricted, either one is enough: Saturday the 1st or Monday the 3rd, whichever comes first
+        assert_eq!(next("0 0 1 * 1", after), local(2025, 2, 1, 0, 0));
+        assert_eq!(next("0 0 15 * 1", after), local(2025, 2, 3, 0, 0));
+        // Restricted to every day of the month, but without a star, so it is still either one
+        assert_eq!(next("0 0 1-31 * 1", after), local(2025, 2, 1, 0, 0));
+        // A field starting with a star means both have to match: the first Monday on an odd day
+        assert_eq!(next("0 0 */2 * 1", after), local(2025, 2, 3, 0, 0));
+        assert_eq!(next("0 0 * * 1", after), local(2025, 2, 3, 0, 0));
+        // 7 is Sunday too
+        assert_eq!(next("0 0 * * 7", after), local(2025, 2, 2, 0, 0));
+        assert_eq!(next("0 0 * * 0", after), local(2025, 2, 2, 0, 0));
+    }
+}
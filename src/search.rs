@@ -0,0 +1,118 @@
+use chrono::DateTime;
+use log::debug;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::error::FtmError;
+use crate::restore::{self, PathVersion};
+use crate::snapshot_manager;
+use crate::storage::{StorageBackend, SNAPSHOT_STORE};
+use crate::DiffEntry;
+use crate::SnapshotEntries;
+
+pub struct Match {
+    pub snapshot_indexes: Vec<usize>, // Every snapshot that has this version of the file, oldest first
+    pub target_path: String,
+    pub line_number: usize,
+    pub line: String,
+}
+
+fn matching_lines(contents: &[u8], regex: &Regex) -> Vec<(usize, String)> {
+    if contents.contains(&0) {
+        return Vec::new(); // Binary files are skipped, same as diff --patch does
+    }
+    String::from_utf8_lossy(contents)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| regex.is_match(line))
+        .map(|(number, line)| (number + 1, line.to_string()))
+        .collect()
+}
+
+pub fn search_history(
+    snapshot_store: &[SnapshotEntries],
+    patch_index: &HashMap<String, DiffEntry>,
+    storage: &dyn StorageBackend,
+    folder_path: &str,
+    regex: &Regex,
+    patterns: &[glob::Pattern],
+    since: Option<DateTime<chrono::Local>>,
+) -> Result<Vec<Match>, FtmError> {
+    /* Most snapshots share versions of a file, so the snapshots are first grouped by the version they have of
+    every path. Each version is then rebuilt and searched once, and a matching line is returned once with every
+    snapshot that has it. Paths are handled one at a time, so only the reference files of a single path are kept
+    in memory.
+    */
+    let mut versions: BTreeMap<&String, Vec<(String, Vec<usize>)>> = BTreeMap::new(); // Path to (patch ID, snapshots)
+
+    for (index, entry) in snapshot_store.iter().enumerate() {
+        if let Some(since) = since {
+            let date = snapshot_manager::parse_date(
+                &entry.date_created,
+                &storage.location(SNAPSHOT_STORE),
+            )?;
+            if date < since {
+                continue;
+            }
+        }
+        for target_path in entry.target_path.iter() {
+            let relative = snapshot_manager::relative_path(folder_path, target_path);
+            if !patterns.is_empty() && !restore::path_matches(patterns, relative) {
+                continue;
+            }
+            if let PathVersion::Patch(id) =
+                restore::resolve_version(snapshot_store, index, target_path)
+            {
+                let path_versions = versions.entry(target_path).or_default();
+                match path_versions.iter_mut().find(|(other, _)| *other == id) {
+                    Some((_, indexes)) => indexes.push(index),
+                    None => path_versions.push((id, vec![index])),
+                }
+            }
+        }
+    }
+
+    let mut matches = Vec::new();
+    for (target_path, path_versions) in versions.iter() {
+        let mut reference_files: HashMap<String, Vec<u8>> = HashMap::new();
+
+        for (id, snapshot_indexes) in path_versions.iter() {
+            debug!("Searching {} in {}", target_path, id);
+            let ref_patch = match patch_index.get(id) {
+                Some(patch_entry) => &patch_entry.ref_patch,
+                None => {
+                    return Err(FtmError::MissingPatch {
+                        target_path: target_path.to_string(),
+                    })
+                }
+            };
+            let contents = if ref_patch == "First patch" {
+                let contents = restore::apply_patch(storage, id, &[])?;
+                reference_files.insert(id.clone(), contents.clone()); // Later versions are diffed against it
+                contents
+            } else {
+                if !reference_files.contains_key(ref_patch) {
+                    let ref_file = restore::rebuild_patch(storage, patch_index, ref_patch)?;
+                    reference_files.insert(ref_patch.clone(), ref_file);
+                }
+                restore::apply_patch(storage, id, &reference_files[ref_patch])?
+            };
+            for (line_number, line) in matching_lines(&contents, regex) {
+                matches.push(Match {
+                    snapshot_indexes: snapshot_indexes.clone(),
+                    target_path: target_path.to_string(),
+                    line_number,
+                    line,
+                });
+            }
+        }
+    }
+    matches.sort_by(|a, b| {
+        (a.snapshot_indexes[0], &a.target_path, a.line_number).cmp(&(
+            b.snapshot_indexes[0],
+            &b.target_path,
+            b.line_number,
+        ))
+    });
+    Ok(matches)
+}
@@ -0,0 +1,476 @@
+use chrono::DateTime;
+use log::debug;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+use std::net::ToSocketAddrs;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tiny_http::{Header, Method, Request, Response, Server};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::error::{FtmError, IoContext};
+use crate::export;
+use crate::metadata_manager;
+use crate::restore::{self, PathVersion};
+use crate::snapshot_manager;
+use crate::storage::{StorageBackend, SNAPSHOT_STORE};
+use crate::DiffEntry;
+use crate::SnapshotEntries;
+
+enum Node {
+    Root,
+    SnapshotList,
+    Directory(usize, String),    // Snapshot index and target path
+    File(usize, String, String), // Snapshot index, target path and patch ID
+    NotFound,
+}
+
+struct Child {
+    name: String,
+    node: Node,
+}
+
+pub struct SnapshotTree {
+    folder_path: String,
+    time_dir: String,
+    storage: Arc<dyn StorageBackend>,
+    snapshot_store: Vec<SnapshotEntries>,
+    patch_index: HashMap<String, DiffEntry>,
+    mtimes: HashMap<String, i64>,
+    names: Vec<String>,      // Directory name of every snapshot under /snapshots
+    store_hash: Option<u64>, // Hash of snapshots.json when the stores were last read
+    sizes: HashMap<String, u64>, // Rebuilt size of every patch that was listed so far
+}
+
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::new();
+    let mut position = 0;
+    while position < bytes.len() {
+        if bytes[position] == b'%' && position + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[position + 1..position + 3]).unwrap_or("");
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                position += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[position]);
+        position += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn percent_encode(path: &str) -> String {
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded += &format!("%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn http_date(epoch: i64) -> String {
+    DateTime::from_timestamp(epoch, 0)
+        .unwrap_or_default()
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name, value).expect("Invalid header")
+}
+
+pub(crate) fn header_value<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+fn is_localhost(host: &str) -> bool {
+    // host may still have a port on it
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().map(|name| format!("[{name}]")),
+        None => host.split(':').next().map(|name| name.to_string()),
+    };
+    matches!(name.as_deref(), Some("127.0.0.1" | "[::1]" | "localhost"))
+}
+
+pub(crate) fn check_origin(
+    request: &Request,
+    port: Option<u16>,
+) -> Result<(), (u16, &'static str)> {
+    /* Web pages can send requests to localhost too, either straight from the browser or by pointing their own
+    domain at 127.0.0.1. The Host and Origin checks stop both. port is None on a unix socket, which has no Host.
+    */
+    if let Some(port) = port {
+        let host_allowed = header_value(request, "Host").is_some_and(|host| {
+            ["127.0.0.1", "[::1]", "localhost"]
+                .iter()
+                .any(|name| host == format!("{name}:{port}"))
+        });
+        if !host_allowed {
+            return Err((403, "Host is not allowed"));
+        }
+    }
+    if let Some(origin) = header_value(request, "Origin") {
+        let origin_host = origin
+            .strip_prefix("http://")
+            .or_else(|| origin.strip_prefix("https://"));
+        if !origin_host.is_some_and(is_localhost) {
+            return Err((403, "Origin is not allowed"));
+        }
+    }
+    Ok(())
+}
+
+impl SnapshotTree {
+    pub fn open(
+        folder_path: &str,
+        time_dir: &str,
+        storage: Arc<dyn StorageBackend>,
+    ) -> Result<SnapshotTree, FtmError> {
+        let mut tree = SnapshotTree {
+            folder_path: folder_path.to_string(),
+            time_dir: time_dir.to_string(),
+            storage,
+            snapshot_store: Vec::new(),
+            patch_index: HashMap::new(),
+            mtimes: HashMap::new(),
+            names: Vec::new(),
+            store_hash: None,
+            sizes: HashMap::new(),
+        };
+        tree.refresh()?;
+        Ok(tree)
+    }
+
+    fn refresh(&mut self) -> Result<(), FtmError> {
+        /* Snapshots taken while serving show up without a restart, the stores are only parsed again once they
+        change. The contents are compared instead of the modification time, which not every storage backend has.
+        */
+        let store_hash = xxh3_64(&self.storage.get(SNAPSHOT_STORE)?);
+        if self.store_hash == Some(store_hash) {
+            return Ok(());
+        }
+        debug!("Reading the snapshot and patch stores");
+        self.snapshot_store = snapshot_manager::read_snapshot_store(&*self.storage)?;
+        self.patch_index =
+            restore::index_patch_store(&snapshot_manager::read_patch_store(&*self.storage)?);
+        self.mtimes = export::collect_mtimes(
+            &metadata_manager::read_metadata_from_file(&(self.time_dir.clone() + "/metadata.json"))
+                .unwrap_or_default(),
+        );
+        self.names.clear();
+        let store = self.storage.location(SNAPSHOT_STORE);
+        for (index, entry) in self.snapshot_store.iter().enumerate() {
+            let mut name = snapshot_manager::parse_date(&entry.date_created, &store)?
+                .format("%Y-%m-%dT%H-%M-%S")
+                .to_string();
+            if self.names.contains(&name) {
+                name += &format!("-{}", index + 1); // Two snapshots in the same second
+            }
+            self.names.push(name);
+        }
+        self.store_hash = Some(store_hash);
+        Ok(())
+    }
+
+    fn lookup(&self, url_path: &str) -> Node {
+        let parts: Vec<&str> = url_path
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .collect();
+        if parts.is_empty() {
+            return Node::Root;
+        }
+        if parts[0] != "snapshots" {
+            return Node::NotFound;
+        }
+        if parts.len() == 1 {
+            return Node::SnapshotList;
+        }
+        let Some(snapshot_index) = self.names.iter().position(|name| name == parts[1]) else {
+            return Node::NotFound;
+        };
+        if parts.len() == 2 {
+            return Node::Directory(snapshot_index, self.folder_path.clone());
+        }
+        if parts[2..].iter().any(|part| *part == "." || *part == "..") {
+            return Node::NotFound;
+        }
+        let target_path = self.folder_path.clone() + "/" + &parts[2..].join("/");
+        let entry = &self.snapshot_store[snapshot_index];
+        let exists = entry
+            .target_path
+            .iter()
+            .enumerate()
+            .any(|(position, path)| *path == target_path && entry.patch_ids[position] != "REMOVED");
+        if !exists {
+            return Node::NotFound;
+        }
+        match restore::resolve_version(&self.snapshot_store, snapshot_index, &target_path) {
+            PathVersion::Patch(id) => Node::File(snapshot_index, target_path, id),
+            PathVersion::Directory => Node::Directory(snapshot_index, target_path),
+            PathVersion::Missing => Node::NotFound,
+        }
+    }
+
+    fn children(&self, node: &Node) -> Vec<Child> {
+        let mut children = Vec::new();
+        match node {
+            Node::Root => children.push(Child {
+                name: "snapshots".to_string(),
+                node: Node::SnapshotList,
+            }),
+            Node::SnapshotList => {
+                for (index, name) in self.names.iter().enumerate() {
+                    children.push(Child {
+                        name: name.clone(),
+                        node: Node::Directory(index, self.folder_path.clone()),
+                    });
+                }
+            }
+            Node::Directory(snapshot_index, directory) => {
+                let entry = &self.snapshot_store[*snapshot_index];
+                let mut sorted = BTreeMap::new();
+                for (position, target_path) in entry.target_path.iter().enumerate() {
+                    if entry.patch_ids[position] == "REMOVED" {
+                        continue;
+                    }
+                    if let Some((parent, name)) = target_path.rsplit_once('/') {
+                        if parent == directory {
+                            sorted.insert(name.to_string(), target_path.clone());
+                        }
+                    }
+                }
+                for (name, target_path) in sorted {
+                    let node = match restore::resolve_version(
+                        &self.snapshot_store,
+                        *snapshot_index,
+                        &target_path,
+                    ) {
+                        PathVersion::Patch(id) => Node::File(*snapshot_index, target_path, id),
+                        PathVersion::Directory => Node::Directory(*snapshot_index, target_path),
+                        PathVersion::Missing => continue,
+                    };
+                    children.push(Child { name, node });
+                }
+            }
+            Node::File(..) | Node::NotFound => {}
+        }
+        children
+    }
+
+    fn mtime(&self, node: &Node) -> i64 {
+        let (snapshot_index, target_path, version) = match node {
+            Node::Directory(index, path) => (*index, path, PathVersion::Directory),
+            Node::File(index, path, id) => (*index, path, PathVersion::Patch(id.clone())),
+            _ => {
+                return SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|duration| duration.as_secs() as i64)
+                    .unwrap_or(0)
+            }
+        };
+        export::version_mtime(
+            &self.snapshot_store,
+            &self.patch_index,
+            &self.mtimes,
+            snapshot_index,
+            target_path,
+            &version,
+        )
+    }
+
+    fn rebuild(&mut self, patch_id: &str) -> Result<Vec<u8>, FtmError> {
+        let contents = restore::rebuild_patch(&*self.storage, &self.patch_index, patch_id)?;
+        self.sizes
+            .insert(patch_id.to_string(), contents.len() as u64);
+        Ok(contents)
+    }
+
+    fn propfind_entry(&mut self, href: &str, node: &Node) -> Result<String, FtmError> {
+        // File managers want a size for every file in a listing, which means rebuilding it once
+        let mut properties = format!(
+            "<D:getlastmodified>{}</D:getlastmodified>",
+            http_date(self.mtime(node))
+        );
+        if let Node::File(_, _, id) = node {
+            let size = match self.sizes.get(id) {
+                Some(size) => *size,
+                None => self.rebuild(id)?.len() as u64,
+            };
+            properties += &format!(
+                "<D:resourcetype/><D:getcontentlength>{size}</D:getcontentlength><D:getcontenttype>application/octet-stream</D:getcontenttype>"
+            );
+        } else {
+            properties += "<D:resourcetype><D:collection/></D:resourcetype>";
+        }
+        let name = href.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+        Ok(format!(
+            "<D:response><D:href>{}</D:href><D:propstat><D:prop><D:displayname>{}</D:displayname>{}</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+            escape_xml(&percent_encode(href)),
+            escape_xml(&percent_decode(name)),
+            properties
+        ))
+    }
+
+    fn response(&mut self, request: &Request) -> Result<Response<Cursor<Vec<u8>>>, FtmError> {
+        self.refresh()?;
+        let url_path = percent_decode(request.url().split('?').next().unwrap_or("/"));
+        let node = self.lookup(&url_path);
+        let is_collection = !matches!(node, Node::File(..));
+        let base = if is_collection && !url_path.ends_with('/') {
+            url_path.clone() + "/"
+        } else {
+            url_path.clone()
+        };
+        debug!("{} {}", request.method(), url_path);
+
+        let response = match (request.method(), &node) {
+            (_, Node::NotFound) => Response::from_string("Not found\n").with_status_code(404),
+            (Method::Get | Method::Head, _) if is_collection && base != url_path => {
+                // Relative links in the listing only work from inside the directory
+                Response::from_string("")
+                    .with_status_code(301)
+                    .with_header(header("Location", &percent_encode(&base)))
+            }
+            (Method::Options, _) => Response::from_string("")
+                .with_header(header("DAV", "1"))
+                .with_header(header("Allow", "OPTIONS, GET, HEAD, PROPFIND")),
+            (Method::Get | Method::Head, Node::File(_, _, id)) => {
+                let id = id.clone();
+                Response::from_data(self.rebuild(&id)?)
+                    .with_header(header("Content-Type", "application/octet-stream"))
+                    .with_header(header("Last-Modified", &http_date(self.mtime(&node))))
+            }
+            (Method::Get | Method::Head, _) => {
+                let mut page = format!(
+                    "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head><body><h1>{0}</h1><ul>\n",
+                    escape_xml(&base)
+                );
+                if base != "/" {
+                    page += "<li><a href=\"../\">../</a></li>\n";
+                }
+                for child in self.children(&node) {
+                    let suffix = if matches!(child.node, Node::File(..)) {
+                        ""
+                    } else {
+                        "/"
+                    };
+                    page += &format!(
+                        "<li><a href=\"{}{}\">{}{}</a></li>\n",
+                        escape_xml(&percent_encode(&child.name)),
+                        suffix,
+                        escape_xml(&child.name),
+                        suffix
+                    );
+                }
+                page += "</ul></body></html>\n";
+                Response::from_string(page)
+                    .with_header(header("Content-Type", "text/html; charset=utf-8"))
+            }
+            (Method::NonStandard(method), _) if method.as_str() == "PROPFIND" => {
+                let depth = request
+                    .headers()
+                    .iter()
+                    .find(|header| header.field.equiv("Depth"))
+                    .map(|header| header.value.as_str().to_string())
+                    .unwrap_or("1".to_string());
+                let mut body =
+                    "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">"
+                        .to_string();
+                body += &self.propfind_entry(&base, &node)?;
+                if depth != "0" {
+                    for child in self.children(&node) {
+                        let suffix = if matches!(child.node, Node::File(..)) {
+                            ""
+                        } else {
+                            "/"
+                        };
+                        let href = base.clone() + &child.name + suffix;
+                        body += &self.propfind_entry(&href, &child.node)?;
+                    }
+                }
+                body += "</D:multistatus>\n";
+                Response::from_string(body)
+                    .with_status_code(207)
+                    .with_header(header("Content-Type", "application/xml; charset=utf-8"))
+            }
+            _ => Response::from_string("This server is read-only\n")
+                .with_status_code(405)
+                .with_header(header("Allow", "OPTIONS, GET, HEAD, PROPFIND")),
+        };
+        Ok(response)
+    }
+}
+
+pub fn require_loopback(address: &str) -> Result<(), FtmError> {
+    // Both servers hand out the whole history of the folder, so they never listen beyond this machine
+    for socket_address in address.to_socket_addrs().at(address)? {
+        if !socket_address.ip().is_loopback() {
+            return Err(FtmError::Listen {
+                address: address.to_string(),
+                reason: "not a localhost address".to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+pub fn serve(
+    folder_path: &str,
+    time_dir: &str,
+    storage: Arc<dyn StorageBackend>,
+    listen: &str,
+) -> Result<(), FtmError> {
+    /* Requests are answered one at a time. Files are rebuilt from their patch chain for every request, nothing is
+    ever written to the folder or .time.
+    */
+    require_loopback(listen)?;
+    let mut tree = SnapshotTree::open(folder_path, time_dir, storage)?;
+    let server = Server::http(listen).map_err(|e| FtmError::Listen {
+        address: listen.to_string(),
+        reason: e.to_string(),
+    })?;
+    let port = server.server_addr().to_ip().map(|address| address.port());
+    println!("Serving snapshots of {folder_path} at http://{listen}/snapshots/");
+
+    for request in server.incoming_requests() {
+        if let Err((status, message)) = check_origin(&request, port) {
+            debug!(
+                "Refusing {} {}: {}",
+                request.method(),
+                request.url(),
+                message
+            );
+            if let Err(e) = request
+                .respond(Response::from_string(format!("{message}\n")).with_status_code(status))
+            {
+                eprintln!("Could not send a response: {e}");
+            }
+            continue;
+        }
+        let response = tree.response(&request).unwrap_or_else(|e| {
+            eprintln!("Could not answer a request for {}: {e}", request.url());
+            Response::from_string(format!("{e}\n")).with_status_code(500)
+        });
+        if let Err(e) = request.respond(response) {
+            eprintln!("Could not send a response: {e}");
+        }
+    }
+    Ok(())
+}
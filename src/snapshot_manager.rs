@@ -0,0 +1,225 @@
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::error::{corrupt_store, FtmError};
+use crate::storage::{StorageBackend, PATCH_STORE, SNAPSHOT_STORE};
+use crate::DiffEntry;
+use crate::SnapshotEntries;
+
+#[derive(Debug, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Removed,
+    Unchanged,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Modified => "modified",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Unchanged => "unchanged",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SnapshotSummary {
+    pub index: usize, // Same numbering as restore --restore-index, starting at 1 being oldest
+    pub date_created: String,
+    pub added: usize,
+    pub modified: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+    pub message: String,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum VersionSize {
+    Bytes(u64),
+    Directory,
+    Missing,       // The path was removed in this snapshot
+    Unrecoverable, // Its patches are missing or corrupt, ftm verify says which
+}
+
+#[derive(Debug)]
+pub struct LogEntry {
+    pub snapshot_index: usize, // Starts at 0, unlike SnapshotSummary
+    pub date_created: String,
+    pub change: ChangeKind, // Added, Modified or Removed, snapshots that left the path alone are skipped
+    pub size: VersionSize,
+}
+
+pub fn read_snapshot_store(storage: &dyn StorageBackend) -> Result<Vec<SnapshotEntries>, FtmError> {
+    // An empty file is a valid store, it is created before the first snapshot is written.
+    let contents = storage.get(SNAPSHOT_STORE)?;
+    if contents.is_empty() {
+        return Ok(Vec::new());
+    }
+    let snapshot_store: Vec<SnapshotEntries> = serde_json::from_slice(&contents)
+        .map_err(|e| corrupt_store(&storage.location(SNAPSHOT_STORE), e))?;
+
+    Ok(snapshot_store)
+}
+
+pub fn write_snapshot_store(
+    snapshot_store: &[SnapshotEntries],
+    storage: &dyn StorageBackend,
+) -> Result<(), FtmError> {
+    let json = serde_json::to_string_pretty(snapshot_store)
+        .map_err(|e| corrupt_store(&storage.location(SNAPSHOT_STORE), e))?;
+    storage.replace(SNAPSHOT_STORE, json.as_bytes())
+}
+
+pub fn read_patch_store(storage: &dyn StorageBackend) -> Result<Vec<DiffEntry>, FtmError> {
+    let contents = storage.get(PATCH_STORE)?;
+    if contents.is_empty() {
+        return Ok(Vec::new());
+    }
+    let patch_store: Vec<DiffEntry> = serde_json::from_slice(&contents)
+        .map_err(|e| corrupt_store(&storage.location(PATCH_STORE), e))?;
+
+    Ok(patch_store)
+}
+
+pub fn write_patch_store(
+    patch_store: &[DiffEntry],
+    storage: &dyn StorageBackend,
+) -> Result<(), FtmError> {
+    let json = serde_json::to_string_pretty(patch_store)
+        .map_err(|e| corrupt_store(&storage.location(PATCH_STORE), e))?;
+    storage.replace(PATCH_STORE, json.as_bytes())
+}
+
+pub fn resolve_target_path(folder_path: &str, path: &str) -> String {
+    // Stored paths are always folder_path joined with the relative path, so accept either form from the user.
+    let path = path.trim_end_matches('/');
+    if path.starts_with(&(folder_path.to_string() + "/")) {
+        path.to_string()
+    } else {
+        folder_path.to_string() + "/" + path.trim_start_matches("./")
+    }
+}
+
+pub fn relative_path<'a>(folder_path: &str, target_path: &'a str) -> &'a str {
+    target_path
+        .strip_prefix(folder_path)
+        .map(|path| path.trim_start_matches('/'))
+        .unwrap_or(target_path)
+}
+
+pub fn parse_date(date_created: &str, store: &str) -> Result<DateTime<FixedOffset>, FtmError> {
+    // store is where the date was read from, so a corrupt one can be tracked down
+    DateTime::parse_from_str(date_created, "%Y-%m-%d %H:%M:%S%.9f %z")
+        .map_err(|e| corrupt_store(store, format!("{date_created} is not a valid date: {e}")))
+}
+
+pub fn pretty_date(date_created: &str, store: &str) -> Result<String, FtmError> {
+    Ok(parse_date(date_created, store)?
+        .format("%Y-%m-%d %H:%M:%S %z")
+        .to_string())
+}
+
+pub fn parse_user_date(date: &str) -> Option<DateTime<Local>> {
+    // Dates given on the command line, in local time. A date without a time means the start of that day.
+    let date = date.trim();
+    let naive = NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S"))
+        .or_else(|_| NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M"))
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .ok()
+                .and_then(|day| day.and_hms_opt(0, 0, 0))
+        })?;
+    naive.and_local_timezone(Local).earliest()
+}
+
+pub fn describe_label(entry: &SnapshotEntries) -> String {
+    // Message and tags in a form that can be appended to a line describing the snapshot
+    let mut label = String::new();
+    if !entry.message.is_empty() {
+        label += &format!("  \"{}\"", entry.message);
+    }
+    if !entry.tags.is_empty() {
+        label += &format!("  [{}]", entry.tags.join(", "));
+    }
+    label
+}
+
+fn is_directory(patch_id: &str) -> bool {
+    patch_id == "DIR" || patch_id == "UNMODIFIED_DIRECTORY"
+}
+
+pub fn classify_entries(
+    previous: Option<&SnapshotEntries>,
+    entry: &SnapshotEntries,
+) -> Vec<ChangeKind> {
+    /* Every snapshot lists every tracked path, so a path is only new if the previous snapshot did not have it
+    (or had it marked as removed). A file that comes back after being removed gets a regular patch instead of a
+    First patch, so ref_patch_ids alone can't tell us this. A directory that stays a directory is unchanged,
+    like in compare_snapshots, its mtime only changes because something inside it did.
+    */
+    let mut previous_paths = HashMap::new();
+    if let Some(previous) = previous {
+        for (index, path) in previous.target_path.iter().enumerate() {
+            if previous.patch_ids[index] != "REMOVED" {
+                previous_paths.insert(path.as_str(), is_directory(&previous.patch_ids[index]));
+            }
+        }
+    }
+
+    let mut kinds = Vec::new();
+    for (index, id) in entry.patch_ids.iter().enumerate() {
+        if id == "REMOVED" {
+            kinds.push(ChangeKind::Removed);
+        } else if !entry.modified[index] {
+            kinds.push(ChangeKind::Unchanged);
+        } else {
+            match previous_paths.get(entry.target_path[index].as_str()) {
+                None => kinds.push(ChangeKind::Added),
+                Some(true) if is_directory(id) => kinds.push(ChangeKind::Unchanged),
+                Some(_) => kinds.push(ChangeKind::Modified),
+            }
+        }
+    }
+    kinds
+}
+
+pub fn summarize_snapshots(snapshot_store: &[SnapshotEntries]) -> Vec<SnapshotSummary> {
+    let mut summaries = Vec::new();
+    for (index, entry) in snapshot_store.iter().enumerate() {
+        let previous = if index > 0 {
+            Some(&snapshot_store[index - 1])
+        } else {
+            None
+        };
+        let mut summary = SnapshotSummary {
+            index: index + 1,
+            date_created: entry.date_created.clone(),
+            added: 0,
+            modified: 0,
+            removed: 0,
+            unchanged: 0,
+            message: entry.message.clone(),
+            tags: entry.tags.clone(),
+        };
+        for kind in classify_entries(previous, entry) {
+            match kind {
+                ChangeKind::Added => summary.added += 1,
+                ChangeKind::Modified => summary.modified += 1,
+                ChangeKind::Removed => summary.removed += 1,
+                ChangeKind::Unchanged => summary.unchanged += 1,
+            }
+        }
+        summaries.push(summary);
+    }
+    summaries
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::error::{FtmError, IoContext};
+use crate::s3::S3Storage;
+use crate::Config;
+
+pub const PATCH_STORE: &str = "patches.json";
+pub const SNAPSHOT_STORE: &str = "snapshots.json";
+
+#[derive(Debug, Clone)]
+pub struct StoredObject {
+    pub name: String, // A patch ID, ID-reverse or one of the stores
+    pub size: u64,
+}
+
+pub trait StorageBackend: Send + Sync {
+    // Objects are named like the files in .time, so a patch is stored as ID and ID-reverse
+    fn get(&self, name: &str) -> Result<Vec<u8>, FtmError>;
+    fn put(&self, name: &str, contents: &[u8]) -> Result<(), FtmError>;
+    fn replace(&self, name: &str, contents: &[u8]) -> Result<(), FtmError>; // Readers see either the old or the new contents, never a mix
+    fn delete(&self, name: &str) -> Result<(), FtmError>;
+    fn list(&self) -> Result<Vec<StoredObject>, FtmError>;
+    fn exists(&self, name: &str) -> Result<bool, FtmError>;
+    fn location(&self, name: &str) -> String; // Where name is kept, for error messages
+}
+
+pub struct LocalStorage {
+    root: String,
+}
+
+impl LocalStorage {
+    pub fn new(root: &str) -> LocalStorage {
+        LocalStorage {
+            root: root.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+impl StorageBackend for LocalStorage {
+    fn get(&self, name: &str) -> Result<Vec<u8>, FtmError> {
+        let path = self.location(name);
+        fs::read(&path).at(&path)
+    }
+
+    fn put(&self, name: &str, contents: &[u8]) -> Result<(), FtmError> {
+        let path = self.location(name);
+        fs::write(&path, contents).at(&path)
+    }
+
+    fn replace(&self, name: &str, contents: &[u8]) -> Result<(), FtmError> {
+        // Write next to the target and rename over it, so a crash never leaves a half written store behind
+        let path = self.location(name);
+        let tmp_path = path.clone() + ".tmp";
+        fs::write(&tmp_path, contents).at(&tmp_path)?;
+        fs::rename(&tmp_path, &path).at(&path)
+    }
+
+    fn delete(&self, name: &str) -> Result<(), FtmError> {
+        let path = self.location(name);
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result.at(&path),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<StoredObject>, FtmError> {
+        let mut objects = Vec::new();
+        if !Path::new(&self.root).exists() {
+            return Ok(objects);
+        }
+        for file in fs::read_dir(&self.root).at(&self.root)? {
+            let file = file.at(&self.root)?;
+            let metadata = file.metadata().at(&file.path().display().to_string())?;
+            if metadata.is_file() {
+                objects.push(StoredObject {
+                    name: file.file_name().to_string_lossy().to_string(),
+                    size: metadata.len(),
+                });
+            }
+        }
+        Ok(objects)
+    }
+
+    fn exists(&self, name: &str) -> Result<bool, FtmError> {
+        Ok(Path::new(&self.location(name)).is_file())
+    }
+
+    fn location(&self, name: &str) -> String {
+        self.root.clone() + "/" + name
+    }
+}
+
+pub fn open(config: &Config) -> Result<Arc<dyn StorageBackend>, FtmError> {
+    // Patches and stores go to the bucket if the config has one, otherwise they stay in .time
+    match &config.s3 {
+        Some(s3_config) => Ok(Arc::new(S3Storage::new(s3_config)?)),
+        None => Ok(Arc::new(LocalStorage::new(&config.time_dir()))),
+    }
+}
+
+pub fn sizes(storage: &dyn StorageBackend) -> Result<HashMap<String, u64>, FtmError> {
+    // One listing instead of asking for every object, which matters once every request is a round trip
+    Ok(storage
+        .list()?
+        .into_iter()
+        .map(|object| (object.name, object.size))
+        .collect())
+}
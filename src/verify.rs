@@ -0,0 +1,110 @@
+use log::debug;
+use std::collections::HashMap;
+
+use crate::compression;
+use crate::restore::{self, PathVersion};
+use crate::storage::StorageBackend;
+use crate::DiffEntry;
+use crate::SnapshotEntries;
+
+#[derive(Debug)]
+pub struct Problem {
+    pub snapshot: Option<usize>, // Index into the snapshot store, None if the problem is only in the patch store
+    pub path: String,
+    pub reason: String,
+}
+
+pub fn verify_patches(storage: &dyn StorageBackend, patch_store: &[DiffEntry]) -> Vec<Problem> {
+    // Every patch needs both of its files, and both need to decompress. A reverse patch can be the ":3" placebo.
+    let mut problems = Vec::new();
+    for patch_entry in patch_store.iter() {
+        let patch_id = restore::patch_id_of(patch_entry);
+        for file_name in [patch_id.clone(), patch_id.clone() + "-reverse"] {
+            let patch_file_compressed = match storage.get(&file_name) {
+                Ok(contents) => contents,
+                Err(e) if e.is_not_found() => {
+                    problems.push(Problem {
+                        snapshot: None,
+                        path: patch_entry.target_path.clone(),
+                        reason: format!("patch file {file_name} is missing"),
+                    });
+                    continue;
+                }
+                Err(e) => {
+                    problems.push(Problem {
+                        snapshot: None,
+                        path: patch_entry.target_path.clone(),
+                        reason: format!("patch file {file_name} could not be read: {e}"),
+                    });
+                    continue;
+                }
+            };
+            if patch_file_compressed == [58, 51] {
+                debug!("{} is a fake patch, skipping", file_name);
+                continue;
+            }
+            if compression::decompress_data(patch_file_compressed).is_err() {
+                problems.push(Problem {
+                    snapshot: None,
+                    path: patch_entry.target_path.clone(),
+                    reason: format!("patch file {file_name} does not decompress"),
+                });
+            }
+        }
+    }
+    problems
+}
+
+pub fn verify_snapshots(
+    storage: &dyn StorageBackend,
+    snapshot_store: &[SnapshotEntries],
+    patch_index: &HashMap<String, DiffEntry>,
+    deep: bool,
+) -> Vec<Problem> {
+    /* Check that every patch a snapshot points to is in the patch store. With deep, also rebuild every version of
+    every file. Versions are shared between snapshots, so each patch chain is only rebuilt once.
+    */
+    let mut problems = Vec::new();
+    let mut rebuilt: HashMap<String, Option<String>> = HashMap::new();
+
+    for (index, entry) in snapshot_store.iter().enumerate() {
+        for (position, target_path) in entry.target_path.iter().enumerate() {
+            let id = &entry.patch_ids[position];
+            if entry.modified[position] && id.len() == 64 && !patch_index.contains_key(id) {
+                problems.push(Problem {
+                    snapshot: Some(index),
+                    path: target_path.clone(),
+                    reason: format!("patch {id} is not in the patch store"),
+                });
+                continue;
+            }
+            if !deep || id == "REMOVED" {
+                continue;
+            }
+            match restore::resolve_version(snapshot_store, index, target_path) {
+                PathVersion::Patch(patch_id) => {
+                    let result = rebuilt.entry(patch_id.clone()).or_insert_with(|| {
+                        debug!("Rebuilding {} from {}", target_path, patch_id);
+                        restore::rebuild_patch(storage, patch_index, &patch_id)
+                            .err()
+                            .map(|e| e.to_string())
+                    });
+                    if let Some(e) = result {
+                        problems.push(Problem {
+                            snapshot: Some(index),
+                            path: target_path.clone(),
+                            reason: format!("could not be rebuilt from patch {patch_id}: {e}"),
+                        });
+                    }
+                }
+                PathVersion::Directory => {}
+                PathVersion::Missing => problems.push(Problem {
+                    snapshot: Some(index),
+                    path: target_path.clone(),
+                    reason: "no earlier snapshot has a version of this file".to_string(),
+                }),
+            }
+        }
+    }
+    problems
+}
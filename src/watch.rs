@@ -0,0 +1,102 @@
+use chrono::Local;
+use log::debug;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use crate::diffs;
+use crate::error::FtmError;
+use crate::progress::{Progress, ProgressSink};
+use crate::Repository;
+
+fn is_change(event: &notify::Event, folder_path: &str, time_dir: &str) -> bool {
+    // Reading a file is not a change, and neither is anything under a path snapshots skip, like .time or .git
+    !matches!(event.kind, EventKind::Access(_))
+        && event.paths.iter().any(|path| {
+            path.to_str()
+                .is_some_and(|path| diffs::is_tracked(path, folder_path, time_dir))
+        })
+}
+
+pub fn run(
+    repository: &Repository,
+    quiet_period: Duration,
+    max_wait: Duration,
+    on_progress: &dyn ProgressSink,
+) -> Result<(), FtmError> {
+    /* Changes usually come in bursts, like an editor saving several files or a build writing its output. A
+    snapshot is taken once nothing has changed for the quiet period, or once the first unsaved change is max_wait
+    old, so a folder that never settles still gets snapshots.
+    */
+    let folder_path = repository.folder_path();
+    let time_dir = repository.time_dir();
+    let (sender, receiver) = mpsc::channel();
+    let watch_error = |reason: String| FtmError::Watch {
+        path: folder_path.to_string(),
+        reason,
+    };
+    let mut watcher =
+        notify::recommended_watcher(sender).map_err(|e| watch_error(e.to_string()))?;
+    watcher
+        .watch(Path::new(folder_path), RecursiveMode::Recursive)
+        .map_err(|e| watch_error(e.to_string()))?;
+    on_progress.event(Progress::Message {
+        text: format!(
+            "Watching {folder_path}, snapshots are taken after {} seconds without changes or at most every {} minutes.",
+            quiet_period.as_secs(),
+            max_wait.as_secs() / 60
+        ),
+    });
+
+    let mut first_change: Option<Instant> = None;
+    let mut last_change = Instant::now();
+    loop {
+        let timeout = match first_change {
+            Some(first_change) => {
+                let now = Instant::now();
+                (last_change + quiet_period)
+                    .min(first_change + max_wait)
+                    .saturating_duration_since(now)
+            }
+            None => Duration::from_secs(60 * 60), // Nothing to do until something changes
+        };
+        match receiver.recv_timeout(timeout) {
+            Ok(Ok(event)) => {
+                if is_change(&event, folder_path, &time_dir) {
+                    debug!("{:?} {:?}", event.kind, event.paths);
+                    last_change = Instant::now();
+                    first_change.get_or_insert(last_change);
+                }
+                continue;
+            }
+            Ok(Err(e)) => {
+                // Usually the kernel dropping events, the next snapshot still finds every change
+                eprintln!("Watch error: {e}");
+                first_change.get_or_insert(Instant::now());
+                last_change = Instant::now();
+                continue;
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Err(watch_error(
+                    "the file watcher stopped unexpectedly".to_string(),
+                ))
+            }
+        }
+        if first_change.is_none() {
+            continue;
+        }
+        first_change = None;
+
+        on_progress.event(Progress::Message {
+            text: format!(
+                "{}: taking a snapshot",
+                Local::now().format("%Y-%m-%d %H:%M:%S")
+            ),
+        });
+        if let Err(e) = repository.snapshot("", &[], on_progress) {
+            eprintln!("Snapshot failed, trying again after the next change: {e}");
+        }
+    }
+}
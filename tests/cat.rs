@@ -0,0 +1,57 @@
+// Reads single files back from snapshots with Repository::read_file and ftm cat.
+use file_time_machine::progress::Silent;
+use file_time_machine::restore::FileVersion;
+
+mod common;
+use common::TestFolder;
+
+#[test]
+fn reads_every_version_without_touching_the_folder() {
+    let test_folder = TestFolder::new("cat-versions");
+    let repository = test_folder.repository();
+    test_folder.write("a.txt", "first version\n");
+    test_folder.write("sub/b.txt", "in a folder\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+    test_folder.write("a.txt", "first version\nsecond line\n");
+    test_folder.remove("sub/b.txt");
+    repository.snapshot("", &[], &Silent).unwrap();
+    test_folder.write("a.txt", "not snapshotted\n");
+
+    let read = |index, path| match repository.read_file(index, path).unwrap() {
+        FileVersion::File(contents) => Some(String::from_utf8(contents).unwrap()),
+        _ => None,
+    };
+    assert_eq!(read(0, "a.txt").unwrap(), "first version\n");
+    assert_eq!(read(1, "a.txt").unwrap(), "first version\nsecond line\n");
+    assert_eq!(read(0, "sub/b.txt").unwrap(), "in a folder\n");
+    assert!(matches!(
+        repository.read_file(1, "sub/b.txt").unwrap(),
+        FileVersion::Missing
+    ));
+    assert!(matches!(
+        repository.read_file(0, "sub").unwrap(),
+        FileVersion::Directory
+    ));
+    assert_eq!(
+        std::fs::read_to_string(test_folder.folder().join("a.txt")).unwrap(),
+        "not snapshotted\n"
+    );
+}
+
+#[test]
+fn prints_the_file_and_fails_on_a_missing_one() {
+    let test_folder = TestFolder::new("cat-cli");
+    let repository = test_folder.repository();
+    test_folder.write("a.txt", "first version\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+
+    let output = test_folder.ftm(
+        &[test_folder.config()],
+        &["cat", "--snapshot", "1", "a.txt"],
+    );
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"first version\n");
+
+    let output = test_folder.ftm(&[test_folder.config()], &["cat", "missing.txt"]);
+    assert!(!output.status.success());
+}
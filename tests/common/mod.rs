@@ -0,0 +1,100 @@
+// Shared by the integration tests, each of them uses a different part of it
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::time::{SystemTime, UNIX_EPOCH};
+use walkdir::WalkDir;
+
+use file_time_machine::{Config, Repository};
+
+pub type Tree = BTreeMap<String, Option<Vec<u8>>>; // Relative path to contents, None for directories
+
+pub struct TestFolder {
+    pub root: PathBuf,
+}
+
+impl TestFolder {
+    pub fn new(name: &str) -> TestFolder {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let root = std::env::temp_dir().join(format!("ftm-{name}-{}-{nanos}", std::process::id()));
+        fs::create_dir_all(root.join("folder")).unwrap();
+        TestFolder { root }
+    }
+
+    pub fn folder(&self) -> PathBuf {
+        self.root.join("folder")
+    }
+
+    pub fn config(&self) -> Config {
+        Config {
+            name: String::new(),
+            folder_path: self.folder().to_str().unwrap().to_string(),
+            get_hashes: false,
+            thread_count: 1,
+            brotli_compression_level: 5,
+            snapshot_mode: "fastest".to_string(),
+            its_my_fault_if_i_lose_data: true,
+            schedule: String::new(),
+            repository_path: String::new(),
+            s3: None,
+        }
+    }
+
+    pub fn repository(&self) -> Repository {
+        Repository::init(self.config()).unwrap()
+    }
+
+    pub fn write(&self, path: &str, contents: &str) {
+        let path = self.folder().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    pub fn remove(&self, path: &str) {
+        fs::remove_file(self.folder().join(path)).unwrap();
+    }
+
+    pub fn ftm(&self, configs: &[Config], args: &[&str]) -> Output {
+        // Runs the ftm binary with a config file holding configs, like a user would
+        let conf_path = self.root.join("config.json");
+        fs::write(&conf_path, serde_json::to_string(configs).unwrap()).unwrap();
+        Command::new(env!("CARGO_BIN_EXE_ftm"))
+            .arg("--config")
+            .arg(&conf_path)
+            .arg("--progress")
+            .arg("none")
+            .args(args)
+            .output()
+            .unwrap()
+    }
+}
+
+impl Drop for TestFolder {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+pub fn read_tree(folder: &Path) -> Tree {
+    let mut tree = Tree::new();
+    for entry in WalkDir::new(folder).min_depth(1) {
+        let entry = entry.unwrap();
+        let relative = entry.path().strip_prefix(folder).unwrap();
+        if relative.starts_with(".time") {
+            continue;
+        }
+        let contents = if entry.file_type().is_file() {
+            Some(fs::read(entry.path()).unwrap())
+        } else {
+            None
+        };
+        tree.insert(relative.to_str().unwrap().to_string(), contents);
+    }
+    tree
+}
@@ -0,0 +1,74 @@
+// Charges every patch file to the snapshot that created it, checked against what is actually in storage.
+use file_time_machine::progress::Silent;
+use file_time_machine::{storage, Repository};
+
+mod common;
+use common::TestFolder;
+
+fn take_snapshots(test_folder: &TestFolder, repository: &Repository) -> Vec<u8> {
+    // Returns snapshots.json after the first snapshot, so a test can roll back the other two
+    test_folder.write("a.txt", "first version\n");
+    test_folder.write("small.txt", "small\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+    let first = repository.storage().get("snapshots.json").unwrap();
+    test_folder.write("small.txt", "small, then changed\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+    test_folder.write("new.txt", "added in three\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+    first
+}
+
+fn patch_bytes(repository: &Repository) -> u64 {
+    storage::sizes(repository.storage())
+        .unwrap()
+        .iter()
+        .filter(|(name, _)| name.len() == 64 || name.ends_with("-reverse"))
+        .map(|(_, size)| size)
+        .sum()
+}
+
+#[test]
+fn every_patch_byte_is_charged_once() {
+    let test_folder = TestFolder::new("du-charges");
+    let repository = test_folder.repository();
+    take_snapshots(&test_folder, &repository);
+    let usage = repository.usage().unwrap();
+
+    assert_eq!(usage.snapshots.len(), 3);
+    assert!(usage.snapshots[0].first > 0);
+    assert_eq!(usage.snapshots[0].forward, 0);
+    assert_eq!(usage.snapshots[1].first, 0); // small.txt only changed, so it got a forward patch
+    assert!(usage.snapshots[1].forward > 0);
+    assert!(usage.snapshots[2].first > 0);
+    assert_eq!(usage.unreferenced.total(), 0);
+    assert!(usage.stores > 0);
+
+    let by_snapshot: u64 = usage.snapshots.iter().map(|sizes| sizes.total()).sum();
+    let by_path: u64 = usage.paths.iter().map(|(_, sizes)| sizes.total()).sum();
+    assert_eq!(by_snapshot, patch_bytes(&repository));
+    assert_eq!(by_path, by_snapshot);
+    assert!(usage
+        .paths
+        .windows(2)
+        .all(|pair| pair[0].1.total() >= pair[1].1.total())); // Largest first
+}
+
+#[test]
+fn patches_no_snapshot_lists_are_unreferenced() {
+    let test_folder = TestFolder::new("du-unreferenced");
+    let repository = test_folder.repository();
+    let first = take_snapshots(&test_folder, &repository);
+    repository
+        .storage()
+        .replace("snapshots.json", &first)
+        .unwrap();
+    let usage = repository.usage().unwrap();
+
+    assert_eq!(usage.snapshots.len(), 1);
+    assert!(usage.unreferenced.forward > 0);
+    assert!(usage.unreferenced.first > 0);
+    assert_eq!(
+        usage.snapshots[0].total() + usage.unreferenced.total(),
+        patch_bytes(&repository)
+    );
+}
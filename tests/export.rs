@@ -0,0 +1,130 @@
+// Exports snapshots to every archive format and reads them back.
+use std::io::{Cursor, Read};
+
+use flate2::read::GzDecoder;
+
+use file_time_machine::export::ArchiveFormat;
+use file_time_machine::progress::Silent;
+use file_time_machine::Repository;
+
+mod common;
+use common::{TestFolder, Tree};
+
+fn take_snapshots(test_folder: &TestFolder, repository: &Repository) {
+    test_folder.write("a.txt", "first version\n");
+    test_folder.write("sub/b.txt", "in a folder\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+    test_folder.write("a.txt", "first version\nsecond line\n");
+    test_folder.remove("sub/b.txt");
+    repository.snapshot("", &[], &Silent).unwrap();
+}
+
+fn export(repository: &Repository, snapshot_index: usize, format: &str) -> Vec<u8> {
+    let mut archive = Vec::new();
+    repository
+        .export(
+            snapshot_index,
+            &ArchiveFormat::parse(format).unwrap(),
+            &mut archive,
+        )
+        .unwrap();
+    archive
+}
+
+fn read_tar(archive: impl Read) -> Tree {
+    let mut tree = Tree::new();
+    for entry in tar::Archive::new(archive).entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let path = entry.path().unwrap().to_str().unwrap().to_string();
+        if entry.header().entry_type().is_dir() {
+            tree.insert(path.trim_end_matches('/').to_string(), None);
+        } else {
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents).unwrap();
+            tree.insert(path, Some(contents));
+        }
+    }
+    tree
+}
+
+fn read_zip(archive: Vec<u8>) -> Tree {
+    let mut tree = Tree::new();
+    let mut archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index).unwrap();
+        let name = file.name().to_string();
+        if file.is_dir() {
+            tree.insert(name.trim_end_matches('/').to_string(), None);
+        } else {
+            let mut contents = Vec::new();
+            file.read_to_end(&mut contents).unwrap();
+            tree.insert(name, Some(contents));
+        }
+    }
+    tree
+}
+
+fn expected(files: &[(&str, Option<&str>)]) -> Tree {
+    files
+        .iter()
+        .map(|(path, contents)| {
+            (
+                path.to_string(),
+                contents.map(|contents| contents.as_bytes().to_vec()),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn every_format_has_the_snapshot_as_it_was() {
+    let test_folder = TestFolder::new("export-formats");
+    let repository = test_folder.repository();
+    take_snapshots(&test_folder, &repository);
+    let first = expected(&[
+        ("a.txt", Some("first version\n")),
+        ("sub", None),
+        ("sub/b.txt", Some("in a folder\n")),
+    ]);
+    let second = expected(&[
+        ("a.txt", Some("first version\nsecond line\n")),
+        ("sub", None),
+    ]);
+
+    assert_eq!(read_tar(&export(&repository, 0, "tar")[..]), first);
+    assert_eq!(read_tar(&export(&repository, 1, "tar")[..]), second);
+    assert_eq!(
+        read_tar(GzDecoder::new(&export(&repository, 0, "tar.gz")[..])),
+        first
+    );
+    assert_eq!(read_zip(export(&repository, 0, "zip")), first);
+    assert_eq!(read_zip(export(&repository, 1, "zip")), second);
+}
+
+#[test]
+fn directories_get_the_time_of_their_snapshot() {
+    let test_folder = TestFolder::new("export-mtimes");
+    let repository = test_folder.repository();
+    take_snapshots(&test_folder, &repository);
+    let snapshots = repository.snapshots().unwrap();
+    let taken =
+        chrono::DateTime::parse_from_str(&snapshots[0].date_created, "%Y-%m-%d %H:%M:%S%.9f %z")
+            .unwrap()
+            .timestamp() as u64;
+
+    let archive = export(&repository, 0, "tar");
+    let directory_mtimes: Vec<u64> = tar::Archive::new(&archive[..])
+        .entries()
+        .unwrap()
+        .map(|entry| entry.unwrap().header().clone())
+        .filter(|header| header.entry_type().is_dir())
+        .map(|header| header.mtime().unwrap())
+        .collect();
+    assert_eq!(directory_mtimes, vec![taken]); // Only sub
+}
+
+#[test]
+fn unknown_formats_are_refused() {
+    assert!(ArchiveFormat::parse("rar").is_err());
+    assert_eq!(ArchiveFormat::parse("tgz").unwrap().extension(), "tar.gz");
+}
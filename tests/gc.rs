@@ -0,0 +1,97 @@
+// Leaves the kind of mess an interrupted snapshot would, then collects it with find_garbage and remove_garbage.
+use std::fs::File;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use file_time_machine::progress::Silent;
+use file_time_machine::Repository;
+
+mod common;
+use common::TestFolder;
+
+fn take_snapshots(test_folder: &TestFolder, repository: &Repository) -> (Vec<u8>, Vec<u8>) {
+    // Returns snapshots.json after the first and the second snapshot
+    test_folder.write("a.txt", "first version\n");
+    test_folder.write("b.txt", "never changes\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+    let first = repository.storage().get("snapshots.json").unwrap();
+    test_folder.write("a.txt", "first version\nsecond line\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+    let second = repository.storage().get("snapshots.json").unwrap();
+    (first, second)
+}
+
+#[test]
+fn only_what_no_snapshot_needs_is_removed() {
+    let test_folder = TestFolder::new("gc-remove");
+    let repository = test_folder.repository();
+    let (first, _) = take_snapshots(&test_folder, &repository);
+    // Like a snapshot that wrote its patches and the patch store, but died before the snapshot store
+    repository
+        .storage()
+        .replace("snapshots.json", &first)
+        .unwrap();
+    let stray = "0B0F9FED27D10947FF7FB07D082F4DFA314EB732AE5AC17FC8C16CB6FEC89D32";
+    repository.storage().put(stray, b"not a patch").unwrap();
+    repository.storage().put("patches.json.tmp", b"[]").unwrap();
+
+    let garbage = repository.find_garbage().unwrap();
+    assert_eq!(garbage.dangling_files, vec![stray.to_string()]);
+    assert_eq!(garbage.stale_files, vec!["patches.json.tmp".to_string()]);
+    assert_eq!(garbage.unreferenced_patches.len(), 1);
+    assert!(garbage.unreferenced_patches[0]
+        .target_path
+        .ends_with("/a.txt"));
+
+    assert!(repository.remove_garbage(&garbage).unwrap() > 0);
+    let garbage = repository.find_garbage().unwrap();
+    assert!(garbage.dangling_files.is_empty());
+    assert!(garbage.unreferenced_patches.is_empty());
+    assert!(garbage.stale_files.is_empty());
+    // Everything the remaining snapshot needs is still there
+    assert!(repository.verify(true, &Silent).unwrap().is_empty());
+}
+
+#[test]
+fn patches_of_a_snapshot_finished_after_find_garbage_are_kept() {
+    let test_folder = TestFolder::new("gc-in-flight");
+    let repository = test_folder.repository();
+    let (first, second) = take_snapshots(&test_folder, &repository);
+    repository
+        .storage()
+        .replace("snapshots.json", &first)
+        .unwrap();
+    let garbage = repository.find_garbage().unwrap();
+    assert_eq!(garbage.unreferenced_patches.len(), 1);
+
+    // The snapshot was only in flight, it finishes before gc gets to remove anything
+    repository
+        .storage()
+        .replace("snapshots.json", &second)
+        .unwrap();
+    assert_eq!(repository.remove_garbage(&garbage).unwrap(), 0);
+    assert!(repository.verify(true, &Silent).unwrap().is_empty());
+}
+
+#[test]
+fn waits_for_the_lock_a_snapshot_holds() {
+    let test_folder = TestFolder::new("gc-lock");
+    let repository = test_folder.repository();
+    take_snapshots(&test_folder, &repository);
+    let lock = File::options()
+        .write(true)
+        .open(repository.time_dir() + "/lock")
+        .unwrap();
+    lock.lock().unwrap();
+
+    let (sender, receiver) = mpsc::channel();
+    let config = test_folder.config();
+    thread::spawn(move || {
+        let repository = Repository::open(config).unwrap();
+        sender.send(repository.find_garbage().is_ok()).unwrap();
+    });
+    assert!(receiver.recv_timeout(Duration::from_millis(300)).is_err());
+    drop(lock);
+    assert!(receiver.recv_timeout(Duration::from_secs(10)).unwrap());
+}
@@ -0,0 +1,99 @@
+// Searches every version of every file with Repository::grep and ftm grep.
+use chrono::{Duration, Local};
+use regex::Regex;
+
+use file_time_machine::progress::Silent;
+use file_time_machine::search::Match;
+use file_time_machine::Repository;
+
+mod common;
+use common::TestFolder;
+
+fn take_snapshots(test_folder: &TestFolder, repository: &Repository) {
+    // keep.txt never changes, a.txt gets a new line in every snapshot
+    test_folder.write("keep.txt", "needle in every snapshot\n");
+    test_folder.write("a.txt", "one\n");
+    test_folder.write("sub/b.txt", "needle in a folder\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+    test_folder.write("a.txt", "one\nneedle two\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+    test_folder.write("a.txt", "one\nneedle two\nthree\n");
+    std::fs::write(test_folder.folder().join("binary"), b"needle\0").unwrap();
+    repository.snapshot("", &[], &Silent).unwrap();
+}
+
+fn summarize(repository: &Repository, matches: &[Match]) -> Vec<(Vec<usize>, String, usize)> {
+    matches
+        .iter()
+        .map(|found| {
+            (
+                found.snapshot_indexes.clone(),
+                found.target_path[repository.folder_path().len() + 1..].to_string(),
+                found.line_number,
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn every_version_is_searched_once() {
+    let test_folder = TestFolder::new("grep-versions");
+    let repository = test_folder.repository();
+    take_snapshots(&test_folder, &repository);
+    let regex = Regex::new("needle").unwrap();
+
+    let matches = repository.grep(&regex, &[], None).unwrap();
+    assert_eq!(
+        summarize(&repository, &matches),
+        vec![
+            (vec![0, 1, 2], "keep.txt".to_string(), 1),
+            (vec![0, 1, 2], "sub/b.txt".to_string(), 1),
+            (vec![1], "a.txt".to_string(), 2),
+            (vec![2], "a.txt".to_string(), 2), // A new version of a.txt, even if the line is the same
+        ]
+    );
+    assert_eq!(matches[2].line, "needle two");
+}
+
+#[test]
+fn paths_and_since_narrow_the_search() {
+    let test_folder = TestFolder::new("grep-filters");
+    let repository = test_folder.repository();
+    take_snapshots(&test_folder, &repository);
+    let regex = Regex::new("needle").unwrap();
+
+    let matches = repository.grep(&regex, &["sub".to_string()], None).unwrap();
+    assert_eq!(
+        summarize(&repository, &matches),
+        vec![(vec![0, 1, 2], "sub/b.txt".to_string(), 1)]
+    );
+
+    let later = Local::now() + Duration::days(1);
+    assert!(repository
+        .grep(&regex, &[], Some(later))
+        .unwrap()
+        .is_empty());
+    assert!(repository.grep(&regex, &["[".to_string()], None).is_err());
+}
+
+#[test]
+fn prints_the_first_and_last_snapshot_of_each_match() {
+    let test_folder = TestFolder::new("grep-cli");
+    let repository = test_folder.repository();
+    take_snapshots(&test_folder, &repository);
+    let configs = [test_folder.config()];
+
+    let output = test_folder.ftm(&configs, &["grep", "--path", "*.txt", "needle"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "1-3:keep.txt:needle in every snapshot\n\
+         1-3:sub/b.txt:needle in a folder\n\
+         2:a.txt:needle two\n\
+         3:a.txt:needle two\n"
+    );
+
+    let output = test_folder.ftm(&configs, &["grep", "nowhere"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
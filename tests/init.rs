@@ -0,0 +1,97 @@
+// Runs ftm init against config files that don't exist yet, or already have entries.
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use file_time_machine::Config;
+
+mod common;
+use common::TestFolder;
+
+fn ftm(conf_path: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ftm"))
+        .arg("--config")
+        .arg(conf_path)
+        .arg("--progress")
+        .arg("none")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn read_configs(conf_path: &Path) -> Vec<Config> {
+    serde_json::from_str(&fs::read_to_string(conf_path).unwrap()).unwrap()
+}
+
+#[test]
+fn writes_a_config_and_takes_the_first_snapshot() {
+    let test_folder = TestFolder::new("init-new");
+    test_folder.write("a.txt", "first version\n");
+    let conf_path = test_folder.root.join("conf/config.json");
+    let folder = test_folder.folder();
+
+    let output = ftm(
+        &conf_path,
+        &[
+            "init",
+            "--folder",
+            folder.to_str().unwrap(),
+            "--snapshot",
+            "--its-my-fault-if-i-lose-data", // Skips the warning that waits before every run
+        ],
+    );
+    assert!(output.status.success(), "{output:?}");
+    let configs = read_configs(&conf_path);
+    assert_eq!(configs.len(), 1);
+    assert_eq!(
+        Path::new(&configs[0].folder_path),
+        fs::canonicalize(&folder).unwrap()
+    );
+    assert_eq!(configs[0].brotli_compression_level, 5);
+
+    let output = ftm(&conf_path, &["list", "--json"]);
+    let listing: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(listing.as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn needs_force_to_replace_a_config() {
+    let test_folder = TestFolder::new("init-force");
+    let conf_path = test_folder.root.join("config.json");
+    let folder = test_folder.folder();
+    let folder = folder.to_str().unwrap();
+    fs::write(&conf_path, "[]").unwrap();
+
+    let output = ftm(&conf_path, &["init", "--folder", folder]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(fs::read_to_string(&conf_path).unwrap(), "[]");
+
+    let output = ftm(&conf_path, &["init", "--folder", folder, "--force"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(read_configs(&conf_path).len(), 1);
+}
+
+#[test]
+fn a_profile_is_added_next_to_the_other_entries() {
+    let test_folder = TestFolder::new("init-profile");
+    let conf_path = test_folder.root.join("config.json");
+    let folder = test_folder.folder();
+    let folder = folder.to_str().unwrap();
+
+    for name in ["work", "photos"] {
+        let output = ftm(&conf_path, &["--profile", name, "init", "--folder", folder]);
+        assert!(output.status.success(), "{output:?}");
+    }
+    let names: Vec<String> = read_configs(&conf_path)
+        .into_iter()
+        .map(|config| config.name)
+        .collect();
+    assert_eq!(names, vec!["work", "photos"]);
+
+    // Only an entry with the same name needs --force
+    let output = ftm(
+        &conf_path,
+        &["--profile", "work", "init", "--folder", folder],
+    );
+    assert_eq!(output.status.code(), Some(1));
+}
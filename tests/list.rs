@@ -0,0 +1,57 @@
+// Counts what every snapshot changed, through Repository::list and ftm list --json.
+use file_time_machine::progress::Silent;
+
+mod common;
+use common::TestFolder;
+
+fn take_snapshots(test_folder: &TestFolder) {
+    let repository = test_folder.repository();
+    test_folder.write("a.txt", "first version\n");
+    test_folder.write("sub/b.txt", "in a folder\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+    test_folder.write("a.txt", "first version\nsecond line\n");
+    test_folder.write("c.txt", "added in two\n");
+    test_folder.remove("sub/b.txt");
+    repository
+        .snapshot("second", &["release".to_string()], &Silent)
+        .unwrap();
+}
+
+#[test]
+fn counts_every_kind_of_change() {
+    let test_folder = TestFolder::new("list-counts");
+    take_snapshots(&test_folder);
+    let summaries = test_folder.repository().list().unwrap();
+
+    let counts: Vec<(usize, usize, usize, usize, usize)> = summaries
+        .iter()
+        .map(|summary| {
+            (
+                summary.index,
+                summary.added,
+                summary.modified,
+                summary.removed,
+                summary.unchanged,
+            )
+        })
+        .collect();
+    // a.txt, sub and sub/b.txt are new. Then sub stays a directory, so it is unchanged like in ftm diff
+    assert_eq!(counts, vec![(1, 3, 0, 0, 0), (2, 1, 1, 1, 1)]);
+    assert_eq!(summaries[1].message, "second");
+    assert_eq!(summaries[1].tags, vec!["release".to_string()]);
+}
+
+#[test]
+fn json_output_is_the_only_thing_on_stdout() {
+    let test_folder = TestFolder::new("list-json");
+    take_snapshots(&test_folder);
+
+    let output = test_folder.ftm(&[test_folder.config()], &["list", "--json"]);
+    assert!(output.status.success());
+    let listing: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let listing = listing.as_array().unwrap();
+    assert_eq!(listing.len(), 2);
+    assert_eq!(listing[0]["index"], 1);
+    assert_eq!(listing[1]["added"], 1);
+    assert_eq!(listing[1]["message"], "second");
+}
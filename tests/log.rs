@@ -0,0 +1,65 @@
+// Follows a single file through its snapshots with Repository::log and puts old versions back with restore_file.
+use std::fs;
+
+use file_time_machine::progress::Silent;
+use file_time_machine::snapshot_manager::{ChangeKind, VersionSize};
+
+mod common;
+use common::TestFolder;
+
+#[test]
+fn lists_only_the_snapshots_that_changed_the_file() {
+    let test_folder = TestFolder::new("log-history");
+    let repository = test_folder.repository();
+    let steps: [&dyn Fn(); 5] = [
+        &|| test_folder.write("a.txt", "first version\n"),
+        &|| test_folder.write("a.txt", "first version\nsecond line\n"),
+        &|| test_folder.write("b.txt", "a.txt is left alone\n"),
+        &|| test_folder.remove("a.txt"),
+        &|| test_folder.write("a.txt", "back again\n"),
+    ];
+    for step in steps.iter() {
+        step();
+        repository.snapshot("", &[], &Silent).unwrap();
+    }
+
+    let history: Vec<(usize, ChangeKind, Option<u64>)> = repository
+        .log("a.txt")
+        .unwrap()
+        .into_iter()
+        .map(|entry| {
+            let size = match entry.size {
+                VersionSize::Bytes(bytes) => Some(bytes),
+                _ => None,
+            };
+            (entry.snapshot_index, entry.change, size)
+        })
+        .collect();
+    assert_eq!(
+        history,
+        vec![
+            (0, ChangeKind::Added, Some(14)),
+            (1, ChangeKind::Modified, Some(26)),
+            (3, ChangeKind::Removed, None),
+            (4, ChangeKind::Added, Some(11)),
+        ]
+    );
+    assert!(repository.log("never-snapshotted.txt").unwrap().is_empty());
+}
+
+#[test]
+fn restore_file_puts_back_one_version() {
+    let test_folder = TestFolder::new("log-restore-file");
+    let repository = test_folder.repository();
+    test_folder.write("a.txt", "first version\n");
+    test_folder.write("b.txt", "other file\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+    test_folder.write("a.txt", "first version\nsecond line\n");
+    test_folder.write("b.txt", "other file, changed\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+
+    repository.restore_file(0, "a.txt").unwrap();
+    let read = |path| fs::read_to_string(test_folder.folder().join(path)).unwrap();
+    assert_eq!(read("a.txt"), "first version\n");
+    assert_eq!(read("b.txt"), "other file, changed\n");
+}
@@ -0,0 +1,63 @@
+// Runs ftm against a config file with several entries.
+use std::fs;
+
+use file_time_machine::{Config, Repository};
+
+mod common;
+use common::TestFolder;
+
+fn two_entries(test_folder: &TestFolder) -> Vec<Config> {
+    let other = test_folder.root.join("other");
+    fs::create_dir(&other).unwrap();
+    fs::write(other.join("b.txt"), "in the other folder\n").unwrap();
+    test_folder.write("a.txt", "in the folder\n");
+    vec![
+        Config {
+            name: "work".to_string(),
+            ..test_folder.config()
+        },
+        Config {
+            name: "photos".to_string(),
+            folder_path: other.to_str().unwrap().to_string(),
+            ..test_folder.config()
+        },
+    ]
+}
+
+fn snapshot_count(config: &Config) -> usize {
+    Repository::open(config.clone())
+        .unwrap()
+        .snapshots()
+        .unwrap()
+        .len()
+}
+
+#[test]
+fn snapshot_without_a_profile_takes_every_entry() {
+    let test_folder = TestFolder::new("profiles-every-entry");
+    let configs = two_entries(&test_folder);
+
+    let output = test_folder.ftm(&configs, &["snapshot"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(snapshot_count(&configs[0]), 1);
+    assert_eq!(snapshot_count(&configs[1]), 1);
+}
+
+#[test]
+fn a_profile_picks_one_entry() {
+    let test_folder = TestFolder::new("profiles-pick");
+    let configs = two_entries(&test_folder);
+
+    let output = test_folder.ftm(&configs, &["--profile", "photos", "snapshot"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(snapshot_count(&configs[1]), 1);
+    assert!(!test_folder.folder().join(".time").exists());
+
+    // Everything but snapshot works on a single folder, so it has to be told which one
+    let output = test_folder.ftm(&configs, &["list"]);
+    assert_eq!(output.status.code(), Some(1));
+    let output = test_folder.ftm(&configs, &["--profile", "photos", "list", "--json"]);
+    assert!(output.status.success(), "{output:?}");
+    let output = test_folder.ftm(&configs, &["--profile", "games", "list"]);
+    assert_eq!(output.status.code(), Some(1));
+}
@@ -0,0 +1,92 @@
+// Collects the progress events of snapshots and restores, through a closure and through --progress json.
+use std::cell::RefCell;
+
+use file_time_machine::progress::Progress;
+
+mod common;
+use common::TestFolder;
+
+#[test]
+fn snapshot_and_restore_report_every_step() {
+    let test_folder = TestFolder::new("progress-events");
+    let repository = test_folder.repository();
+    let events = RefCell::new(Vec::new());
+    let collect = |event: Progress| events.borrow_mut().push(event);
+
+    test_folder.write("a.txt", "first version\n");
+    test_folder.write("b.txt", "never changes\n");
+    repository.snapshot("", &[], &collect).unwrap();
+    // Messages are only for people, the rest says what is going on
+    let taken: Vec<Progress> = events
+        .take()
+        .into_iter()
+        .filter(|event| !matches!(event, Progress::Message { .. }))
+        .collect();
+    // The first snapshot indexes the folder, then writes a patch for every file
+    assert!(matches!(
+        taken.first(),
+        Some(Progress::Indexing { files: 2 })
+    ));
+    assert!(taken
+        .iter()
+        .any(|event| matches!(event, Progress::Writing { files: 2 })));
+    assert!(matches!(
+        taken.last(),
+        Some(Progress::Finished { snapshot: 1 })
+    ));
+    let written: Vec<&String> = taken
+        .iter()
+        .filter_map(|event| match event {
+            Progress::PatchWritten { path, .. } => Some(path),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(written.len(), 2);
+
+    repository.snapshot("", &[], &collect).unwrap();
+    assert!(matches!(
+        events.take().last(),
+        Some(Progress::NothingChanged)
+    ));
+
+    test_folder.write("a.txt", "first version\nsecond line\n");
+    repository.snapshot("", &[], &collect).unwrap();
+    events.take();
+    repository.restore(0, &[], None, &collect).unwrap();
+    let restored = events.take();
+    assert!(restored.iter().any(|event| matches!(
+        event,
+        Progress::RestoreStep { path } if path.ends_with("/a.txt")
+    )));
+    assert!(matches!(
+        restored.last(),
+        Some(Progress::Finished { snapshot: 1 })
+    ));
+
+    // A failure is reported as an event as well as returned
+    assert!(repository.restore(5, &[], None, &collect).is_err());
+    assert!(matches!(
+        events.take().last(),
+        Some(Progress::Failed { .. })
+    ));
+}
+
+#[test]
+fn json_progress_is_one_event_per_line() {
+    let test_folder = TestFolder::new("progress-json");
+    test_folder.write("a.txt", "first version\n");
+    test_folder.repository();
+
+    // TestFolder::ftm already passes --progress none, the last one given wins
+    let output = test_folder.ftm(&[test_folder.config()], &["--progress", "json", "snapshot"]);
+    assert!(output.status.success(), "{output:?}");
+    let events: Vec<serde_json::Value> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .filter(|event| event["event"] != "message")
+        .collect();
+    assert_eq!(events.first().unwrap()["event"], "indexing");
+    assert_eq!(events.last().unwrap()["event"], "finished");
+    assert_eq!(events.last().unwrap()["snapshot"], 1);
+}
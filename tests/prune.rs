@@ -0,0 +1,153 @@
+// Prunes real stores built with Repository::snapshot, then checks that what is left still restores.
+use chrono::{DateTime, Local, TimeZone};
+use std::fs;
+
+use file_time_machine::progress::Silent;
+use file_time_machine::{prune, snapshot_manager, Repository};
+
+mod common;
+use common::{read_tree, TestFolder, Tree};
+
+fn take_snapshots(test_folder: &TestFolder, repository: &Repository) -> Vec<Tree> {
+    /* Five snapshots. a.txt is created in the first one and changes in every later one, so its versions all hang
+    off the First patch of snapshot 1. Contents change length every time, so the change is seen within a second.
+    */
+    let steps: [&dyn Fn(); 5] = [
+        &|| {
+            test_folder.write("a.txt", "first version\n");
+            test_folder.write("b.txt", "never changes\n");
+            test_folder.write("sub/c.txt", "in a folder\n");
+        },
+        &|| test_folder.write("a.txt", "first version\nsecond line\n"),
+        &|| {
+            test_folder.write("a.txt", "first version\nsecond line\nthird\n");
+            test_folder.write("d.txt", "added in three\n");
+        },
+        &|| {
+            test_folder.write("a.txt", "rewritten in four\n");
+            test_folder.remove("d.txt");
+            test_folder.write("sub/c.txt", "in a folder, changed\n");
+        },
+        &|| test_folder.write("a.txt", "rewritten in four\nand five\n"),
+    ];
+    let mut trees = Vec::new();
+    for (index, step) in steps.iter().enumerate() {
+        step();
+        assert_eq!(
+            repository.snapshot("", &[], &Silent).unwrap(),
+            Some(index + 1)
+        );
+        trees.push(read_tree(&test_folder.folder()));
+    }
+    trees
+}
+
+fn check_survivors(test_folder: &TestFolder, repository: &Repository, expected: &[&Tree]) {
+    // Every snapshot left restores byte for byte, nothing is missing and nothing is left behind
+    assert_eq!(repository.snapshots().unwrap().len(), expected.len());
+    for (index, tree) in expected.iter().enumerate() {
+        let restore_to = test_folder.root.join(format!("restored-{index}"));
+        repository
+            .restore(index, &[], Some(restore_to.to_str().unwrap()), &Silent)
+            .unwrap();
+        assert_eq!(
+            read_tree(&restore_to),
+            **tree,
+            "snapshot {} did not restore",
+            index + 1
+        );
+        fs::remove_dir_all(restore_to).unwrap();
+    }
+
+    let problems = repository.verify(true, &Silent).unwrap();
+    let problems: Vec<String> = problems
+        .iter()
+        .map(|problem| format!("{}: {}", problem.path, problem.reason))
+        .collect();
+    assert!(problems.is_empty(), "{problems:?}");
+
+    let garbage = repository.find_garbage().unwrap();
+    assert!(garbage.dangling_files.is_empty());
+    assert!(garbage.unreferenced_patches.is_empty());
+}
+
+fn set_dates(repository: &Repository, dates: &[DateTime<Local>]) {
+    // Snapshots taken by a test are seconds apart, so spread them out like a real history
+    let mut snapshot_store = repository.snapshots().unwrap();
+    for (entry, date) in snapshot_store.iter_mut().zip(dates.iter()) {
+        entry.date_created = date.to_string();
+    }
+    snapshot_manager::write_snapshot_store(&snapshot_store, repository.storage()).unwrap();
+}
+
+fn local(year: i32, month: u32, day: u32, hour: u32) -> DateTime<Local> {
+    Local
+        .with_ymd_and_hms(year, month, day, hour, 0, 0)
+        .single()
+        .unwrap()
+}
+
+#[test]
+fn keep_last_rebases_onto_a_new_first_patch() {
+    let test_folder = TestFolder::new("prune-keep-last");
+    let repository = test_folder.repository();
+    let trees = take_snapshots(&test_folder, &repository);
+
+    let keep = repository.prune_selection(2, false).unwrap();
+    assert_eq!(keep, vec![false, false, false, true, true]);
+    let report = repository.prune(&keep, false).unwrap();
+    assert_eq!(report.removed_snapshots, 3);
+    assert!(report.rewritten_patches > 0); // The First patch of a.txt was in snapshot 1
+    check_survivors(&test_folder, &repository, &[&trees[3], &trees[4]]);
+
+    // The rewritten patches are a normal history, later snapshots build on them
+    test_folder.write("a.txt", "after the prune\n");
+    assert_eq!(repository.snapshot("", &[], &Silent).unwrap(), Some(3));
+    let after = read_tree(&test_folder.folder());
+    check_survivors(&test_folder, &repository, &[&trees[3], &trees[4], &after]);
+}
+
+#[test]
+fn time_machine_keeps_the_newest_snapshot_of_each_period() {
+    let test_folder = TestFolder::new("prune-time-machine");
+    let repository = test_folder.repository();
+    let trees = take_snapshots(&test_folder, &repository);
+    set_dates(
+        &repository,
+        &[
+            local(2025, 4, 1, 10), // Same week as the next one, which is newer
+            local(2025, 4, 1, 11),
+            local(2025, 6, 1, 9), // Same day as the next one, which is newer
+            local(2025, 6, 1, 18),
+            local(2025, 6, 15, 11),
+        ],
+    );
+
+    let snapshot_store = repository.snapshots().unwrap();
+    let keep = prune::select_snapshots(
+        repository.storage(),
+        &snapshot_store,
+        0,
+        true,
+        local(2025, 6, 15, 12),
+    )
+    .unwrap();
+    assert_eq!(keep, vec![false, true, false, true, true]);
+    let report = repository.prune(&keep, false).unwrap();
+    assert_eq!(report.removed_snapshots, 2);
+    assert!(report.rewritten_patches > 0); // Snapshot 1 had every First patch
+    check_survivors(&test_folder, &repository, &[&trees[1], &trees[3], &trees[4]]);
+}
+
+#[test]
+fn dry_run_changes_nothing() {
+    let test_folder = TestFolder::new("prune-dry-run");
+    let repository = test_folder.repository();
+    let trees = take_snapshots(&test_folder, &repository);
+
+    let keep = repository.prune_selection(1, false).unwrap();
+    let report = repository.prune(&keep, true).unwrap();
+    assert_eq!(report.removed_snapshots, 4);
+    let expected: Vec<&Tree> = trees.iter().collect();
+    check_survivors(&test_folder, &repository, &expected);
+}
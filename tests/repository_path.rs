@@ -0,0 +1,78 @@
+// Keeps the repository outside the folder, or somewhere inside it other than .time.
+use std::fs;
+
+use file_time_machine::progress::Silent;
+use file_time_machine::{Config, FtmError, Repository};
+
+mod common;
+use common::{read_tree, TestFolder};
+
+fn with_repository_path(test_folder: &TestFolder, repository_path: &str) -> Config {
+    Config {
+        repository_path: repository_path.to_string(),
+        ..test_folder.config()
+    }
+}
+
+#[test]
+fn an_external_repository_restores_the_folder() {
+    let test_folder = TestFolder::new("repository-path-external");
+    let repository_path = test_folder.root.join("repository");
+    let repository = Repository::init(with_repository_path(
+        &test_folder,
+        repository_path.to_str().unwrap(),
+    ))
+    .unwrap();
+
+    test_folder.write("a.txt", "first version\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+    let first = read_tree(&test_folder.folder());
+    test_folder.write("a.txt", "first version\nsecond line\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+
+    assert!(!test_folder.folder().join(".time").exists());
+    for name in ["metadata.json", "patches.json", "snapshots.json"] {
+        assert!(repository_path.join(name).is_file(), "{name} is missing");
+    }
+    repository.restore(0, &[], None, &Silent).unwrap();
+    assert_eq!(read_tree(&test_folder.folder()), first);
+    assert!(repository.verify(true, &Silent).unwrap().is_empty());
+}
+
+#[test]
+fn a_repository_inside_the_folder_is_not_snapshotted() {
+    let test_folder = TestFolder::new("repository-path-inside");
+    let repository_path = test_folder.folder().join("backups");
+    let repository = Repository::init(with_repository_path(
+        &test_folder,
+        repository_path.to_str().unwrap(),
+    ))
+    .unwrap();
+
+    test_folder.write("a.txt", "first version\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+    assert!(repository.status().unwrap().is_empty());
+    let snapshots = repository.snapshots().unwrap();
+    assert!(snapshots[0]
+        .target_path
+        .iter()
+        .all(|path| !path.contains("/backups")));
+}
+
+#[test]
+fn invalid_repository_paths_are_refused() {
+    let test_folder = TestFolder::new("repository-path-invalid");
+    let file = test_folder.root.join("file");
+    fs::write(&file, "").unwrap();
+    for repository_path in [
+        "relative/path".to_string(),
+        file.to_str().unwrap().to_string(),
+        test_folder.root.to_str().unwrap().to_string(), // Contains the folder
+    ] {
+        let config = with_repository_path(&test_folder, &repository_path);
+        assert!(
+            matches!(Repository::open(config), Err(FtmError::Config(_))),
+            "{repository_path} was accepted"
+        );
+    }
+}
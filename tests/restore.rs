@@ -0,0 +1,134 @@
+// Restores snapshots in place or into another folder, checking that nothing else is touched.
+use std::fs;
+use std::path::Path;
+
+use file_time_machine::progress::Silent;
+use file_time_machine::{FtmError, Repository};
+
+mod common;
+use common::{read_tree, TestFolder, Tree};
+
+fn take_snapshots(test_folder: &TestFolder, repository: &Repository) -> Vec<Tree> {
+    // Snapshot 1 has every file, snapshot 2 changes one in each folder and adds sub/new.txt
+    test_folder.write("a.txt", "first version\n");
+    test_folder.write("docs/b.md", "docs\n");
+    test_folder.write("sub/c.txt", "in a folder\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+    let first = read_tree(&test_folder.folder());
+    test_folder.write("a.txt", "first version\nsecond line\n");
+    test_folder.write("docs/b.md", "docs, rewritten\n");
+    test_folder.write("sub/new.txt", "added in two\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+    vec![first, read_tree(&test_folder.folder())]
+}
+
+fn contents(test_folder: &TestFolder, path: &str) -> Option<String> {
+    fs::read_to_string(test_folder.folder().join(path)).ok()
+}
+
+#[test]
+fn only_matching_paths_are_restored() {
+    let test_folder = TestFolder::new("restore-paths");
+    let repository = test_folder.repository();
+    take_snapshots(&test_folder, &repository);
+
+    repository
+        .restore(
+            0,
+            &["a.txt".to_string(), "docs/*.txt".to_string()],
+            None,
+            &Silent,
+        )
+        .unwrap();
+    assert_eq!(contents(&test_folder, "a.txt").unwrap(), "first version\n");
+    assert_eq!(
+        contents(&test_folder, "docs/b.md").unwrap(),
+        "docs, rewritten\n"
+    );
+    // Paths outside the patterns are never removed, even if they are not in the snapshot
+    assert!(contents(&test_folder, "sub/new.txt").is_some());
+}
+
+#[test]
+fn a_directory_pattern_restores_everything_inside_it() {
+    let test_folder = TestFolder::new("restore-directory");
+    let repository = test_folder.repository();
+    take_snapshots(&test_folder, &repository);
+
+    repository
+        .restore(0, &["./sub/".to_string()], None, &Silent)
+        .unwrap();
+    assert!(contents(&test_folder, "sub/new.txt").is_none()); // Not in snapshot 1, so it is removed
+    assert_eq!(
+        contents(&test_folder, "sub/c.txt").unwrap(),
+        "in a folder\n"
+    );
+    assert_eq!(
+        contents(&test_folder, "a.txt").unwrap(),
+        "first version\nsecond line\n"
+    );
+}
+
+#[test]
+fn invalid_globs_are_refused_before_anything_changes() {
+    let test_folder = TestFolder::new("restore-invalid-glob");
+    let repository = test_folder.repository();
+    let trees = take_snapshots(&test_folder, &repository);
+
+    let error = repository
+        .restore(0, &["a.txt".to_string(), "[".to_string()], None, &Silent)
+        .unwrap_err();
+    assert!(matches!(error, FtmError::Config(_)), "{error}");
+    assert_eq!(read_tree(&test_folder.folder()), trees[1]);
+}
+
+#[test]
+fn restore_to_extracts_without_touching_the_folder() {
+    let test_folder = TestFolder::new("restore-to");
+    let repository = test_folder.repository();
+    let trees = take_snapshots(&test_folder, &repository);
+    let restore_to = test_folder.root.join("extract");
+    let restore_to = restore_to.to_str().unwrap();
+
+    repository
+        .restore(0, &[], Some(restore_to), &Silent)
+        .unwrap();
+    assert_eq!(read_tree(Path::new(restore_to)), trees[0]);
+    assert_eq!(read_tree(&test_folder.folder()), trees[1]);
+    assert!(!Path::new(&(repository.time_dir() + "/activeSnapshot")).exists());
+
+    // Only the matching paths end up in the extracted folder
+    let partial = test_folder.root.join("partial");
+    repository
+        .restore(
+            1,
+            &["sub".to_string()],
+            Some(partial.to_str().unwrap()),
+            &Silent,
+        )
+        .unwrap();
+    let expected: Tree = trees[1]
+        .clone()
+        .into_iter()
+        .filter(|(path, _)| path.starts_with("sub"))
+        .collect();
+    assert_eq!(read_tree(&partial), expected);
+}
+
+#[test]
+fn restore_to_refuses_a_folder_that_is_not_empty() {
+    let test_folder = TestFolder::new("restore-to-not-empty");
+    let repository = test_folder.repository();
+    take_snapshots(&test_folder, &repository);
+    let restore_to = test_folder.root.join("extract");
+    fs::create_dir(&restore_to).unwrap();
+    fs::write(restore_to.join("keep.txt"), "mine\n").unwrap();
+
+    let error = repository
+        .restore(0, &[], Some(restore_to.to_str().unwrap()), &Silent)
+        .unwrap_err();
+    assert!(matches!(error, FtmError::Io { .. }), "{error}");
+    let mut expected = Tree::new();
+    expected.insert("keep.txt".to_string(), Some(b"mine\n".to_vec()));
+    assert_eq!(read_tree(&restore_to), expected);
+}
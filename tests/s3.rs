@@ -0,0 +1,75 @@
+// Runs against a real S3-compatible server, see "Testing the s3 backend" in the README. Skipped unless
+// FTM_TEST_S3_ENDPOINT is set.
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use file_time_machine::s3::S3Storage;
+use file_time_machine::storage::StorageBackend;
+use file_time_machine::S3Config;
+
+fn test_config() -> Option<S3Config> {
+    let endpoint = env::var("FTM_TEST_S3_ENDPOINT").ok()?;
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    Some(S3Config {
+        endpoint,
+        bucket: env::var("FTM_TEST_S3_BUCKET").unwrap_or("ftm-test".to_string()),
+        region: env::var("FTM_TEST_S3_REGION").unwrap_or("us-east-1".to_string()),
+        prefix: format!("ftm-test-{nanos}"), // Every run gets its own prefix, so runs don't see each other
+    })
+}
+
+#[test]
+fn put_get_list_exists_delete() {
+    let Some(config) = test_config() else {
+        eprintln!("FTM_TEST_S3_ENDPOINT is not set, skipping");
+        return;
+    };
+    let storage = S3Storage::new(&config).unwrap();
+    let name = "0B0F9FED27D10947FF7FB07D082F4DFA314EB732AE5AC17FC8C16CB6FEC89D32";
+    let contents: Vec<u8> = (0..=255).collect();
+
+    assert!(!storage.exists(name).unwrap());
+    assert!(storage.get(name).unwrap_err().is_not_found());
+    assert!(storage.list().unwrap().is_empty());
+
+    storage.put(name, &contents).unwrap();
+    storage.replace("snapshots.json", b"[]").unwrap();
+    assert!(storage.exists(name).unwrap());
+    assert_eq!(storage.get(name).unwrap(), contents);
+    assert_eq!(storage.get("snapshots.json").unwrap(), b"[]");
+
+    let mut listed: Vec<(String, u64)> = storage
+        .list()
+        .unwrap()
+        .into_iter()
+        .map(|object| (object.name, object.size))
+        .collect();
+    listed.sort();
+    assert_eq!(
+        listed,
+        vec![
+            (name.to_string(), 256),
+            ("snapshots.json".to_string(), 2)
+        ]
+    );
+
+    storage.delete(name).unwrap();
+    storage.delete("snapshots.json").unwrap();
+    storage.delete(name).unwrap(); // Deleting something that is already gone is fine
+    assert!(!storage.exists(name).unwrap());
+    assert!(storage.list().unwrap().is_empty());
+}
+
+#[test]
+fn missing_bucket_is_an_error() {
+    let Some(mut config) = test_config() else {
+        eprintln!("FTM_TEST_S3_ENDPOINT is not set, skipping");
+        return;
+    };
+    config.bucket += "-does-not-exist";
+    let error = S3Storage::new(&config).err().unwrap();
+    assert!(error.to_string().contains("NoSuchBucket"), "{error}");
+}
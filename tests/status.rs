@@ -0,0 +1,46 @@
+// Runs ftm status, whose exit code tells scripts whether there is anything to snapshot.
+use std::fs;
+
+use file_time_machine::progress::Silent;
+
+mod common;
+use common::TestFolder;
+
+#[test]
+fn exit_code_says_whether_anything_changed() {
+    let test_folder = TestFolder::new("status-exit-code");
+    let repository = test_folder.repository();
+    test_folder.write("a.txt", "first version\n");
+    test_folder.write("b.txt", "will be removed\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+    let configs = [test_folder.config()];
+
+    let output = test_folder.ftm(&configs, &["status"]);
+    assert_eq!(output.status.code(), Some(0));
+
+    test_folder.write("a.txt", "first version\nsecond line\n");
+    test_folder.write("c.txt", "new\n");
+    test_folder.remove("b.txt");
+    let output = test_folder.ftm(&configs, &["status"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    for line in ["modified  a.txt", "deleted   b.txt", "new       c.txt"] {
+        assert!(stdout.contains(line), "{line} is missing from {stdout}");
+    }
+
+    // status never writes anything, so the changes are still pending
+    let output = test_folder.ftm(&configs, &["status"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn exit_code_is_2_if_the_folder_could_not_be_checked() {
+    let test_folder = TestFolder::new("status-corrupt");
+    let repository = test_folder.repository();
+    test_folder.write("a.txt", "first version\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+    fs::write(repository.time_dir() + "/metadata.json", "not json").unwrap();
+
+    let output = test_folder.ftm(&[test_folder.config()], &["status"]);
+    assert_eq!(output.status.code(), Some(2));
+}
@@ -0,0 +1,79 @@
+// Gives snapshots messages and tags, then finds them again by tag.
+use file_time_machine::progress::Silent;
+use file_time_machine::{snapshot_manager, FtmError};
+
+mod common;
+use common::TestFolder;
+
+#[test]
+fn the_most_recent_snapshot_with_a_tag_is_picked() {
+    let test_folder = TestFolder::new("tags-pick");
+    let repository = test_folder.repository();
+    let versions = ["one\n", "one\ntwo\n", "one\ntwo\nthree\n"];
+    let tags = [vec!["release"], vec![], vec!["release", "friday"]];
+    for (contents, tags) in versions.iter().zip(tags) {
+        test_folder.write("a.txt", contents);
+        let tags: Vec<String> = tags.iter().map(|tag| tag.to_string()).collect();
+        repository.snapshot("a message", &tags, &Silent).unwrap();
+    }
+
+    assert_eq!(repository.tagged_snapshot("release").unwrap(), 2);
+    assert_eq!(repository.tagged_snapshot("friday").unwrap(), 2);
+    assert!(matches!(
+        repository.tagged_snapshot("missing"),
+        Err(FtmError::UnknownTag(tag)) if tag == "missing"
+    ));
+
+    let snapshots = repository.snapshots().unwrap();
+    assert_eq!(snapshots[0].message, "a message");
+    assert_eq!(snapshots[0].tags, vec!["release".to_string()]);
+    assert!(snapshots[1].tags.is_empty());
+}
+
+#[test]
+fn restore_by_tag_from_the_command_line() {
+    let test_folder = TestFolder::new("tags-restore");
+    let repository = test_folder.repository();
+    test_folder.write("a.txt", "tagged\n");
+    repository
+        .snapshot("", &["good".to_string()], &Silent)
+        .unwrap();
+    test_folder.write("a.txt", "tagged, then broken\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+
+    let output = test_folder.ftm(&[test_folder.config()], &["restore", "--tag", "good"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        std::fs::read_to_string(test_folder.folder().join("a.txt")).unwrap(),
+        "tagged\n"
+    );
+
+    let output = test_folder.ftm(&[test_folder.config()], &["restore", "--tag", "missing"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn snapshots_from_before_tags_still_load() {
+    let test_folder = TestFolder::new("tags-old-store");
+    let repository = test_folder.repository();
+    test_folder.write("a.txt", "first version\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+
+    // Drop the fields older versions never wrote
+    let mut store: serde_json::Value =
+        serde_json::from_slice(&repository.storage().get("snapshots.json").unwrap()).unwrap();
+    for entry in store.as_array_mut().unwrap() {
+        let entry = entry.as_object_mut().unwrap();
+        entry.remove("message");
+        entry.remove("tags");
+    }
+    repository
+        .storage()
+        .replace("snapshots.json", store.to_string().as_bytes())
+        .unwrap();
+
+    let snapshots = snapshot_manager::read_snapshot_store(repository.storage()).unwrap();
+    assert_eq!(snapshots.len(), 1);
+    assert!(snapshots[0].message.is_empty());
+    assert!(snapshots[0].tags.is_empty());
+}
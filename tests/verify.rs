@@ -0,0 +1,72 @@
+// Damages patch files behind the repository's back and checks that verify notices.
+use file_time_machine::progress::Silent;
+use file_time_machine::{restore, snapshot_manager, Repository};
+
+mod common;
+use common::TestFolder;
+
+fn take_snapshots(test_folder: &TestFolder, repository: &Repository) -> String {
+    // Returns the ID of the patch a.txt was created with, every later version of it depends on that one
+    test_folder.write("a.txt", &"first version\n".repeat(100));
+    test_folder.write("b.txt", "never changes\n");
+    repository.snapshot("", &[], &Silent).unwrap();
+    test_folder.write("a.txt", &"second version\n".repeat(100));
+    repository.snapshot("", &[], &Silent).unwrap();
+
+    let patch_store = snapshot_manager::read_patch_store(repository.storage()).unwrap();
+    let first = patch_store
+        .iter()
+        .find(|patch| patch.target_path.ends_with("/a.txt") && patch.ref_patch == "First patch")
+        .unwrap();
+    restore::patch_id_of(first)
+}
+
+#[test]
+fn a_healthy_repository_has_no_problems() {
+    let test_folder = TestFolder::new("verify-healthy");
+    let repository = test_folder.repository();
+    take_snapshots(&test_folder, &repository);
+    assert!(repository.verify(true, &Silent).unwrap().is_empty());
+}
+
+#[test]
+fn a_truncated_patch_is_found() {
+    let test_folder = TestFolder::new("verify-truncated");
+    let repository = test_folder.repository();
+    let patch_id = take_snapshots(&test_folder, &repository);
+    let contents = repository.storage().get(&patch_id).unwrap();
+    repository
+        .storage()
+        .replace(&patch_id, &contents[..contents.len() / 2])
+        .unwrap();
+
+    let problems = repository.verify(false, &Silent).unwrap();
+    assert_eq!(problems.len(), 1, "{problems:?}");
+    assert_eq!(problems[0].snapshot, None);
+    assert!(problems[0].path.ends_with("/a.txt"));
+    assert!(problems[0].reason.contains("does not decompress"));
+
+    // Rebuilding every file also finds each snapshot that can't be restored because of it
+    let problems = repository.verify(true, &Silent).unwrap();
+    let mut snapshots: Vec<usize> = problems
+        .iter()
+        .filter_map(|problem| problem.snapshot)
+        .collect();
+    snapshots.dedup();
+    assert_eq!(snapshots, vec![0, 1]);
+
+    let output = test_folder.ftm(&[test_folder.config()], &["verify"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn a_missing_patch_is_found() {
+    let test_folder = TestFolder::new("verify-missing");
+    let repository = test_folder.repository();
+    let patch_id = take_snapshots(&test_folder, &repository);
+    repository.storage().delete(&patch_id).unwrap();
+
+    let problems = repository.verify(false, &Silent).unwrap();
+    assert_eq!(problems.len(), 1, "{problems:?}");
+    assert!(problems[0].reason.contains("is missing"));
+}